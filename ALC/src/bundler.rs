@@ -80,6 +80,8 @@ enum Value {
     String(String),
     Integer(i64),
     Boolean(bool),
+    Range(i64, i64, i64, bool),
+    List(Vec<Value>),
 }
 
 impl std::fmt::Display for Value {
@@ -89,6 +91,49 @@ impl std::fmt::Display for Value {
             Value::String(s) => write!(f, "{}", s),
             Value::Integer(n) => write!(f, "{}", n),
             Value::Boolean(b) => write!(f, "{}", b),
+            Value::Range(start, end, step, inclusive) => {
+                write!(f, "{}{}{}", start, if *inclusive { "..=" } else { ".." }, end)?;
+                if *step != 1 {
+                    write!(f, " step {}", step)?;
+                }
+                Ok(())
+            }
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+enum Iteration {
+    Range(Option<i64>, i64, i64, bool),
+    Chars(Vec<char>, usize),
+    Items(Vec<Value>, usize),
+}
+
+impl Iteration {
+    fn next_item(&mut self) -> Option<Value> {
+        match self {
+            Iteration::Range(next, end, step, inclusive) => {
+                let current = (*next)?;
+                if (*inclusive && current > *end) || (!*inclusive && current >= *end) {
+                    *next = None;
+                    return None;
+                }
+                *next = current.checked_add(*step);
+                Some(Value::Integer(current))
+            }
+            Iteration::Chars(chars, index) => {
+                let ch = chars.get(*index)?;
+                *index += 1;
+                Some(Value::String(ch.to_string()))
+            }
+            Iteration::Items(items, index) => {
+                let item = items.get(*index)?.clone();
+                *index += 1;
+                Some(item)
+            }
         }
     }
 }
@@ -132,6 +177,12 @@ fn deserialize(bytes: &[u8]) -> BytecodeModule {
                 pos += 1;
                 constants.push(Value::Boolean(b));
             }
+            4 => {
+                let int_at = |p: usize| i64::from_be_bytes([bytes[p], bytes[p+1], bytes[p+2], bytes[p+3],
+                                                           bytes[p+4], bytes[p+5], bytes[p+6], bytes[p+7]]);
+                constants.push(Value::Range(int_at(pos), int_at(pos+8), int_at(pos+16), bytes[pos+24] != 0));
+                pos += 25;
+            }
             _ => panic!("Unknown type tag"),
         }
     }
@@ -147,6 +198,10 @@ fn deserialize(bytes: &[u8]) -> BytecodeModule {
 
 fn execute(module: &BytecodeModule) {
     let mut stack: Vec<Value> = Vec::new();
+    let mut locals: Vec<Value> = Vec::new();
+    let mut iterations: Vec<Iteration> = Vec::new();
+    // (return ip, locals base, stack base, iterations base) per call
+    let mut frames: Vec<(usize, usize, usize, usize)> = Vec::new();
     let mut ip = 0;
     
     while ip < module.instructions.len() {
@@ -175,11 +230,301 @@ fn execute(module: &BytecodeModule) {
                 stack.pop();
                 ip += 1;
             }
+            0x06 => {        // Assert
+                let location = constant_at(module, ip);
+                let message = stack.pop().unwrap_or(Value::Null);
+                match stack.pop() {
+                    Some(Value::Boolean(true)) => {}
+                    Some(Value::Boolean(false)) => match message {
+                        Value::Null => fail(&format!("Assertion failed at {}", location)),
+                        message => fail(&format!("Assertion failed at {}: {}", location, message)),
+                    },
+                    other => fail(&format!(
+                        "Assertion condition at {} must be a boolean, found {}",
+                        location,
+                        other.unwrap_or(Value::Null)
+                    )),
+                }
+                ip += 3;
+            }
+            0x07 => {        // AssertEq
+                let location = constant_at(module, ip);
+                let right = stack.pop().unwrap_or(Value::Null);
+                let left = stack.pop().unwrap_or(Value::Null);
+                if left != right {
+                    fail(&format!(
+                        "Assertion failed at {}: left: {}, right: {}",
+                        location, left, right
+                    ));
+                }
+                ip += 3;
+            }
+            0x08 => {        // Jump
+                ip = jump_target(module, ip);
+            }
+            0x09 => {        // JumpIfFalse
+                match stack.pop() {
+                    Some(Value::Boolean(true)) => ip += 5,
+                    Some(Value::Boolean(false)) => ip = jump_target(module, ip),
+                    other => fail(&format!(
+                        "Condition must be a boolean, found {}",
+                        other.unwrap_or(Value::Null)
+                    )),
+                }
+            }
+            0x0A => {        // Dup
+                let top = stack.last().cloned().unwrap_or(Value::Null);
+                stack.push(top);
+                ip += 1;
+            }
+            0x0B => {        // Equal
+                let right = stack.pop();
+                let left = stack.pop();
+                stack.push(Value::Boolean(left == right));
+                ip += 1;
+            }
+            0x0C => {        // MakeRange
+                let inclusive = module.instructions[ip+1] != 0;
+                let step = pop_integer(&mut stack, "Range step");
+                let end = pop_integer(&mut stack, "Range end");
+                let start = pop_integer(&mut stack, "Range start");
+                if step <= 0 {
+                    fail(&format!("Range step must be positive, found {}", step));
+                }
+                stack.push(Value::Range(start, end, step, inclusive));
+                ip += 2;
+            }
+            0x0D => {        // Slice
+                let range = stack.pop().unwrap_or(Value::Null);
+                let target = stack.pop().unwrap_or(Value::Null);
+                stack.push(slice(&target, &range));
+                ip += 1;
+            }
+            0x0E => {        // LoadLocal
+                let slot = locals_base(&frames) + (((module.instructions[ip+1] as usize) << 8) | (module.instructions[ip+2] as usize));
+                stack.push(locals[slot].clone());
+                ip += 3;
+            }
+            0x0F => {        // StoreLocal
+                let slot = locals_base(&frames) + (((module.instructions[ip+1] as usize) << 8) | (module.instructions[ip+2] as usize));
+                if slot >= locals.len() {
+                    locals.resize(slot + 1, Value::Null);
+                }
+                locals[slot] = stack.pop().unwrap_or(Value::Null);
+                ip += 3;
+            }
+            0x10 => {        // IterStart
+                match stack.pop() {
+                    Some(Value::Range(start, end, step, inclusive)) => {
+                        iterations.push(Iteration::Range(Some(start), end, step, inclusive))
+                    }
+                    Some(Value::String(s)) => iterations.push(Iteration::Chars(s.chars().collect(), 0)),
+                    Some(Value::List(items)) => iterations.push(Iteration::Items(items, 0)),
+                    other => fail(&format!("Cannot iterate over {}", other.unwrap_or(Value::Null))),
+                }
+                ip += 1;
+            }
+            0x11 => {        // IterNext
+                match iterations.last_mut().and_then(|it| it.next_item()) {
+                    Some(item) => {
+                        stack.push(item);
+                        ip += 5;
+                    }
+                    None => {
+                        iterations.pop();
+                        ip = jump_target(module, ip);
+                    }
+                }
+            }
+            0x12 => {        // IsNull
+                let value = stack.pop().unwrap_or(Value::Null);
+                stack.push(Value::Boolean(value == Value::Null));
+                ip += 1;
+            }
+            0x13 => {        // Call
+                let argc = ((module.instructions[ip+5] as usize) << 8) | (module.instructions[ip+6] as usize);
+                if frames.len() >= 10_000 {
+                    fail("Stack overflow: more than 10000 nested calls");
+                }
+                let arguments = stack.split_off(stack.len() - argc);
+                frames.push((ip + 7, locals.len(), stack.len(), iterations.len()));
+                locals.extend(arguments);
+                ip = jump_target(module, ip);
+            }
+            0x14 => {        // Return
+                let value = stack.pop().unwrap_or(Value::Null);
+                let (return_ip, locals_base, stack_base, iterations_base) = frames.pop().unwrap();
+                stack.truncate(stack_base);
+                locals.truncate(locals_base);
+                iterations.truncate(iterations_base);
+                stack.push(value);
+                ip = return_ip;
+            }
+            0x15 => {        // MakeList
+                let count = ((module.instructions[ip+1] as usize) << 8) | (module.instructions[ip+2] as usize);
+                let items = stack.split_off(stack.len() - count);
+                stack.push(Value::List(items));
+                ip += 3;
+            }
+            0x16..=0x1A | 0x1C..=0x1F => { // Add, Subtract, Multiply, Divide, Remainder, Less, LessEqual, Greater, GreaterEqual
+                let right = stack.pop().unwrap_or(Value::Null);
+                let left = stack.pop().unwrap_or(Value::Null);
+                stack.push(binary(module.instructions[ip], left, right));
+                ip += 1;
+            }
+            0x1B => {        // NotEqual
+                let right = stack.pop().unwrap_or(Value::Null);
+                let left = stack.pop().unwrap_or(Value::Null);
+                stack.push(Value::Boolean(left != right));
+                ip += 1;
+            }
+            0x20 => {        // Negate
+                match stack.pop().unwrap_or(Value::Null) {
+                    Value::Integer(n) => match n.checked_neg() {
+                        Some(n) => stack.push(Value::Integer(n)),
+                        None => fail(&format!("Integer overflow in `-({})`", n)),
+                    },
+                    other => fail(&format!("Cannot apply unary `-` to {}", type_name(&other))),
+                }
+                ip += 1;
+            }
+            0x21 => {        // Concat
+                let count = ((module.instructions[ip+1] as usize) << 8) | (module.instructions[ip+2] as usize);
+                let parts = stack.split_off(stack.len() - count);
+                stack.push(Value::String(parts.iter().map(|part| part.to_string()).collect()));
+                ip += 3;
+            }
+            0x22 => {        // PrintConst
+                println!("{}", constant_at(module, ip));
+                ip += 3;
+            }
+            0x23 => {        // AddLocalConst
+                let slot = ((module.instructions[ip+1] as usize) << 8) | (module.instructions[ip+2] as usize);
+                let left = locals[locals_base(&frames) + slot].clone();
+                stack.push(binary(0x16, left, constant_at(module, ip + 2)));
+                ip += 5;
+            }
+            0x24 => {        // PushConstWide
+                let i = &module.instructions;
+                let idx = u32::from_be_bytes([i[ip+1], i[ip+2], i[ip+3], i[ip+4]]);
+                stack.push(module.constants[idx as usize].clone());
+                ip += 5;
+            }
+            0x25 => {        // TailCall
+                let argc = ((module.instructions[ip+5] as usize) << 8) | (module.instructions[ip+6] as usize);
+                let arguments = stack.split_off(stack.len() - argc);
+                let &(_, locals_base, stack_base, iterations_base) = frames.last().unwrap();
+                stack.truncate(stack_base);
+                locals.truncate(locals_base);
+                iterations.truncate(iterations_base);
+                locals.extend(arguments);
+                ip = jump_target(module, ip);
+            }
             _ => panic!("Unknown opcode"),
         }
     }
 }
 
+fn locals_base(frames: &[(usize, usize, usize, usize)]) -> usize {
+    frames.last().map_or(0, |frame| frame.1)
+}
+
+fn constant_at(module: &BytecodeModule, ip: usize) -> Value {
+    let idx = ((module.instructions[ip+1] as u16) << 8) | (module.instructions[ip+2] as u16);
+    module.constants[idx as usize].clone()
+}
+
+fn jump_target(module: &BytecodeModule, ip: usize) -> usize {
+    let i = &module.instructions;
+    u32::from_be_bytes([i[ip+1], i[ip+2], i[ip+3], i[ip+4]]) as usize
+}
+
+fn pop_integer(stack: &mut Vec<Value>, what: &str) -> i64 {
+    match stack.pop() {
+        Some(Value::Integer(n)) => n,
+        other => fail(&format!("{} must be an integer, found {}", what, other.unwrap_or(Value::Null))),
+    }
+}
+
+fn binary(opcode: u8, left: Value, right: Value) -> Value {
+    let symbol = ["+", "-", "*", "/", "%", "!=", "<", "<=", ">", ">="][(opcode - 0x16) as usize];
+    match (left, right) {
+        (Value::String(a), Value::String(b)) if opcode == 0x16 || opcode >= 0x1C => {
+            match opcode {
+                0x16 => Value::String(a + &b),
+                0x1C => Value::Boolean(a < b),
+                0x1D => Value::Boolean(a <= b),
+                0x1E => Value::Boolean(a > b),
+                _ => Value::Boolean(a >= b),
+            }
+        }
+        (Value::Integer(a), Value::Integer(b)) => {
+            let checked = match opcode {
+                0x16 => a.checked_add(b),
+                0x17 => a.checked_sub(b),
+                0x18 => a.checked_mul(b),
+                0x19 | 0x1A if b == 0 => {
+                    let what = if opcode == 0x19 { "Division" } else { "Remainder" };
+                    fail(&format!("{} by zero in `{} {} {}`", what, a, symbol, b))
+                }
+                0x19 => a.checked_div(b),
+                0x1A => a.checked_rem(b),
+                0x1C => return Value::Boolean(a < b),
+                0x1D => return Value::Boolean(a <= b),
+                0x1E => return Value::Boolean(a > b),
+                _ => return Value::Boolean(a >= b),
+            };
+            match checked {
+                Some(n) => Value::Integer(n),
+                None => fail(&format!("Integer overflow in `{} {} {}`", a, symbol, b)),
+            }
+        }
+        (left, right) => fail(&format!(
+            "Cannot apply `{}` to {} and {}",
+            symbol,
+            type_name(&left),
+            type_name(&right)
+        )),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::String(_) => "a string",
+        Value::Integer(_) => "an integer",
+        Value::Boolean(_) => "a boolean",
+        Value::Range(..) => "a range",
+        Value::List(_) => "a list",
+    }
+}
+
+fn slice(target: &Value, range: &Value) -> Value {
+    let (start, end, step, inclusive) = match range {
+        Value::Range(start, end, step, inclusive) => (*start, *end, *step, *inclusive),
+        other => fail(&format!("Cannot slice with {}", other)),
+    };
+    let (kind, len) = match target {
+        Value::String(s) => ("string", s.chars().count()),
+        Value::List(items) => ("list", items.len()),
+        other => fail(&format!("Cannot slice {}", other)),
+    };
+    let end = if inclusive { end.saturating_add(1) } else { end };
+    if start < 0 || end < start || end as usize > len {
+        fail(&format!("Slice {} out of bounds for {} of length {}", range, kind, len));
+    }
+    let (start, end, step) = (start as usize, end as usize, step as usize);
+    match target {
+        Value::List(items) => Value::List(items[start..end].iter().step_by(step).cloned().collect()),
+        _ => Value::String(target.to_string().chars().skip(start).take(end - start).step_by(step).collect()),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
 fn main() {
     let module = deserialize(BYTECODE);
     execute(&module);
@@ -1,12 +1,55 @@
 // artificial-language/ALC/src/cli.rs
+use artificial_core::lints::{self, Level};
+use artificial_core::OptLevel;
+
+/// How diagnostics are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Source snippets with underlines, for people
+    Human,
+    /// One JSON object per line, for editors and CI
+    Json,
+}
+
+/// What runs the compiled program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// `VirtualMachine`, which runs the stack bytecode
+    Stack,
+    /// `RegisterMachine`, which runs register code built from the same IR
+    Register,
+}
 
 pub struct CliOptions {
     pub source_file: String,
     pub emit_bytecode: bool,
     pub no_run: bool,
     pub bundle: bool,
+    pub no_asserts: bool,
+    /// Compile calls in tail position as ordinary calls
+    pub no_tco: bool,
     pub out_dir: String,
     pub debug: bool,
+    /// Print the IR to stdout after lowering
+    pub dump_ir: bool,
+    /// Print what each peephole rule saved
+    pub peephole_stats: bool,
+    pub opt_level: OptLevel,
+    /// Largest function inlined at its call sites, if not the level's
+    /// default; 0 disables inlining
+    pub inline_threshold: Option<usize>,
+    /// `--enable-pass` (true) and `--disable-pass` (false) in the order
+    /// given
+    pub pass_flags: Vec<(String, bool)>,
+    /// Passes to print the module after
+    pub print_after: Vec<String>,
+    pub error_format: ErrorFormat,
+    pub engine: Engine,
+    /// Set by `ALC explain <code>`; nothing is compiled
+    pub explain: Option<String>,
+    /// `-W`, `-A` and `-D` flags in the order given
+    pub lint_levels: Vec<(String, Level)>,
+    pub deny_warnings: bool,
 }
 
 impl Default for CliOptions {
@@ -16,8 +59,21 @@ impl Default for CliOptions {
             emit_bytecode: false,
             no_run: false,
             bundle: false,
+            no_asserts: false,
+            no_tco: false,
             out_dir: "target/artificial_out".to_string(),
             debug: false,
+            dump_ir: false,
+            peephole_stats: false,
+            opt_level: OptLevel::default(),
+            inline_threshold: None,
+            pass_flags: Vec::new(),
+            print_after: Vec::new(),
+            error_format: ErrorFormat::Human,
+            engine: Engine::Stack,
+            explain: None,
+            lint_levels: Vec::new(),
+            deny_warnings: false,
         }
     }
 }
@@ -27,6 +83,16 @@ pub fn parse_cli_args() -> Result<CliOptions, String> {
     let mut opts = CliOptions::default();
     let mut i = 1;
 
+    if args.get(1).map(String::as_str) == Some("explain") {
+        return match args.get(2..) {
+            Some([code]) => Ok(CliOptions {
+                explain: Some(code.clone()),
+                ..opts
+            }),
+            _ => Err("explain requires exactly one error code, such as E0301".to_string()),
+        };
+    }
+
     while i < args.len() {
         match args[i].as_str() {
             "--emit-bytecode" => {
@@ -41,10 +107,103 @@ pub fn parse_cli_args() -> Result<CliOptions, String> {
                 opts.bundle = true;
                 i += 1;
             }
+            "--no-asserts" => {
+                opts.no_asserts = true;
+                i += 1;
+            }
+            "--no-tco" => {
+                opts.no_tco = true;
+                i += 1;
+            }
             "--debug" => {
                 opts.debug = true;
                 i += 1;
             }
+            "--dump-ir" => {
+                opts.dump_ir = true;
+                i += 1;
+            }
+            "--peephole-stats" => {
+                opts.peephole_stats = true;
+                i += 1;
+            }
+            "--deny-warnings" => {
+                opts.deny_warnings = true;
+                i += 1;
+            }
+            // `-W lint` or `-Wlint`
+            arg if matches!(arg.get(..2), Some("-W" | "-A" | "-D")) => {
+                let level = match &arg[..2] {
+                    "-W" => Level::Warn,
+                    "-A" => Level::Allow,
+                    _ => Level::Deny,
+                };
+                let name = if arg.len() > 2 {
+                    arg[2..].to_string()
+                } else {
+                    i += 1;
+                    args.get(i)
+                        .ok_or_else(|| format!("{} requires a lint name", arg))?
+                        .clone()
+                };
+                if !lints::is_known(&name) {
+                    return Err(format!("Unknown lint: {}", name));
+                }
+                opts.lint_levels.push((name, level));
+                i += 1;
+            }
+            arg if arg.starts_with("--inline-threshold=") => {
+                let value = &arg["--inline-threshold=".len()..];
+                let threshold = value
+                    .parse()
+                    .map_err(|_| format!("Invalid inline threshold: {}", value))?;
+                opts.inline_threshold = Some(threshold);
+                i += 1;
+            }
+            arg if arg.starts_with("-O") => {
+                opts.opt_level = OptLevel::parse(&arg[2..]).ok_or_else(|| {
+                    format!("Unknown optimization level: {} (expected -O0, -O1, -O2 or -Os)", arg)
+                })?;
+                i += 1;
+            }
+            arg if arg.starts_with("--enable-pass=") => {
+                opts.pass_flags.push((arg["--enable-pass=".len()..].to_string(), true));
+                i += 1;
+            }
+            arg if arg.starts_with("--disable-pass=") => {
+                opts.pass_flags.push((arg["--disable-pass=".len()..].to_string(), false));
+                i += 1;
+            }
+            arg if arg.starts_with("--print-after=") => {
+                opts.print_after.push(arg["--print-after=".len()..].to_string());
+                i += 1;
+            }
+            arg if arg.starts_with("--error-format=") => {
+                opts.error_format = match &arg["--error-format=".len()..] {
+                    "human" => ErrorFormat::Human,
+                    "json" => ErrorFormat::Json,
+                    other => {
+                        return Err(format!(
+                            "Unknown error format: {} (expected human or json)",
+                            other
+                        ))
+                    }
+                };
+                i += 1;
+            }
+            arg if arg.starts_with("--engine=") => {
+                opts.engine = match &arg["--engine=".len()..] {
+                    "stack" => Engine::Stack,
+                    "register" => Engine::Register,
+                    other => {
+                        return Err(format!(
+                            "Unknown engine: {} (expected stack or register)",
+                            other
+                        ))
+                    }
+                };
+                i += 1;
+            }
             "--out-dir" => {
                 i += 1;
                 if i >= args.len() {
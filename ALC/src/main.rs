@@ -3,8 +3,9 @@ mod cli;
 mod runner;
 mod bundler;
 
+use artificial_core::{codes, lints, DEFAULT_INLINE_THRESHOLD, SIZE_INLINE_THRESHOLD};
 use cli::parse_cli_args;
-use runner::run_compiler;
+use runner::{run_compiler, RunError};
 
 fn main() {
     let opts = match parse_cli_args() {
@@ -12,19 +13,60 @@ fn main() {
         Err(e) => {
             eprintln!("Error: {}", e);
             eprintln!("Usage: ALC [OPTIONS] <source-file>");
+            eprintln!("       ALC explain <code>");
             eprintln!();
             eprintln!("Options:");
             eprintln!("  --emit-bytecode    Save bytecode to .artb file");
             eprintln!("  --no-run           Compile without executing");
             eprintln!("  --bundle           Create standalone executable");
+            eprintln!("  --no-asserts       Strip assert and assert_eq statements");
+            eprintln!("  --no-tco           Keep a frame for every call, even in tail position");
             eprintln!("  --out-dir <DIR>    Output directory (default: target/artificial_out)");
             eprintln!("  --debug            Enable debug output");
+            eprintln!("  --dump-ir          Print the optimized IR");
+            eprintln!("  -O0, -O1, -O2, -Os  Optimization level (default -O2; -Os favors small bytecode)");
+            eprintln!("  --enable-pass=<PASS>   Run a pass the optimization level leaves out");
+            eprintln!("  --disable-pass=<PASS>  Skip a pass");
+            eprintln!("  --print-after=<PASS>   Print the IR or bytecode after a pass");
+            eprintln!("  --inline-threshold=<N>  Inline functions of up to N instructions (default {}, {} with -Os; 0 disables)", DEFAULT_INLINE_THRESHOLD, SIZE_INLINE_THRESHOLD);
+            eprintln!("  --peephole-stats   Report what each bytecode peephole rule saved");
+            eprintln!("  --error-format=<human|json>  Print diagnostics as text or JSON lines");
+            eprintln!("  --engine=<stack|register>  Run the program on the stack or the register VM (default stack)");
+            eprintln!("  -W <LINT>          Warn about a lint");
+            eprintln!("  -A <LINT>          Allow a lint");
+            eprintln!("  -D <LINT>          Deny a lint, making it an error");
+            eprintln!("  --deny-warnings    Make every warning an error");
+            eprintln!();
+            eprintln!("Lints:");
+            for lint in lints::LINTS {
+                eprintln!("  {:<20} {}", lint.name, lint.description);
+            }
+            eprintln!("  {:<20} unused_variables, unused_functions and unused_macros", "unused");
+            eprintln!("  {:<20} every lint", "warnings");
             std::process::exit(1);
         }
     };
 
-    if let Err(e) = run_compiler(&opts) {
-        eprintln!("Compiler Error: {}", e);
-        std::process::exit(1);
+    if let Some(code) = &opts.explain {
+        match codes::ErrorCode::parse(code).and_then(codes::explain) {
+            Some(text) => println!("{}", text),
+            None => {
+                eprintln!("Error: Unknown error code `{}`", code);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match run_compiler(&opts) {
+        Ok(()) => {}
+        Err(RunError::Diagnostics(rendered)) => {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        Err(RunError::Other(e)) => {
+            eprintln!("Compiler Error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -1,46 +1,145 @@
 // artificial-language/ALC/src/runner.rs
 use crate::bundler;
-use crate::cli::CliOptions;
-use artificial_core::{lower_module, Lexer, Parser};
-use artificial_vm::{BytecodeCompiler, VirtualMachine, write_to_file};
+use crate::cli::{CliOptions, Engine, ErrorFormat};
+use artificial_core::lints::LintLevels;
+use artificial_core::{
+    ir_pass_manager, lower_module, validate, Diagnostic, DiagnosticRenderer, Expansions, IrModule, Lexer,
+    LoweringOptions, MacroExpander, Parser, PassError, PassManager, Resolver, Severity,
+};
+use artificial_vm::{
+    bytecode_pass_manager, BytecodeCompiler, BytecodeModule, CompilerOptions, RegisterCompiler, RegisterMachine,
+    VirtualMachine, write_to_file,
+};
 use std::fs;
+use std::io::IsTerminal;
 use std::path::Path;
 
-pub fn run_compiler(opts: &CliOptions) -> Result<(), String> {
+/// Why a run stopped early
+pub enum RunError {
+    /// Problems in the program, already rendered against its source
+    Diagnostics(String),
+    /// Anything else, such as a file that could not be read or written
+    Other(String),
+}
+
+impl From<String> for RunError {
+    fn from(message: String) -> Self {
+        RunError::Other(message)
+    }
+}
+
+pub fn run_compiler(opts: &CliOptions) -> Result<(), RunError> {
     // Read source file
     let source = fs::read_to_string(&opts.source_file)
         .map_err(|e| format!("Failed to read source file '{}': {}", &opts.source_file, e))?;
 
     // Lexical analysis
     let lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize().map_err(|e| e.to_string())?;
+    let (tokens, lex_errors) = lexer.tokenize();
+    // Lex and parse errors are collected so they can be reported together
+    let mut diagnostics: Vec<Diagnostic> = lex_errors.iter().map(|e| e.to_diagnostic()).collect();
 
     if opts.debug {
         println!("[DEBUG] Tokens: {:#?}", tokens);
     }
 
+    // Macro expansion
+    let mut expander = MacroExpander::new();
+    let expanded = expander.expand(tokens);
+    // Diagnostics in expanded code point back at the macro invocations
+    let expansions = expander.expansions();
+    let report = |diagnostics: &[Diagnostic]| {
+        RunError::Diagnostics(render_diagnostics(opts, &source, expansions, diagnostics))
+    };
+    let tokens = expanded.map_err(|e| {
+        diagnostics.push(e.to_diagnostic());
+        report(&diagnostics)
+    })?;
+    let mut warnings = expander.warnings();
+
+    if opts.debug {
+        println!("[DEBUG] Expanded tokens: {:#?}", tokens);
+    }
+
     // Parsing
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse_module().map_err(|e| e.to_string())?;
+    let mut parser = Parser::new(tokens).with_expansions(expansions);
+    let (ast, parse_errors) = parser.parse_module();
+    diagnostics.extend(parse_errors.iter().map(|e| e.to_diagnostic()));
+    warnings.extend(parser.take_warnings());
 
     if opts.debug {
         println!("[DEBUG] AST: {:#?}", ast);
     }
 
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|d| d.span.map(|span| span.start));
+        return Err(report(&diagnostics));
+    }
+
+    // Name resolution
+    let mut levels = LintLevels::new();
+    for (name, level) in &opts.lint_levels {
+        levels.set(name, *level);
+    }
+    if opts.deny_warnings {
+        levels.deny_warnings();
+    }
+    warnings.extend(levels.add_attributes(&ast.attributes));
+    warnings.extend(
+        Resolver::new()
+            .resolve(&ast)
+            .map_err(|e| report(&[e.to_diagnostic()]))?,
+    );
+
+    emit_warnings(opts, &source, expansions, &mut levels, warnings)?;
+
     // Lowering to IR
-    let ir = lower_module(ast);
+    let mut ir = lower_module(ast, &LoweringOptions {
+        strip_asserts: opts.no_asserts,
+    });
+
+    if opts.debug {
+        println!("[DEBUG] IR:\n{}", ir);
+    }
+    validate(&ir).map_err(|e| format!("Invalid IR: {}", e))?;
+
+    // Optimization; constant operations that always fail are errors
+    let mut ir_passes = ir_pass_manager(opts.opt_level, opts.inline_threshold);
+    let mut bytecode_passes = bytecode_pass_manager(opts.opt_level, opts.peephole_stats);
+    configure_passes(opts, &mut ir_passes, &mut bytecode_passes)?;
+    let warnings = ir_passes.run(&mut ir).map_err(|e| match e {
+        PassError::Diagnostics(errors) => report(&errors),
+        PassError::Invalid { pass, message } => format!("Invalid IR after {}: {}", pass, message).into(),
+    })?;
+    emit_warnings(opts, &source, expansions, &mut levels, warnings)?;
 
     if opts.debug {
-        println!("[DEBUG] IR: {:#?}", ir);
+        println!("[DEBUG] Optimized IR:\n{}", ir);
+    }
+    if opts.dump_ir {
+        print!("{}", ir);
     }
 
     // Compile to bytecode
-    let compiler = BytecodeCompiler::new();
-    let bytecode = compiler.compile(&ir);
+    let compiler_options = CompilerOptions {
+        source_name: opts.source_file.clone(),
+        tail_calls: !opts.no_tco,
+    };
+    let compiler = BytecodeCompiler::with_options(compiler_options.clone());
+    let mut bytecode = compiler.compile(&ir).map_err(|errors| report(&errors))?;
+    bytecode_passes.run(&mut bytecode).map_err(|e| match e {
+        PassError::Diagnostics(errors) => report(&errors),
+        PassError::Invalid { pass, message } => format!("Invalid bytecode after {}: {}", pass, message).into(),
+    })?;
 
     if opts.debug {
+        println!("[DEBUG] Pass timings ({}):", opts.opt_level);
+        for timing in ir_passes.timings().iter().chain(bytecode_passes.timings()) {
+            println!("[DEBUG]   {:<12} {:?}", timing.name, timing.duration);
+        }
         println!("[DEBUG] Bytecode constants: {:?}", bytecode.constants);
         println!("[DEBUG] Bytecode instructions: {:?}", bytecode.instructions);
+        println!("[DEBUG] Source map: {:?}", bytecode.source_map.entries());
     }
 
     // Ensure output directory exists
@@ -76,9 +175,95 @@ pub fn run_compiler(opts: &CliOptions) -> Result<(), String> {
 
     // Execute unless --no-run is specified
     if !opts.no_run && !opts.bundle {
-        let mut vm = VirtualMachine::new();
-        vm.execute(&bytecode).map_err(|e| e.to_string())?;
+        match opts.engine {
+            Engine::Stack => {
+                let mut vm = VirtualMachine::new();
+                vm.execute(&bytecode)
+                    .map_err(|e| report(&[e.to_diagnostic(&bytecode.source_map)]))?;
+            }
+            Engine::Register => {
+                // Files and bundles always hold stack bytecode; register
+                // code only exists in memory
+                let code = RegisterCompiler::with_options(compiler_options).compile(&ir);
+                if opts.debug {
+                    println!("[DEBUG] Register code:\n{}", code);
+                }
+                let mut vm = RegisterMachine::new();
+                vm.execute(&code)
+                    .map_err(|e| report(&[e.to_diagnostic(&code.source_map)]))?;
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Apply `--enable-pass`, `--disable-pass` and `--print-after`, whose pass
+/// may be an IR or a bytecode pass
+fn configure_passes(
+    opts: &CliOptions,
+    ir_passes: &mut PassManager<IrModule>,
+    bytecode_passes: &mut PassManager<BytecodeModule>,
+) -> Result<(), String> {
+    let known = [ir_passes.names(), bytecode_passes.names()].concat();
+    let unknown = |name: &str| format!("Unknown pass: {} (expected one of {})", name, known.join(", "));
+    for (name, enabled) in &opts.pass_flags {
+        let found = ir_passes.set_enabled(name, *enabled) | bytecode_passes.set_enabled(name, *enabled);
+        if !found {
+            return Err(unknown(name));
+        }
+    }
+    for name in &opts.print_after {
+        if ir_passes.has_pass(name) {
+            ir_passes.print_after(name);
+        } else if bytecode_passes.has_pass(name) {
+            bytecode_passes.print_after(name);
+        } else {
+            return Err(unknown(name));
+        }
+    }
+    Ok(())
+}
+
+/// Print warnings at their lint levels. Denied lints are errors, which
+/// stop compilation; otherwise compiling goes on.
+fn emit_warnings(
+    opts: &CliOptions,
+    source: &str,
+    expansions: &Expansions,
+    levels: &mut LintLevels,
+    mut warnings: Vec<Diagnostic>,
+) -> Result<(), RunError> {
+    warnings.sort_by_key(|d| d.span.map(|span| span.start));
+    let warnings = levels.apply(warnings);
+    if warnings.iter().any(|d| d.severity == Severity::Error) {
+        return Err(RunError::Diagnostics(render_diagnostics(opts, source, expansions, &warnings)));
+    }
+    if !warnings.is_empty() {
+        let separator = if opts.error_format == ErrorFormat::Human { "\n" } else { "" };
+        eprint!("{}{}", render_diagnostics(opts, source, expansions, &warnings), separator);
+    }
+    Ok(())
+}
+
+/// Render diagnostics in the requested format; text output gets source
+/// snippets, in color on a terminal
+fn render_diagnostics(
+    opts: &CliOptions,
+    source: &str,
+    expansions: &Expansions,
+    diagnostics: &[Diagnostic],
+) -> String {
+    let color = std::io::stderr().is_terminal();
+    let renderer = DiagnosticRenderer::new(&opts.source_file, source, color).with_expansions(expansions);
+    match opts.error_format {
+        ErrorFormat::Human => {
+            let blocks: Vec<String> = diagnostics.iter().map(|d| renderer.render(d)).collect();
+            blocks.join("\n")
+        }
+        ErrorFormat::Json => diagnostics
+            .iter()
+            .map(|d| renderer.render_json(d) + "\n")
+            .collect(),
+    }
+}
@@ -0,0 +1,85 @@
+// artificial-language/ALC/tests/pass_flags.rs
+//! `--enable-pass`, `--disable-pass`, `--print-after` and
+//! `--inline-threshold`, run through the compiler binary
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const PROGRAM: &str = "let x = 2 + 3\naccrete x\n";
+
+/// Run `ALC` with `args` on a copy of `source` named `name`
+fn alc(name: &str, source: &str, args: &[&str]) -> Output {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, source).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_ALC"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// The IR printed after `dce`, which sees what the passes before it did
+fn ir_after_dce(name: &str, args: &[&str]) -> String {
+    let args = [args, &["--print-after=dce"]].concat();
+    stdout(&alc(name, PROGRAM, &args))
+}
+
+#[test]
+fn print_after_shows_the_module_after_the_pass() {
+    let output = stdout(&alc("print_after.art", PROGRAM, &["--print-after=const-fold", "--print-after=peephole"]));
+    let ir = output.find("; after const-fold\nfn main() {\n").unwrap();
+    let bytecode = output.find("; after peephole\n0000  ").unwrap();
+    assert!(ir < bytecode, "{}", output);
+    assert!(output.contains("const 5"), "{}", output);
+    assert!(output.ends_with("5\n"), "{}", output);
+}
+
+#[test]
+fn disable_pass_skips_the_pass() {
+    assert!(ir_after_dce("folded.art", &[]).contains("const 5"));
+    let output = ir_after_dce("unfolded.art", &["--disable-pass=const-fold"]);
+    assert!(output.contains("add %"), "{}", output);
+    assert!(!output.contains("const 5"), "{}", output);
+    assert!(output.ends_with("5\n"), "{}", output);
+}
+
+#[test]
+fn enable_pass_runs_a_pass_the_level_leaves_out() {
+    let output = ir_after_dce("unoptimized.art", &["-O0", "--enable-pass=dce"]);
+    assert!(output.contains("add %"), "{}", output);
+    let output = ir_after_dce("enabled.art", &["-O0", "--enable-pass=dce", "--enable-pass=const-fold"]);
+    assert!(output.contains("const 5"), "{}", output);
+}
+
+#[test]
+fn unknown_pass_names_are_errors() {
+    for flag in ["--enable-pass=licm", "--disable-pass=licm", "--print-after=licm"] {
+        let output = alc("unknown.art", PROGRAM, &[flag]);
+        assert!(!output.status.success(), "{} was accepted", flag);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Unknown pass: licm (expected one of const-fold, dce, inline, peephole)"),
+            "{}",
+            stderr
+        );
+        assert!(output.stdout.is_empty(), "{} ran the program", flag);
+    }
+}
+
+#[test]
+fn inline_threshold_limits_the_inlined_functions() {
+    let source = "fn double(n) { return n * 2 }\naccrete double(4)\n";
+    let output = stdout(&alc("inlined.art", source, &["--print-after=inline"]));
+    assert!(!output.contains("call double"), "{}", output);
+    let output = stdout(&alc("called.art", source, &["--inline-threshold=0", "--print-after=inline"]));
+    assert!(output.contains("call double(%0)"), "{}", output);
+    assert!(output.ends_with("8\n"), "{}", output);
+
+    let output = alc("threshold.art", source, &["--inline-threshold=many"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid inline threshold: many"));
+}
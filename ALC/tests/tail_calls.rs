@@ -0,0 +1,37 @@
+// artificial-language/ALC/tests/tail_calls.rs
+//! `--no-tco` on both engines, run through the compiler binary
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Recurses deeper than the frame limit, always in tail position
+const COUNTDOWN: &str = "fn count(n) {\n    if n == 0 {\n        return \"done\"\n    }\n    return count(n - 1)\n}\naccrete count(20000)\n";
+
+fn alc(name: &str, args: &[&str]) -> Output {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, COUNTDOWN).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_ALC"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn deep_tail_recursion_runs_in_one_frame() {
+    for engine in ["--engine=stack", "--engine=register"] {
+        let output = alc("countdown.art", &[engine]);
+        assert!(output.status.success(), "{}: {}", engine, String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n", "{}", engine);
+    }
+}
+
+#[test]
+fn no_tco_keeps_a_frame_per_call() {
+    for engine in ["--engine=stack", "--engine=register"] {
+        let output = alc("countdown_no_tco.art", &[engine, "--no-tco"]);
+        assert!(!output.status.success(), "{} ran without overflowing", engine);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("error[E0405]: Stack overflow: more than 10000 nested calls"), "{}", stderr);
+        assert!(output.stdout.is_empty(), "{}", engine);
+    }
+}
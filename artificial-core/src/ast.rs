@@ -1,24 +1,245 @@
 // artificial-language/artificial-core/src/ast.rs
+use crate::lints::Level;
+use crate::ops::{BinaryOp, UnaryOp};
+use crate::span::Span;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct AstModule {
+    /// Top-level `fn` items, callable from anywhere in the module
+    pub functions: Vec<AstFunction>,
     pub statements: Vec<AstStatement>,
+    /// Lint attributes anywhere in the module
+    pub attributes: Vec<AstAttribute>,
+    pub span: Span,
 }
 
+/// `#allow(lint, ...)`, `#warn(...)` or `#deny(...)` on the item or
+/// statement that follows it
 #[derive(Debug, Clone)]
-pub enum AstStatement {
+pub struct AstAttribute {
+    pub level: Level,
+    pub lints: Vec<(String, Span)>,
+    pub span: Span,
+    /// The source the attribute applies to, including the attribute itself
+    pub target: Span,
+}
+
+/// `fn name(params) { body }`
+#[derive(Debug, Clone)]
+pub struct AstFunction {
+    /// Functions are items, so their names are not hygienic
+    pub name: String,
+    pub name_span: Span,
+    pub params: Vec<AstParam>,
+    pub body: AstBlock,
+    pub span: Span,
+}
+
+/// `name`, `name = default` or `...name`
+#[derive(Debug, Clone)]
+pub struct AstParam {
+    pub name: Identifier,
+    pub name_span: Span,
+    /// Evaluated at the call site when the argument is left out
+    pub default: Option<AstExpression>,
+    /// Collects the remaining positional arguments into a list
+    pub variadic: bool,
+    pub span: Span,
+}
+
+/// `value` or `name: value` at a call site
+#[derive(Debug, Clone)]
+pub struct AstArgument {
+    pub name: Option<String>,
+    pub value: AstExpression,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct AstStatement {
+    pub kind: AstStatementKind,
+    pub span: Span,
+}
+
+impl AstStatement {
+    pub fn new(kind: AstStatementKind, span: Span) -> Self {
+        AstStatement { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AstStatementKind {
     Accrete(AstExpression),
+    /// `assert condition, message`; the message is optional
+    Assert {
+        condition: AstExpression,
+        message: Option<AstExpression>,
+    },
+    /// `assert_eq left, right`
+    AssertEq {
+        left: AstExpression,
+        right: AstExpression,
+    },
+    /// `let name = value` or `let mut name = value`
+    Let {
+        name: Identifier,
+        name_span: Span,
+        mutable: bool,
+        value: AstExpression,
+    },
+    /// `name = value`
+    Assign {
+        name: Identifier,
+        value: AstExpression,
+    },
+    /// `return value`; a missing value returns null
+    Return { value: Option<AstExpression> },
+    /// An expression evaluated for its side effects; its value is discarded
+    Expression(AstExpression),
+    /// Source that failed to parse; the error has already been reported
+    Error,
+}
+
+/// A name as written in the source. Names a macro template binds are
+/// distinct from the caller's even when spelled the same, which keeps
+/// expansions hygienic.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identifier {
+    pub name: String,
+    /// Expansion whose template bound the name, `None` for names that
+    /// resolve in the user's text
+    pub expansion: Option<usize>,
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
 }
 
 #[derive(Debug, Clone)]
-pub enum AstExpression {
+pub struct AstExpression {
+    pub kind: AstExpressionKind,
+    pub span: Span,
+}
+
+impl AstExpression {
+    pub fn new(kind: AstExpressionKind, span: Span) -> Self {
+        AstExpression { kind, span }
+    }
+
+    /// Block-like expressions can end a statement without a semicolon
+    pub fn is_block_like(&self) -> bool {
+        matches!(
+            self.kind,
+            AstExpressionKind::Block(_)
+                | AstExpressionKind::If { .. }
+                | AstExpressionKind::IfLet { .. }
+                | AstExpressionKind::Match { .. }
+                | AstExpressionKind::For { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AstExpressionKind {
     StringLiteral(String),
+    /// `"text ${expression} text"`; text parts are `StringLiteral`s
+    Interpolation(Vec<AstExpression>),
+    IntegerLiteral(i64),
+    BooleanLiteral(bool),
+    NullLiteral,
+    Variable(Identifier),
+    /// `callee(arguments)`
+    Call {
+        callee: String,
+        args: Vec<AstArgument>,
+    },
+    /// `start..end`, `start..=end`, optionally followed by `step n`
+    Range {
+        start: Box<AstExpression>,
+        end: Box<AstExpression>,
+        step: Option<Box<AstExpression>>,
+        inclusive: bool,
+    },
+    /// `target[range]`
+    Slice {
+        target: Box<AstExpression>,
+        range: Box<AstExpression>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<AstExpression>,
+        right: Box<AstExpression>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<AstExpression>,
+    },
+    /// `value ?? fallback`: the fallback is evaluated only when value is null
+    Coalesce {
+        value: Box<AstExpression>,
+        fallback: Box<AstExpression>,
+    },
+    Block(AstBlock),
+    If {
+        condition: Box<AstExpression>,
+        then_branch: AstBlock,
+        /// Either a block or a chained `else if`
+        else_branch: Option<Box<AstExpression>>,
+    },
+    /// `if let name = value { ... }`: runs the then branch with `name`
+    /// bound when the value is not null
+    IfLet {
+        name: Identifier,
+        name_span: Span,
+        value: Box<AstExpression>,
+        then_branch: AstBlock,
+        else_branch: Option<Box<AstExpression>>,
+    },
+    Match {
+        scrutinee: Box<AstExpression>,
+        arms: Vec<AstMatchArm>,
+    },
+    /// `for variable in iterable { body }`; evaluates to null
+    For {
+        variable: Identifier,
+        variable_span: Span,
+        iterable: Box<AstExpression>,
+        body: AstBlock,
+    },
+    /// A malformed literal; the error has already been reported
+    Error,
+}
+
+/// `{ statements... value }`; the trailing expression is the block's value
+#[derive(Debug, Clone)]
+pub struct AstBlock {
+    pub statements: Vec<AstStatement>,
+    pub value: Option<Box<AstExpression>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct AstMatchArm {
+    pub pattern: AstPattern,
+    pub body: AstExpression,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum AstPattern {
+    Literal(AstExpression),
+    Wildcard(Span),
 }
 
 impl fmt::Display for AstModule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "AstModule {{")?;
+        for function in &self.functions {
+            writeln!(f, "  {:?}", function)?;
+        }
         for stmt in &self.statements {
             writeln!(f, "  {:?}", stmt)?;
         }
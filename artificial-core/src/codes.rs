@@ -0,0 +1,384 @@
+// artificial-language/artificial-core/src/codes.rs
+//! Stable diagnostic codes. Errors are written `E` and four digits; the
+//! hundreds digit names the stage that reports them: 0 lexing, 1 macro
+//! expansion, 2 parsing, 3 name resolution, 4 execution and 5 code
+//! generation. Lints are written `W` and four digits. Codes are never
+//! reused once published.
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErrorCode {
+    prefix: char,
+    number: u16,
+}
+
+impl ErrorCode {
+    pub const fn error(number: u16) -> Self {
+        ErrorCode {
+            prefix: 'E',
+            number,
+        }
+    }
+
+    pub const fn lint(number: u16) -> Self {
+        ErrorCode {
+            prefix: 'W',
+            number,
+        }
+    }
+
+    /// Read a code as written by users, such as `E0301` or `w0001`
+    pub fn parse(text: &str) -> Option<ErrorCode> {
+        let first = text.chars().next()?;
+        let prefix = first.to_ascii_uppercase();
+        let digits = &text[first.len_utf8()..];
+        if !matches!(prefix, 'E' | 'W')
+            || digits.len() != 4
+            || !digits.bytes().all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let number = digits.parse().ok()?;
+        Some(ErrorCode { prefix, number })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{:04}", self.prefix, self.number)
+    }
+}
+
+pub const UNEXPECTED_CHARACTER: ErrorCode = ErrorCode::error(1);
+pub const INTEGER_OUT_OF_RANGE: ErrorCode = ErrorCode::error(2);
+pub const UNTERMINATED_STRING: ErrorCode = ErrorCode::error(3);
+
+pub const MACRO_NESTING_LIMIT: ErrorCode = ErrorCode::error(101);
+pub const MALFORMED_MACRO_DEFINITION: ErrorCode = ErrorCode::error(102);
+pub const DUPLICATE_MACRO_PARAMETER: ErrorCode = ErrorCode::error(103);
+pub const MACRO_ARGUMENT_COUNT: ErrorCode = ErrorCode::error(104);
+pub const MALFORMED_MACRO_INVOCATION: ErrorCode = ErrorCode::error(105);
+
+pub const UNEXPECTED_TOKEN: ErrorCode = ErrorCode::error(201);
+pub const NESTED_FUNCTION: ErrorCode = ErrorCode::error(202);
+pub const INVALID_PARAMETER_ORDER: ErrorCode = ErrorCode::error(203);
+pub const DUPLICATE_PARAMETER: ErrorCode = ErrorCode::error(204);
+pub const INVALID_PATTERN: ErrorCode = ErrorCode::error(205);
+pub const MALFORMED_ATTRIBUTE: ErrorCode = ErrorCode::error(206);
+
+pub const UNKNOWN_VARIABLE: ErrorCode = ErrorCode::error(301);
+pub const ASSIGN_TO_IMMUTABLE: ErrorCode = ErrorCode::error(302);
+pub const UNKNOWN_FUNCTION: ErrorCode = ErrorCode::error(303);
+pub const DUPLICATE_FUNCTION: ErrorCode = ErrorCode::error(304);
+pub const ARGUMENT_MISMATCH: ErrorCode = ErrorCode::error(305);
+pub const RETURN_OUTSIDE_FUNCTION: ErrorCode = ErrorCode::error(306);
+
+pub const INVALID_BYTECODE: ErrorCode = ErrorCode::error(400);
+pub const ASSERTION_FAILED: ErrorCode = ErrorCode::error(401);
+pub const TYPE_MISMATCH: ErrorCode = ErrorCode::error(402);
+pub const INVALID_SLICE: ErrorCode = ErrorCode::error(403);
+pub const INVALID_RANGE: ErrorCode = ErrorCode::error(404);
+pub const STACK_OVERFLOW: ErrorCode = ErrorCode::error(405);
+pub const INTEGER_OVERFLOW: ErrorCode = ErrorCode::error(406);
+pub const DIVISION_BY_ZERO: ErrorCode = ErrorCode::error(407);
+pub const NULL_RECEIVER: ErrorCode = ErrorCode::error(408);
+
+pub const TOO_MANY_CONSTANTS: ErrorCode = ErrorCode::error(501);
+
+pub const UNUSED_VARIABLES: ErrorCode = ErrorCode::lint(1);
+pub const UNREACHABLE_CODE: ErrorCode = ErrorCode::lint(2);
+pub const SHADOWED_BINDINGS: ErrorCode = ErrorCode::lint(3);
+pub const UNUSED_MACROS: ErrorCode = ErrorCode::lint(4);
+pub const NEEDLESS_SEMICOLONS: ErrorCode = ErrorCode::lint(5);
+pub const UNKNOWN_LINTS: ErrorCode = ErrorCode::lint(6);
+pub const UNUSED_FUNCTIONS: ErrorCode = ErrorCode::lint(7);
+
+/// The long-form description of `code`, for `ALC explain`
+pub fn explain(code: ErrorCode) -> Option<&'static str> {
+    let text = match code {
+        UNEXPECTED_CHARACTER => {
+            "A character that cannot start any token was found.
+
+The lexer skips the character and keeps going, so other errors in the file
+are still reported. Characters that are not part of the language may only
+appear inside string literals and comments.
+
+    let x = 1 @ 2    // `@` is not an operator"
+        }
+        INTEGER_OUT_OF_RANGE => {
+            "An integer literal does not fit in a signed 64-bit integer.
+
+Integers range from -9223372036854775808 to 9223372036854775807. The
+literal 9223372036854775808 is only allowed right after a minus sign.
+
+    let big = 99999999999999999999"
+        }
+        UNTERMINATED_STRING => {
+            "A string literal is missing its closing quote.
+
+Strings may span lines, so an unterminated literal runs to the end of the
+file and everything after the opening quote is part of it.
+
+    accrete \"hello"
+        }
+        MACRO_NESTING_LIMIT => {
+            "A macro expansion kept expanding into further macro calls.
+
+Expansion stops after a fixed nesting depth. This usually means a macro
+invokes itself, directly or through another macro.
+
+    macro loop() => { loop() }
+    loop()"
+        }
+        MALFORMED_MACRO_DEFINITION => {
+            "A macro definition does not have the form
+`macro name(params) => { body }`.
+
+    macro (a) => { a }         // missing name
+    macro twice(a) { a a }     // missing `=>`"
+        }
+        DUPLICATE_MACRO_PARAMETER => {
+            "A macro declares the same parameter name twice.
+
+    macro pair(a, a) => { a }"
+        }
+        MACRO_ARGUMENT_COUNT => {
+            "A macro was invoked with a different number of arguments than it
+declares. Macros have no default or variadic parameters.
+
+    macro pair(a, b) => { a b }
+    pair(1)"
+        }
+        MALFORMED_MACRO_INVOCATION => {
+            "A macro invocation is missing its closing parenthesis or has an
+empty argument.
+
+    macro show(a) => { accrete a }
+    show(\"x\",)"
+        }
+        UNEXPECTED_TOKEN => {
+            "The parser found a token that cannot appear at this point.
+
+The message names what was expected. Parsing resumes at the next statement,
+so one mistake can be followed by further, related errors.
+
+    let x = ;
+    { 1 2 }        // missing `;` between expressions"
+        }
+        NESTED_FUNCTION => {
+            "A function was declared inside a block. Functions can only be
+declared at the top level of a file, where they are visible everywhere.
+
+    fn outer() {
+        fn inner() { 1 }
+    }"
+        }
+        INVALID_PARAMETER_ORDER => {
+            "Parameters are in an order calls could not bind unambiguously.
+
+Parameters with default values must follow those without, and a variadic
+`...rest` parameter must come last.
+
+    fn f(a = 1, b) { b }
+    fn g(...rest, last) { last }"
+        }
+        DUPLICATE_PARAMETER => {
+            "A function declares the same parameter name twice.
+
+    fn f(a, a) { a }"
+        }
+        INVALID_PATTERN => {
+            "A `match` arm uses a pattern other than a literal or `_`.
+
+    match x {
+        y => 1,        // use `_` to match anything
+    }"
+        }
+        UNKNOWN_VARIABLE => {
+            "A name was used that is not bound in any enclosing scope.
+
+Variables are visible from their `let` to the end of the enclosing block.
+Function bodies only see their own parameters and locals.
+
+    accrete total
+    let total = 1"
+        }
+        ASSIGN_TO_IMMUTABLE => {
+            "A variable that is immutable was assigned to.
+
+Bindings are immutable unless declared with `let mut`. Parameters and loop
+variables can never be assigned.
+
+    let count = 0
+    count = 1        // declare it with `let mut count = 0`"
+        }
+        UNKNOWN_FUNCTION => {
+            "A call names a function that is not declared anywhere in the file.
+
+    greet(\"world\")"
+        }
+        DUPLICATE_FUNCTION => {
+            "Two functions share a name. Functions cannot be overloaded.
+
+    fn f() { 1 }
+    fn f(a) { a }"
+        }
+        ARGUMENT_MISMATCH => {
+            "The arguments of a call do not match the parameters of the function.
+
+Every parameter without a default needs exactly one argument. Positional
+arguments come before named ones, and a name may only be given once.
+
+    fn f(a, b = 2) { a }
+    f(b: 1)          // missing `a`
+    f(1, c: 3)       // no parameter `c`"
+        }
+        RETURN_OUTSIDE_FUNCTION => {
+            "`return` was used outside of a function body.
+
+    return 1"
+        }
+        INVALID_BYTECODE => {
+            "The virtual machine found malformed bytecode, such as an unknown
+opcode or a jump out of bounds.
+
+Bytecode produced by the compiler never does this; the `.artb` file was
+probably damaged or written by a different compiler version."
+        }
+        ASSERTION_FAILED => {
+            "An `assert` condition was false, or the two sides of an
+`assert_eq` were different. Build with `--no-asserts` to strip assertions.
+
+    assert_eq 1, 2"
+        }
+        TYPE_MISMATCH => {
+            "A value had the wrong type for the operation applied to it, for
+example a condition that is not a boolean or an integer where a range
+bound was expected.
+
+    if 1 { accrete \"yes\" }"
+        }
+        INVALID_SLICE => {
+            "A slice range is outside of the string or list being sliced, or the
+sliced value is not a string or list.
+
+    \"abc\"[1..5]"
+        }
+        INVALID_RANGE => {
+            "A range was built with a step that is zero or negative.
+
+    for i in 0..10 step 0 { accrete i }"
+        }
+        STACK_OVERFLOW => {
+            "Function calls were nested too deeply, usually because of
+recursion without a base case.
+
+    fn forever(n) { 1 + forever(n) }
+    forever(1)
+
+A call whose result is returned as is, such as `count(n - 1)` at the end
+of `count`, reuses the caller's frame and never nests, unless the program
+is built with `--no-tco`."
+        }
+        INTEGER_OVERFLOW => {
+            "Integer arithmetic produced a result that does not fit in 64 bits.
+
+Integers are signed 64-bit values and arithmetic never wraps around. When
+every operand is a constant, the compiler reports the overflow before the
+program runs.
+
+    accrete 9223372036854775807 + 1"
+        }
+        DIVISION_BY_ZERO => {
+            "An integer was divided by zero, or its remainder by zero was taken.
+
+When both operands are constants, the compiler reports the error before
+the program runs.
+
+    accrete 10 / 0"
+        }
+        NULL_RECEIVER => {
+            "A value that is `null` was sliced. Check for `null` with `if let`, or
+give a fallback with `??`.
+
+    let name = if false { \"Ada\" }
+    accrete name[0..1]                  // fails
+    accrete (name ?? \"anonymous\")[0..1]  // prints `a`"
+        }
+        TOO_MANY_CONSTANTS => {
+            "The program uses more distinct constants than a bytecode module can
+hold. Constants are literals such as strings and numbers, plus one source
+location per assertion; each distinct value is stored once.
+
+A module holds up to 16,777,216 constants, and at most 65,536 of them may
+be assertion locations. Split the program, or build it with `--no-asserts`."
+        }
+        MALFORMED_ATTRIBUTE => {
+            "An attribute is not one of `#allow(...)`, `#warn(...)` or
+`#deny(...)`, or nothing follows it.
+
+Attributes set lint levels for the item or statement right after them.
+
+    #allow unused_variables     // missing parentheses
+    #ignore(unused)             // unknown attribute"
+        }
+        UNUSED_VARIABLES => {
+            "Lint `unused_variables`: a variable, parameter or loop variable is
+never read. Remove it, or start its name with `_` to show that is intended.
+
+    fn greet(name, greeting) { accrete name }    // `greeting` is unused"
+        }
+        UNREACHABLE_CODE => {
+            "Lint `unreachable_code`: statements follow a `return` in the same
+block, `match` arms follow a `_` arm, or code sits behind a condition that is
+always true or always false, so it can never run. The compiler leaves such
+code out of the bytecode.
+
+    fn f() {
+        return 1
+        accrete \"never printed\"
+    }
+
+    if false { accrete \"never printed\" }
+
+    match n { _ => 0, 1 => 1 }    // the `1` arm is never chosen"
+        }
+        SHADOWED_BINDINGS => {
+            "Lint `shadowed_bindings`: a `let`, parameter or loop variable has
+the same name as a binding in an enclosing scope, which is hidden from then on.
+
+    let count = 1
+    for count in 0..3 { accrete count }"
+        }
+        UNUSED_FUNCTIONS => {
+            "Lint `unused_functions`: a function is never called, either by the
+top-level statements or by a function they call. The compiler leaves it out
+of the bytecode. Start its name with `_` to show that is intended.
+
+    fn helper() { 1 }    // nothing calls `helper`"
+        }
+        UNUSED_MACROS => {
+            "Lint `unused_macros`: a macro is defined but never invoked.
+
+    macro twice(a) => { a a }"
+        }
+        NEEDLESS_SEMICOLONS => {
+            "Lint `needless_semicolons`: a statement ends with a semicolon it
+does not need. Semicolons are only required where the next statement would
+otherwise continue the current one.
+
+    accrete \"hello\";"
+        }
+        UNKNOWN_LINTS => {
+            "Lint `unknown_lints`: an attribute or command-line flag names a lint
+that does not exist. The lints are `unused_variables`, `unused_functions`,
+`unreachable_code`, `shadowed_bindings`, `unused_macros`,
+`needless_semicolons` and `unknown_lints`; `unused` names the three unused
+lints and `warnings` all of them.
+
+    #allow(unused_varaibles)"
+        }
+        _ => return None,
+    };
+    Some(text)
+}
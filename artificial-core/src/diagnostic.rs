@@ -0,0 +1,311 @@
+// artificial-language/artificial-core/src/diagnostic.rs
+use crate::codes::ErrorCode;
+use crate::json::Json;
+use crate::macros::Expansions;
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A secondary span that explains a diagnostic, such as a declaration
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An edit that would fix a diagnostic: replace the text of `span` with
+/// `replacement`. An empty span inserts. Suggestions are for tools; people
+/// read the diagnostic's help instead.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// A problem found in a program, in a form every stage of the compiler
+/// and the VM can report the same way
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable code from `codes`
+    pub code: ErrorCode,
+    pub message: String,
+    /// Where the problem is; runtime errors outside the source map have none
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    pub suggestions: Vec<Suggestion>,
+    /// The lint that reported this, for warnings that come from `lints`
+    pub lint: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: String) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: ErrorCode, message: String) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    fn new(severity: Severity, code: ErrorCode, message: String) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+            suggestions: Vec::new(),
+            lint: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestions.push(suggestion);
+        self
+    }
+}
+
+/// The single-line form, for places without the source at hand
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.code)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics against the source they refer to, with the offending
+/// lines underlined:
+///
+/// ```text
+/// error[E0301]: Unknown variable `x`
+///  --> main.art:3:9
+///   |
+/// 3 | accrete x
+///   |         ^
+/// ```
+pub struct DiagnosticRenderer<'a> {
+    file_name: &'a str,
+    source: &'a str,
+    color: bool,
+    expansions: Option<&'a Expansions>,
+}
+
+impl<'a> DiagnosticRenderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str, color: bool) -> Self {
+        DiagnosticRenderer {
+            file_name,
+            source,
+            color,
+            expansions: None,
+        }
+    }
+
+    /// Label diagnostics inside macro expansions with the invocation and
+    /// the definition of the macro
+    pub fn with_expansions(mut self, expansions: &'a Expansions) -> Self {
+        self.expansions = Some(expansions);
+        self
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let diagnostic = &self.with_expansion_labels(diagnostic);
+        let accent = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = format!(
+            "{}{}\n",
+            self.paint(accent, &format!("{}[{}]", diagnostic.severity, diagnostic.code)),
+            self.paint(BOLD, &format!(": {}", diagnostic.message))
+        );
+
+        let lines: Vec<usize> = diagnostic
+            .span
+            .iter()
+            .chain(diagnostic.labels.iter().map(|label| &label.span))
+            .map(|span| span.line)
+            .collect();
+        let width = lines.iter().max().map_or(1, |line| line.to_string().len());
+        let gutter = " ".repeat(width);
+
+        if let Some(span) = diagnostic.span {
+            out.push_str(&format!(
+                "{}{} {}:{}\n",
+                gutter,
+                self.paint(BLUE, "-->"),
+                self.file_name,
+                span
+            ));
+            out.push_str(&format!("{} {}\n", gutter, self.paint(BLUE, "|")));
+            out.push_str(&self.snippet(span, '^', accent, "", width));
+        }
+        for label in &diagnostic.labels {
+            out.push_str(&format!("{} {}\n", gutter, self.paint(BLUE, "|")));
+            out.push_str(&self.snippet(label.span, '-', BLUE, &label.message, width));
+        }
+
+        if !diagnostic.notes.is_empty() || diagnostic.help.is_some() {
+            out.push_str(&format!("{} {}\n", gutter, self.paint(BLUE, "|")));
+        }
+        for note in &diagnostic.notes {
+            out.push_str(&format!("{} {} note: {}\n", gutter, self.paint(BLUE, "="), note));
+        }
+        if let Some(help) = &diagnostic.help {
+            out.push_str(&format!("{} {} help: {}\n", gutter, self.paint(BLUE, "="), help));
+        }
+        out
+    }
+
+    /// One line of JSON, for editors and CI
+    pub fn render_json(&self, diagnostic: &Diagnostic) -> String {
+        let diagnostic = &self.with_expansion_labels(diagnostic);
+        let labels = diagnostic
+            .labels
+            .iter()
+            .map(|label| {
+                Json::object([
+                    ("message", label.message.as_str().into()),
+                    ("span", span_json(label.span)),
+                ])
+            })
+            .collect();
+        let suggestions = diagnostic
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                Json::object([
+                    ("message", suggestion.message.as_str().into()),
+                    ("span", span_json(suggestion.span)),
+                    ("replacement", suggestion.replacement.as_str().into()),
+                ])
+            })
+            .collect();
+        Json::object([
+            ("file", self.file_name.into()),
+            ("severity", diagnostic.severity.to_string().into()),
+            ("code", diagnostic.code.to_string().into()),
+            ("message", diagnostic.message.as_str().into()),
+            ("span", diagnostic.span.map_or(Json::Null, span_json)),
+            ("labels", Json::Array(labels)),
+            (
+                "notes",
+                Json::Array(diagnostic.notes.iter().map(|n| n.as_str().into()).collect()),
+            ),
+            ("help", diagnostic.help.as_deref().into()),
+            ("suggestions", Json::Array(suggestions)),
+            ("lint", diagnostic.lint.into()),
+        ])
+        .to_string()
+    }
+
+    fn with_expansion_labels(&self, diagnostic: &Diagnostic) -> Diagnostic {
+        match self.expansions {
+            Some(expansions) => expansions.label(diagnostic.clone()),
+            None => diagnostic.clone(),
+        }
+    }
+
+    /// The source line `span` starts on, underlined up to the end of the
+    /// span or the line, whichever comes first
+    fn snippet(&self, span: Span, marker: char, color: &str, message: &str, width: usize) -> String {
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let text = self.source[line_start..line_end].trim_end_matches('\r');
+
+        // Keep tabs so the underline lines up with the text above it
+        let indent: String = self.source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = span.end.clamp(start, line_end);
+        let length = self.source[start..end].chars().count().max(1);
+        let underline = marker.to_string().repeat(length);
+
+        let mut out = format!(
+            "{} {} {}\n",
+            self.paint(BLUE, &format!("{:>width$}", span.line, width = width)),
+            self.paint(BLUE, "|"),
+            text
+        );
+        out.push_str(&format!(
+            "{} {} {}{}",
+            " ".repeat(width),
+            self.paint(BLUE, "|"),
+            indent,
+            self.paint(color, &underline)
+        ));
+        if !message.is_empty() {
+            out.push_str(&format!(" {}", self.paint(color, message)));
+        }
+        out.push('\n');
+        out
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+fn span_json(span: Span) -> Json {
+    Json::object([
+        ("start", span.start.into()),
+        ("end", span.end.into()),
+        ("line", span.line.into()),
+        ("column", span.column.into()),
+    ])
+}
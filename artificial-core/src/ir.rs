@@ -1,27 +1,666 @@
 // artificial-language/artificial-core/src/ir.rs
+//! The intermediate representation: functions made of basic blocks in SSA
+//! form. Every temporary is assigned exactly once, either by an
+//! instruction or by a phi at the start of a block, and every block ends
+//! in a single terminator.
+use crate::ops::{BinaryOp, UnaryOp};
+use crate::span::Span;
+use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct IrModule {
-    pub statements: Vec<IrStatement>,
+    /// Callable functions; `Call` refers to them by index
+    pub functions: Vec<IrFunction>,
+    /// The top-level statements, which end in `Halt` instead of `Return`
+    pub main: IrFunction,
+    pub span: Span,
+}
+
+/// An SSA temporary, numbered per function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Temp(pub u32);
+
+impl Temp {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+/// A basic block, numbered per function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl BlockId {
+    /// Every function starts executing here
+    pub const ENTRY: BlockId = BlockId(0);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    /// Parameters arrive as `Param(0..param_count)`
+    pub param_count: usize,
+    /// `blocks[0]` is the entry block
+    pub blocks: Vec<BasicBlock>,
+    /// Temporaries are numbered `0..temp_count`
+    pub temp_count: u32,
+    pub span: Span,
+}
+
+impl IrFunction {
+    pub fn new(name: String, param_count: usize, span: Span) -> Self {
+        IrFunction {
+            name,
+            param_count,
+            blocks: Vec::new(),
+            temp_count: 0,
+            span,
+        }
+    }
+
+    pub fn new_temp(&mut self) -> Temp {
+        let temp = Temp(self.temp_count);
+        self.temp_count += 1;
+        temp
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id.index()]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut BasicBlock {
+        &mut self.blocks[id.index()]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+
+    /// The indices of the functions this one calls, once per call
+    pub fn callees(&self) -> Vec<usize> {
+        self.blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| match instruction.kind {
+                InstructionKind::Call { function, .. } => Some(function),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The predecessors of every block, indexed by block
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for id in self.block_ids() {
+            for successor in self.block(id).terminator.kind.successors() {
+                predecessors[successor.index()].push(id);
+            }
+        }
+        predecessors
+    }
+
+    /// Blocks reachable from the entry, each before its successors except
+    /// along back edges. Branches put their first successor first.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let successors = |id: BlockId| self.block(id).terminator.kind.successors();
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        // (block, successors left to visit). Successors are visited last to
+        // first, which places the first one right after the block.
+        let mut stack = vec![(BlockId::ENTRY, successors(BlockId::ENTRY))];
+        visited[0] = true;
+        while let Some((block, pending)) = stack.last_mut() {
+            match pending.pop() {
+                Some(next) if !visited[next.index()] => {
+                    visited[next.index()] = true;
+                    stack.push((next, successors(next)));
+                }
+                Some(_) => {}
+                None => {
+                    postorder.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// The immediate dominator of every reachable block; the entry block
+    /// dominates itself and unreachable blocks have none
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (index, block) in order.iter().enumerate() {
+            position[block.index()] = index;
+        }
+        let predecessors = self.predecessors();
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(BlockId::ENTRY);
+
+        // Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm"
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while position[a.index()] > position[b.index()] {
+                    a = idom[a.index()].unwrap();
+                }
+                while position[b.index()] > position[a.index()] {
+                    b = idom[b.index()].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &predecessors[block.index()] {
+                    if idom[pred.index()].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(current) => intersect(&idom, pred, current),
+                    });
+                }
+                if new_idom.is_some() && idom[block.index()] != new_idom {
+                    idom[block.index()] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    /// Replace phis whose inputs are all one value, or the phi itself, with
+    /// that value, until none are left
+    pub fn remove_trivial_phis(&mut self) {
+        let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+        let resolve = |replacements: &HashMap<Temp, Temp>, mut temp: Temp| {
+            while let Some(&next) = replacements.get(&temp) {
+                temp = next;
+            }
+            temp
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in &self.blocks {
+                for phi in &block.phis {
+                    if replacements.contains_key(&phi.dest) {
+                        continue;
+                    }
+                    let mut same = None;
+                    let mut trivial = true;
+                    for &(_, value) in &phi.incoming {
+                        let value = resolve(&replacements, value);
+                        if value == phi.dest || Some(value) == same {
+                            continue;
+                        }
+                        if same.is_some() {
+                            trivial = false;
+                            break;
+                        }
+                        same = Some(value);
+                    }
+                    // A phi that only merges itself is in an unreachable loop
+                    if let (true, Some(same)) = (trivial, same) {
+                        replacements.insert(phi.dest, same);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if replacements.is_empty() {
+            return;
+        }
+
+        for block in &mut self.blocks {
+            block.phis.retain(|phi| !replacements.contains_key(&phi.dest));
+            for phi in &mut block.phis {
+                for (_, value) in &mut phi.incoming {
+                    *value = resolve(&replacements, *value);
+                }
+            }
+            for instruction in &mut block.instructions {
+                for operand in instruction.kind.operands_mut() {
+                    *operand = resolve(&replacements, *operand);
+                }
+            }
+            for operand in block.terminator.kind.operands_mut() {
+                *operand = resolve(&replacements, *operand);
+            }
+        }
+    }
+
+    /// Move the instructions of `id` from `at` on, and its terminator, to a
+    /// new block that `id` jumps to. Phis in the successors then receive
+    /// their values from the new block.
+    pub fn split_block(&mut self, id: BlockId, at: usize) -> BlockId {
+        let new = BlockId(self.blocks.len() as u32);
+        let block = self.block_mut(id);
+        let instructions = block.instructions.split_off(at);
+        let span = block.terminator.span;
+        let terminator = std::mem::replace(
+            &mut block.terminator,
+            Terminator::new(TerminatorKind::Jump(new), span),
+        );
+        for successor in terminator.kind.successors() {
+            for phi in &mut self.block_mut(successor).phis {
+                for (pred, _) in &mut phi.incoming {
+                    if *pred == id {
+                        *pred = new;
+                    }
+                }
+            }
+        }
+        self.blocks.push(BasicBlock {
+            phis: Vec::new(),
+            instructions,
+            terminator,
+        });
+        new
+    }
+
+    /// Put the blocks in `order`, renumbering them; blocks left out of
+    /// `order` are dropped, along with phi inputs from them
+    pub fn reorder_blocks(&mut self, order: &[BlockId]) {
+        let mut renumbered = vec![None; self.blocks.len()];
+        for (index, block) in order.iter().enumerate() {
+            renumbered[block.index()] = Some(BlockId(index as u32));
+        }
+        let mut old = std::mem::take(&mut self.blocks);
+        self.blocks = order
+            .iter()
+            .map(|block| std::mem::take(&mut old[block.index()]))
+            .collect();
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                phi.incoming.retain_mut(|(pred, _)| match renumbered[pred.index()] {
+                    Some(new) => {
+                        *pred = new;
+                        true
+                    }
+                    None => false,
+                });
+            }
+            for successor in block.terminator.kind.successors_mut() {
+                *successor = renumbered[successor.index()]
+                    .expect("a kept block jumps to a dropped block");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// Selects the value of `dest` by the predecessor control came from
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub dest: Temp,
+    /// One value per predecessor
+    pub incoming: Vec<(BlockId, Temp)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
-pub enum IrStatement {
-    Accrete(IrExpression),
+pub struct Instruction {
+    /// The temporary the instruction defines, if it produces a value
+    pub dest: Option<Temp>,
+    pub kind: InstructionKind,
+    pub span: Span,
+}
+
+impl Instruction {
+    pub fn new(dest: Option<Temp>, kind: InstructionKind, span: Span) -> Self {
+        Instruction { dest, kind, span }
+    }
+}
+
+/// A literal value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Null,
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Null => write!(f, "null"),
+            Constant::String(s) => write!(f, "{:?}", s),
+            Constant::Integer(n) => write!(f, "{}", n),
+            Constant::Boolean(b) => write!(f, "{}", b),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub enum IrExpression {
-    StringLiteral(String),
+pub enum InstructionKind {
+    Const(Constant),
+    /// The argument passed for a parameter
+    Param(u16),
+    /// Call `functions[function]` with exactly one value per parameter
+    Call { function: usize, args: Vec<Temp> },
+    /// Build a list from the values, in order
+    List(Vec<Temp>),
+    /// Whether the value is null
+    IsNull(Temp),
+    Binary {
+        op: BinaryOp,
+        left: Temp,
+        right: Temp,
+    },
+    Unary { op: UnaryOp, operand: Temp },
+    /// Join the printed forms of the values into one string, for string
+    /// interpolation
+    Concat(Vec<Temp>),
+    Range {
+        start: Temp,
+        end: Temp,
+        step: Temp,
+        inclusive: bool,
+    },
+    Slice { target: Temp, range: Temp },
+    /// Start iterating over a value; defines the iterator `Next` advances
+    IterStart(Temp),
+    /// Print a value followed by a newline
+    Print(Temp),
+    Assert {
+        condition: Temp,
+        message: Option<Temp>,
+    },
+    AssertEq { left: Temp, right: Temp },
+}
+
+impl InstructionKind {
+    /// The temporaries the instruction reads, in evaluation order
+    pub fn operands(&self) -> Vec<Temp> {
+        match self {
+            InstructionKind::Const(_) | InstructionKind::Param(_) => Vec::new(),
+            InstructionKind::Call { args, .. }
+            | InstructionKind::List(args)
+            | InstructionKind::Concat(args) => args.clone(),
+            InstructionKind::IsNull(value)
+            | InstructionKind::Unary { operand: value, .. }
+            | InstructionKind::IterStart(value)
+            | InstructionKind::Print(value) => vec![*value],
+            InstructionKind::Binary { left, right, .. }
+            | InstructionKind::AssertEq { left, right } => vec![*left, *right],
+            InstructionKind::Range {
+                start, end, step, ..
+            } => vec![*start, *end, *step],
+            InstructionKind::Slice { target, range } => vec![*target, *range],
+            InstructionKind::Assert { condition, message } => {
+                std::iter::once(*condition).chain(*message).collect()
+            }
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Temp> {
+        match self {
+            InstructionKind::Const(_) | InstructionKind::Param(_) => Vec::new(),
+            InstructionKind::Call { args, .. }
+            | InstructionKind::List(args)
+            | InstructionKind::Concat(args) => args.iter_mut().collect(),
+            InstructionKind::IsNull(value)
+            | InstructionKind::Unary { operand: value, .. }
+            | InstructionKind::IterStart(value)
+            | InstructionKind::Print(value) => vec![value],
+            InstructionKind::Binary { left, right, .. }
+            | InstructionKind::AssertEq { left, right } => vec![left, right],
+            InstructionKind::Range {
+                start, end, step, ..
+            } => vec![start, end, step],
+            InstructionKind::Slice { target, range } => vec![target, range],
+            InstructionKind::Assert { condition, message } => {
+                std::iter::once(condition).chain(message.as_mut()).collect()
+            }
+        }
+    }
+
+    /// Whether executing the instruction can do anything besides defining
+    /// its result: print, fail, call a function or start an iteration
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            InstructionKind::Const(_)
+            | InstructionKind::Param(_)
+            | InstructionKind::List(_)
+            | InstructionKind::IsNull(_)
+            | InstructionKind::Concat(_) => false,
+            InstructionKind::Binary { op, .. } => op.can_fail(),
+            InstructionKind::Unary { .. }
+            | InstructionKind::Call { .. }
+            | InstructionKind::Range { .. }
+            | InstructionKind::Slice { .. }
+            | InstructionKind::IterStart(_)
+            | InstructionKind::Print(_)
+            | InstructionKind::Assert { .. }
+            | InstructionKind::AssertEq { .. } => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Terminator {
+    pub kind: TerminatorKind,
+    pub span: Span,
+}
+
+impl Terminator {
+    pub fn new(kind: TerminatorKind, span: Span) -> Self {
+        Terminator { kind, span }
+    }
 }
 
+#[derive(Debug, Clone, Default)]
+pub enum TerminatorKind {
+    Jump(BlockId),
+    /// Go to `then_block` when the condition is true and to `else_block`
+    /// when it is false
+    Branch {
+        condition: Temp,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// Advance an iterator: define `item` and go to `body`, or go to
+    /// `exit` when it is exhausted. `item` is only available in `body`.
+    Next {
+        iterator: Temp,
+        item: Temp,
+        body: BlockId,
+        exit: BlockId,
+    },
+    /// Leave the function with a value
+    Return(Temp),
+    /// Stop the program; ends `main`
+    #[default]
+    Halt,
+}
+
+impl TerminatorKind {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            TerminatorKind::Jump(target) => vec![*target],
+            TerminatorKind::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            TerminatorKind::Next { body, exit, .. } => vec![*body, *exit],
+            TerminatorKind::Return(_) | TerminatorKind::Halt => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            TerminatorKind::Jump(target) => vec![target],
+            TerminatorKind::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            TerminatorKind::Next { body, exit, .. } => vec![body, exit],
+            TerminatorKind::Return(_) | TerminatorKind::Halt => Vec::new(),
+        }
+    }
+
+    /// The temporaries the terminator reads
+    pub fn operands(&self) -> Vec<Temp> {
+        match self {
+            TerminatorKind::Branch { condition, .. } => vec![*condition],
+            TerminatorKind::Next { iterator, .. } => vec![*iterator],
+            TerminatorKind::Return(value) => vec![*value],
+            TerminatorKind::Jump(_) | TerminatorKind::Halt => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Temp> {
+        match self {
+            TerminatorKind::Branch { condition, .. } => vec![condition],
+            TerminatorKind::Next { iterator, .. } => vec![iterator],
+            TerminatorKind::Return(value) => vec![value],
+            TerminatorKind::Jump(_) | TerminatorKind::Halt => Vec::new(),
+        }
+    }
+}
+
+/// The textual form printed by `ALC --dump-ir`:
+///
+/// ```text
+/// fn main() {
+/// bb0:
+///     %0 = const "Hello"
+///     print %0
+///     halt
+/// }
+/// ```
 impl fmt::Display for IrModule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "IrModule {{")?;
-        for stmt in &self.statements {
-            writeln!(f, "  {:?}", stmt)?;
+        write_function(f, self, &self.main)?;
+        for function in &self.functions {
+            writeln!(f)?;
+            write_function(f, self, function)?;
         }
-        write!(f, "}}")
+        Ok(())
+    }
+}
+
+fn write_function(f: &mut fmt::Formatter<'_>, module: &IrModule, function: &IrFunction) -> fmt::Result {
+    let params: Vec<String> = (0..function.param_count).map(|i| format!("p{}", i)).collect();
+    writeln!(f, "fn {}({}) {{", function.name, params.join(", "))?;
+    let predecessors = function.predecessors();
+    for id in function.block_ids() {
+        let block = function.block(id);
+        write!(f, "{}:", id)?;
+        if !predecessors[id.index()].is_empty() {
+            let preds: Vec<String> = predecessors[id.index()].iter().map(|p| p.to_string()).collect();
+            write!(f, "  ; preds: {}", preds.join(", "))?;
+        }
+        writeln!(f)?;
+        for phi in &block.phis {
+            let incoming: Vec<String> = phi
+                .incoming
+                .iter()
+                .map(|(pred, value)| format!("[{}: {}]", pred, value))
+                .collect();
+            writeln!(f, "    {} = phi {}", phi.dest, incoming.join(", "))?;
+        }
+        for instruction in &block.instructions {
+            write!(f, "    ")?;
+            if let Some(dest) = instruction.dest {
+                write!(f, "{} = ", dest)?;
+            }
+            write_instruction(f, module, &instruction.kind)?;
+            writeln!(f)?;
+        }
+        write!(f, "    ")?;
+        write_terminator(f, &block.terminator.kind)?;
+        writeln!(f)?;
+    }
+    writeln!(f, "}}")
+}
+
+fn write_instruction(f: &mut fmt::Formatter<'_>, module: &IrModule, kind: &InstructionKind) -> fmt::Result {
+    let list = |temps: &[Temp]| {
+        temps.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ")
+    };
+    match kind {
+        InstructionKind::Const(constant) => write!(f, "const {}", constant),
+        InstructionKind::Param(index) => write!(f, "param {}", index),
+        InstructionKind::Call { function, args } => {
+            let name = module
+                .functions
+                .get(*function)
+                .map_or("<unknown>", |function| function.name.as_str());
+            write!(f, "call {}({})", name, list(args))
+        }
+        InstructionKind::List(items) => write!(f, "list [{}]", list(items)),
+        InstructionKind::IsNull(value) => write!(f, "is_null {}", value),
+        InstructionKind::Binary { op, left, right } => write!(f, "{} {}, {}", op, left, right),
+        InstructionKind::Unary { op, operand } => write!(f, "{} {}", op, operand),
+        InstructionKind::Concat(parts) => write!(f, "concat [{}]", list(parts)),
+        InstructionKind::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => {
+            let op = if *inclusive { "..=" } else { ".." };
+            write!(f, "range {}{}{} step {}", start, op, end, step)
+        }
+        InstructionKind::Slice { target, range } => write!(f, "slice {}[{}]", target, range),
+        InstructionKind::IterStart(value) => write!(f, "iter {}", value),
+        InstructionKind::Print(value) => write!(f, "print {}", value),
+        InstructionKind::Assert { condition, message } => match message {
+            Some(message) => write!(f, "assert {}, {}", condition, message),
+            None => write!(f, "assert {}", condition),
+        },
+        InstructionKind::AssertEq { left, right } => write!(f, "assert_eq {}, {}", left, right),
+    }
+}
+
+fn write_terminator(f: &mut fmt::Formatter<'_>, kind: &TerminatorKind) -> fmt::Result {
+    match kind {
+        TerminatorKind::Jump(target) => write!(f, "jump {}", target),
+        TerminatorKind::Branch {
+            condition,
+            then_block,
+            else_block,
+        } => write!(f, "branch {}, {}, {}", condition, then_block, else_block),
+        TerminatorKind::Next {
+            iterator,
+            item,
+            body,
+            exit,
+        } => write!(f, "{} = next {}, {}, {}", item, iterator, body, exit),
+        TerminatorKind::Return(value) => write!(f, "return {}", value),
+        TerminatorKind::Halt => write!(f, "halt"),
     }
 }
@@ -0,0 +1,150 @@
+// artificial-language/artificial-core/src/json.rs
+use std::fmt;
+
+/// A JSON value, written compactly on one line by `Display`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys keep their insertion order
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Build an object from `(key, value)` pairs
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as i64)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codes;
+    use crate::diagnostic::{Diagnostic, DiagnosticRenderer};
+    use crate::span::{FileId, Span};
+
+    fn string(s: &str) -> String {
+        Json::from(s).to_string()
+    }
+
+    #[test]
+    fn escapes_quotes_and_backslashes() {
+        assert_eq!(string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(string(r"C:\dir\"), r#""C:\\dir\\""#);
+        assert_eq!(string(r#"\""#), r#""\\\"""#);
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!(string("a\nb\rc\td"), r#""a\nb\rc\td""#);
+        assert_eq!(string("\u{0}\u{1}\u{8}\u{c}\u{1b}\u{1f}"), r#""\u0000\u0001\u0008\u000c\u001b\u001f""#);
+        // Only characters below 0x20 need escaping
+        assert_eq!(string(" \u{7f}"), "\" \u{7f}\"");
+    }
+
+    #[test]
+    fn keeps_non_ascii_text() {
+        assert_eq!(string("héllo wörld"), "\"héllo wörld\"");
+        assert_eq!(string("変数 `名前` 🦀"), "\"変数 `名前` 🦀\"");
+    }
+
+    #[test]
+    fn escapes_keys_like_values() {
+        let object = Json::object([("a\"b", Json::Null), ("ключ", Json::Bool(true))]);
+        assert_eq!(object.to_string(), r#"{"a\"b":null,"ключ":true}"#);
+    }
+
+    #[test]
+    fn diagnostic_messages_are_escaped() {
+        let source = "accrete \"ünïcode\\t\"";
+        let renderer = DiagnosticRenderer::new("dir\\\"quoted\".art", source, false);
+        let span = Span::new(FileId(0), 8, source.len(), 1, 9);
+        let diagnostic = Diagnostic::error(codes::TYPE_MISMATCH, "Cannot slice \"ünïcode\\t\"\n\u{1}".to_string())
+            .with_span(span);
+        let json = renderer.render_json(&diagnostic);
+        assert!(json.starts_with(r#"{"file":"dir\\\"quoted\".art","#), "{}", json);
+        assert!(json.contains(r#""message":"Cannot slice \"ünïcode\\t\"\n\u0001","#), "{}", json);
+        assert!(!json.contains('\n'));
+    }
+}
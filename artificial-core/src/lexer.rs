@@ -1,117 +1,305 @@
 // artificial-language/artificial-core/src/lexer.rs
+use crate::codes::{self, ErrorCode};
+use crate::diagnostic::Diagnostic;
+use crate::span::{FileId, Span};
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
+    /// For identifiers, the macro expansion whose bindings the name refers
+    /// to. Names a macro template binds get the expansion that wrote them;
+    /// other names keep the context they were written in.
+    pub context: Option<usize>,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Token {
+            kind,
+            span,
+            context: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     Accrete,
+    Assert,
+    AssertEq,
+    Macro,
+    Fn,
+    Return,
+    Let,
+    Mut,
+    If,
+    Else,
+    Match,
+    For,
+    In,
+    Step,
+    True,
+    False,
+    Null,
+    Underscore,
+    Identifier(String),
     StringLiteral(String),
+    /// A string literal containing `${expression}` parts
+    InterpolatedString(Vec<InterpolationPart>),
+    /// The digits of an integer. 9223372036854775808 is allowed so that
+    /// `-9223372036854775808` can be written; the parser rejects it
+    /// anywhere else.
+    IntegerLiteral(u64),
     Semicolon,
+    Comma,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    FatArrow,
+    Assign,
+    Colon,
+    QuestionQuestion,
+    DotDot,
+    DotDotDot,
+    DotDotEqual,
+    /// `#`, which starts an attribute such as `#allow(unused)`
+    Hash,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    EqualEqual,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// A malformed literal; the lexer has already reported it
+    Error,
     Eof,
 }
 
+/// A piece of an interpolated string literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpolationPart {
+    Text(String),
+    /// The tokens between `${` and `}`, followed by `Eof`
+    Code(Vec<Token>),
+}
+
 #[derive(Debug)]
 pub struct LexError {
+    pub code: ErrorCode,
     pub message: String,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
 }
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Lex error at {}:{}: {}",
-            self.line, self.column, self.message
+            "Lex error at {}: {}",
+            self.span, self.message
         )
     }
 }
 
+impl LexError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.code, self.message.clone()).with_span(self.span)
+    }
+}
+
+/// Every reserved word, for suggesting corrections of misspelled ones
+pub const KEYWORDS: &[&str] = &[
+    "accrete", "assert", "assert_eq", "macro", "fn", "return", "let", "mut", "if", "else",
+    "match", "for", "in", "step", "true", "null", "false",
+];
+
 pub struct Lexer {
     input: Vec<char>,
+    file: FileId,
     position: usize,
+    /// Byte offset of `position` in the source
+    offset: usize,
     line: usize,
     column: usize,
 }
 
 impl Lexer {
     pub fn new(source: &str) -> Self {
+        Self::with_file(source, FileId::default())
+    }
+
+    /// Create a lexer whose spans point into `file`
+    pub fn with_file(source: &str, file: FileId) -> Self {
         Lexer {
             input: source.chars().collect(),
+            file,
             position: 0,
+            offset: 0,
             line: 1,
             column: 1,
         }
     }
 
-    pub fn tokenize(mut self) -> Result<Vec<Token>, LexError> {
+    /// Split the source into tokens. Unexpected characters are skipped and
+    /// malformed literals become `Error` tokens, so lexing always reaches
+    /// the end of the input; every problem is returned alongside.
+    pub fn tokenize(mut self) -> (Vec<Token>, Vec<LexError>) {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
 
         loop {
             self.skip_whitespace_and_comments();
 
             if self.is_at_end() {
-                tokens.push(Token {
-                    kind: TokenKind::Eof,
-                    line: self.line,
-                    column: self.column,
-                });
+                tokens.push(Token::new(TokenKind::Eof, self.here()));
                 break;
             }
+            if let Some(token) = self.next_token(&mut errors) {
+                tokens.push(token);
+            }
+        }
 
-            let ch = self.current_char();
+        (tokens, errors)
+    }
 
-            if ch.is_alphabetic() {
-                let start_col = self.column;
-                let word = self.read_identifier();
-
-                match word.as_str() {
-                    "accrete" => {
-                        tokens.push(Token {
-                            kind: TokenKind::Accrete,
-                            line: self.line,
-                            column: start_col,
-                        });
-                    }
-                    _ => {
-                        return Err(LexError {
-                            message: format!("Unknown identifier: {}", word),
-                            line: self.line,
-                            column: start_col,
-                        });
-                    }
+    /// Lex the token at the current position, which is not whitespace or
+    /// the end of input. Returns `None` for a character that cannot start a
+    /// token, after reporting it.
+    fn next_token(&mut self, errors: &mut Vec<LexError>) -> Option<Token> {
+        let ch = self.current_char();
+        let start = self.here();
+
+        if ch.is_alphabetic() || ch == '_' {
+            let word = self.read_identifier();
+
+            let kind = match word.as_str() {
+                "accrete" => TokenKind::Accrete,
+                "assert" => TokenKind::Assert,
+                "assert_eq" => TokenKind::AssertEq,
+                "macro" => TokenKind::Macro,
+                "fn" => TokenKind::Fn,
+                "return" => TokenKind::Return,
+                "let" => TokenKind::Let,
+                "mut" => TokenKind::Mut,
+                "if" => TokenKind::If,
+                "else" => TokenKind::Else,
+                "match" => TokenKind::Match,
+                "for" => TokenKind::For,
+                "in" => TokenKind::In,
+                "step" => TokenKind::Step,
+                "_" => TokenKind::Underscore,
+                "true" => TokenKind::True,
+                "null" => TokenKind::Null,
+                "false" => TokenKind::False,
+                _ => TokenKind::Identifier(word),
+            };
+            Some(Token::new(kind, self.span_from(start)))
+        } else if ch.is_ascii_digit() {
+            let kind = match self.read_integer_literal(start) {
+                Ok(value) => TokenKind::IntegerLiteral(value),
+                Err(error) => {
+                    errors.push(error);
+                    TokenKind::Error
+                }
+            };
+            Some(Token::new(kind, self.span_from(start)))
+        } else if ch == '"' {
+            let kind = match self.read_string_literal(start, errors) {
+                Ok(kind) => kind,
+                Err(error) => {
+                    errors.push(error);
+                    TokenKind::Error
                 }
-            } else if ch == '"' {
-                let start_col = self.column;
-                let string_literal = self.read_string_literal()?;
-                tokens.push(Token {
-                    kind: TokenKind::StringLiteral(string_literal),
-                    line: self.line,
-                    column: start_col,
-                });
-            } else if ch == ';' {
-                tokens.push(Token {
-                    kind: TokenKind::Semicolon,
-                    line: self.line,
-                    column: self.column,
-                });
+            };
+            Some(Token::new(kind, self.span_from(start)))
+        } else if let Some(kind) = Self::two_char_operator(ch, self.peek_char()) {
+            self.advance();
+            self.advance();
+            Some(Token::new(kind, self.span_from(start)))
+        } else if ch == '.' && self.peek_char() == '.' {
+            self.advance();
+            self.advance();
+            let kind = if self.current_char() == '=' {
                 self.advance();
+                TokenKind::DotDotEqual
+            } else if self.current_char() == '.' {
+                self.advance();
+                TokenKind::DotDotDot
             } else {
-                return Err(LexError {
-                    message: format!("Unexpected character: '{}'", ch),
-                    line: self.line,
-                    column: self.column,
-                });
-            }
+                TokenKind::DotDot
+            };
+            Some(Token::new(kind, self.span_from(start)))
+        } else if let Some(kind) = Self::punctuation(ch) {
+            self.advance();
+            Some(Token::new(kind, self.span_from(start)))
+        } else {
+            self.advance();
+            errors.push(LexError {
+                code: codes::UNEXPECTED_CHARACTER,
+                message: format!("Unexpected character: '{}'", ch),
+                span: self.span_from(start),
+            });
+            None
+        }
+
+    }
+
+    fn two_char_operator(ch: char, next: char) -> Option<TokenKind> {
+        match (ch, next) {
+            ('=', '>') => Some(TokenKind::FatArrow),
+            ('?', '?') => Some(TokenKind::QuestionQuestion),
+            ('=', '=') => Some(TokenKind::EqualEqual),
+            ('!', '=') => Some(TokenKind::BangEqual),
+            ('<', '=') => Some(TokenKind::LessEqual),
+            ('>', '=') => Some(TokenKind::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    fn punctuation(ch: char) -> Option<TokenKind> {
+        match ch {
+            ';' => Some(TokenKind::Semicolon),
+            ',' => Some(TokenKind::Comma),
+            '(' => Some(TokenKind::LeftParen),
+            ')' => Some(TokenKind::RightParen),
+            '{' => Some(TokenKind::LeftBrace),
+            '}' => Some(TokenKind::RightBrace),
+            '=' => Some(TokenKind::Assign),
+            ':' => Some(TokenKind::Colon),
+            '[' => Some(TokenKind::LeftBracket),
+            ']' => Some(TokenKind::RightBracket),
+            '#' => Some(TokenKind::Hash),
+            '+' => Some(TokenKind::Plus),
+            '-' => Some(TokenKind::Minus),
+            '*' => Some(TokenKind::Star),
+            '/' => Some(TokenKind::Slash),
+            '%' => Some(TokenKind::Percent),
+            '<' => Some(TokenKind::Less),
+            '>' => Some(TokenKind::Greater),
+            _ => None,
         }
+    }
+
+    /// An empty span at the current position
+    fn here(&self) -> Span {
+        Span::new(self.file, self.offset, self.offset, self.line, self.column)
+    }
 
-        Ok(tokens)
+    /// The span from `start` to the current position
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
+        }
     }
 
     fn current_char(&self) -> char {
@@ -138,6 +326,7 @@ impl Lexer {
             } else {
                 self.column += 1;
             }
+            self.offset += self.input[self.position].len_utf8();
             self.position += 1;
         }
     }
@@ -171,21 +360,42 @@ impl Lexer {
         ident
     }
 
-    fn read_string_literal(&mut self) -> Result<String, LexError> {
+    fn read_integer_literal(&mut self, start: Span) -> Result<u64, LexError> {
+        let mut digits = String::new();
+        while !self.is_at_end() && self.current_char().is_ascii_digit() {
+            digits.push(self.current_char());
+            self.advance();
+        }
+        let value = digits.parse().ok().filter(|&value| value <= i64::MIN.unsigned_abs());
+        value.ok_or_else(|| LexError {
+            code: codes::INTEGER_OUT_OF_RANGE,
+            message: format!("Integer literal out of range: {}", digits),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Read a string literal. Text of the form `${expression}` is lexed as
+    /// tokens, making the literal an `InterpolatedString`; `\$` is a
+    /// literal `$`.
+    fn read_string_literal(
+        &mut self,
+        start: Span,
+        errors: &mut Vec<LexError>,
+    ) -> Result<TokenKind, LexError> {
+        let unterminated = |lexer: &Self| LexError {
+            code: codes::UNTERMINATED_STRING,
+            message: "Unterminated string literal".to_string(),
+            span: lexer.span_from(start),
+        };
         self.advance(); // Consume opening quote
+        let mut parts = Vec::new();
         let mut string = String::new();
-        let start_line = self.line;
-        let start_col = self.column;
 
         while !self.is_at_end() && self.current_char() != '"' {
             if self.current_char() == '\\' {
                 self.advance();
                 if self.is_at_end() {
-                    return Err(LexError {
-                        message: "Unterminated string literal".to_string(),
-                        line: start_line,
-                        column: start_col,
-                    });
+                    return Err(unterminated(self));
                 }
                 match self.current_char() {
                     'n' => string.push('\n'),
@@ -193,12 +403,19 @@ impl Lexer {
                     'r' => string.push('\r'),
                     '\\' => string.push('\\'),
                     '"' => string.push('"'),
+                    '$' => string.push('$'),
                     _ => {
                         string.push('\\');
                         string.push(self.current_char());
                     }
                 }
                 self.advance();
+            } else if self.current_char() == '$' && self.peek_char() == '{' {
+                self.advance();
+                self.advance();
+                parts.push(InterpolationPart::Text(std::mem::take(&mut string)));
+                let tokens = self.read_interpolation(errors).ok_or_else(|| unterminated(self))?;
+                parts.push(InterpolationPart::Code(tokens));
             } else {
                 string.push(self.current_char());
                 self.advance();
@@ -206,14 +423,40 @@ impl Lexer {
         }
 
         if self.is_at_end() {
-            return Err(LexError {
-                message: "Unterminated string literal".to_string(),
-                line: start_line,
-                column: start_col,
-            });
+            return Err(unterminated(self));
         }
 
         self.advance(); // Consume closing quote
-        Ok(string)
+        if parts.is_empty() {
+            return Ok(TokenKind::StringLiteral(string));
+        }
+        parts.push(InterpolationPart::Text(string));
+        parts.retain(|part| !matches!(part, InterpolationPart::Text(text) if text.is_empty()));
+        Ok(TokenKind::InterpolatedString(parts))
+    }
+
+    /// Lex the tokens of an interpolation up to its closing `}`, which is
+    /// consumed. Returns `None` if the input ends first.
+    fn read_interpolation(&mut self, errors: &mut Vec<LexError>) -> Option<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            self.skip_whitespace_and_comments();
+            if self.is_at_end() {
+                return None;
+            }
+            if self.current_char() == '}' && depth == 0 {
+                tokens.push(Token::new(TokenKind::Eof, self.here()));
+                self.advance();
+                return Some(tokens);
+            }
+            let Some(token) = self.next_token(errors) else { continue };
+            match token.kind {
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
     }
 }
@@ -1,13 +1,33 @@
 // artificial-language/artificial-core/src/lib.rs
 pub mod ast;
+pub mod codes;
+pub mod diagnostic;
 pub mod ir;
+pub mod json;
 pub mod lexer;
+pub mod lints;
+pub mod macros;
+pub mod ops;
 pub mod parser;
+pub mod pass_manager;
+pub mod passes;
 pub mod lowering;
+pub mod resolver;
+pub mod span;
+pub mod suggest;
+pub mod validator;
 
 pub use ast::*;
+pub use diagnostic::*;
 pub use ir::*;
 pub use lexer::*;
+pub use macros::*;
+pub use ops::*;
 pub use parser::*;
+pub use pass_manager::*;
+pub use passes::*;
 pub use lowering::*;
+pub use resolver::*;
+pub use span::*;
+pub use validator::*;
 
@@ -0,0 +1,254 @@
+// artificial-language/artificial-core/src/lints.rs
+use crate::ast::AstAttribute;
+use crate::codes::{self, ErrorCode};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::span::Span;
+use std::fmt;
+
+/// What happens when a lint fires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    /// The level named by an attribute, such as `allow` in `#allow(unused)`
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warn"),
+            Level::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// A named check that reports suspicious but valid code
+#[derive(Debug)]
+pub struct Lint {
+    pub name: &'static str,
+    pub code: ErrorCode,
+    pub default: Level,
+    pub description: &'static str,
+}
+
+impl Lint {
+    pub fn warning(&'static self, message: String, span: Span) -> Diagnostic {
+        let mut diagnostic = Diagnostic::warning(self.code, message).with_span(span);
+        diagnostic.lint = Some(self.name);
+        diagnostic
+    }
+}
+
+pub static UNUSED_VARIABLES: Lint = Lint {
+    name: "unused_variables",
+    code: codes::UNUSED_VARIABLES,
+    default: Level::Warn,
+    description: "a variable, parameter or loop variable is never read",
+};
+
+pub static UNUSED_FUNCTIONS: Lint = Lint {
+    name: "unused_functions",
+    code: codes::UNUSED_FUNCTIONS,
+    default: Level::Warn,
+    description: "a function is never called",
+};
+
+pub static UNREACHABLE_CODE: Lint = Lint {
+    name: "unreachable_code",
+    code: codes::UNREACHABLE_CODE,
+    default: Level::Warn,
+    description: "code follows a `return` or a condition that is always true or false",
+};
+
+pub static SHADOWED_BINDINGS: Lint = Lint {
+    name: "shadowed_bindings",
+    code: codes::SHADOWED_BINDINGS,
+    default: Level::Warn,
+    description: "a binding hides another binding of the same name",
+};
+
+pub static UNUSED_MACROS: Lint = Lint {
+    name: "unused_macros",
+    code: codes::UNUSED_MACROS,
+    default: Level::Warn,
+    description: "a macro is defined but never invoked",
+};
+
+pub static NEEDLESS_SEMICOLONS: Lint = Lint {
+    name: "needless_semicolons",
+    code: codes::NEEDLESS_SEMICOLONS,
+    default: Level::Warn,
+    description: "a semicolon that the statement does not need",
+};
+
+pub static UNKNOWN_LINTS: Lint = Lint {
+    name: "unknown_lints",
+    code: codes::UNKNOWN_LINTS,
+    default: Level::Warn,
+    description: "a lint attribute names a lint that does not exist",
+};
+
+pub static LINTS: &[&Lint] = &[
+    &UNUSED_VARIABLES,
+    &UNUSED_FUNCTIONS,
+    &UNREACHABLE_CODE,
+    &SHADOWED_BINDINGS,
+    &UNUSED_MACROS,
+    &NEEDLESS_SEMICOLONS,
+    &UNKNOWN_LINTS,
+];
+
+/// Names that stand for several lints at once
+const GROUPS: &[(&str, &[&str])] = &[(
+    "unused",
+    &["unused_variables", "unused_functions", "unused_macros"],
+)];
+
+/// Whether `name`, a lint or a group, covers `lint`. `warnings` covers all.
+fn covers(name: &str, lint: &Lint) -> bool {
+    name == lint.name
+        || name == "warnings"
+        || GROUPS
+            .iter()
+            .any(|(group, members)| *group == name && members.contains(&lint.name))
+}
+
+/// Whether `name` is a lint, a group or `warnings`
+pub fn is_known(name: &str) -> bool {
+    name == "warnings"
+        || LINTS.iter().any(|lint| lint.name == name)
+        || GROUPS.iter().any(|(group, _)| *group == name)
+}
+
+/// Where the level of a particular warning came from
+enum LevelSource {
+    Default,
+    CommandLine(String),
+    Attribute(Span),
+    DenyWarnings,
+}
+
+/// Lint levels from the command line and from `#allow`, `#warn` and
+/// `#deny` attributes. Attributes override the command line, and inner
+/// attributes override outer ones.
+#[derive(Debug, Default)]
+pub struct LintLevels {
+    command_line: Vec<(String, Level)>,
+    attributes: Vec<AstAttribute>,
+    deny_warnings: bool,
+    /// Lints whose first warning already said they are on by default
+    noted: Vec<&'static str>,
+}
+
+impl LintLevels {
+    pub fn new() -> Self {
+        LintLevels::default()
+    }
+
+    /// Set the level of a lint or group; later settings win
+    pub fn set(&mut self, name: &str, level: Level) {
+        self.command_line.push((name.to_string(), level));
+    }
+
+    /// Turn every warning into an error, as CI wants
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings = true;
+    }
+
+    /// Take the lint attributes of a module, reporting names that are not
+    /// lints
+    pub fn add_attributes(&mut self, attributes: &[AstAttribute]) -> Vec<Diagnostic> {
+        let mut warnings = Vec::new();
+        for attribute in attributes {
+            for (name, span) in &attribute.lints {
+                if !is_known(name) {
+                    warnings.push(UNKNOWN_LINTS.warning(format!("Unknown lint `{}`", name), *span));
+                }
+            }
+            self.attributes.push(attribute.clone());
+        }
+        // Outer attributes first, so inner ones are applied last
+        self.attributes
+            .sort_by_key(|attribute| std::cmp::Reverse(attribute.target.end - attribute.target.start));
+        warnings
+    }
+
+    /// Drop allowed warnings and turn denied ones into errors. Only the
+    /// first warning of each lint says it is on by default, also across
+    /// calls, since later passes report warnings of their own.
+    pub fn apply(&mut self, warnings: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        warnings
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let lint = LINTS.iter().find(|lint| Some(lint.name) == diagnostic.lint)?;
+                let span = diagnostic.span.unwrap_or_default();
+                let (level, source) = self.level_of(lint, span);
+                let mut diagnostic = match level {
+                    Level::Allow => return None,
+                    Level::Warn => diagnostic,
+                    Level::Deny => Diagnostic {
+                        severity: Severity::Error,
+                        ..diagnostic
+                    },
+                };
+                diagnostic = match source {
+                    LevelSource::Default if !self.noted.contains(&lint.name) => {
+                        self.noted.push(lint.name);
+                        diagnostic.with_note(format!("`#warn({})` is on by default", lint.name))
+                    }
+                    LevelSource::Default => diagnostic,
+                    LevelSource::CommandLine(flag) => {
+                        diagnostic.with_note(format!("requested on the command line with `{}`", flag))
+                    }
+                    LevelSource::Attribute(span) => {
+                        diagnostic.with_label(span, "the lint level is defined here".to_string())
+                    }
+                    LevelSource::DenyWarnings => diagnostic
+                        .with_note("warnings are denied by `--deny-warnings`".to_string()),
+                };
+                Some(diagnostic)
+            })
+            .collect()
+    }
+
+    fn level_of(&self, lint: &Lint, span: Span) -> (Level, LevelSource) {
+        let mut level = lint.default;
+        let mut source = LevelSource::Default;
+        for (name, set) in &self.command_line {
+            if covers(name, lint) {
+                level = *set;
+                let flag = match set {
+                    Level::Allow => "-A",
+                    Level::Warn => "-W",
+                    Level::Deny => "-D",
+                };
+                source = LevelSource::CommandLine(format!("{} {}", flag, name));
+            }
+        }
+        for attribute in &self.attributes {
+            let applies = attribute.target.start <= span.start && span.end <= attribute.target.end;
+            if applies && attribute.lints.iter().any(|(name, _)| covers(name, lint)) {
+                level = attribute.level;
+                source = LevelSource::Attribute(attribute.span);
+            }
+        }
+        if level == Level::Warn && self.deny_warnings {
+            (Level::Deny, LevelSource::DenyWarnings)
+        } else {
+            (level, source)
+        }
+    }
+}
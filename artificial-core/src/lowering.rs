@@ -1,25 +1,599 @@
 // artificial-language/artificial-core/src/lowering.rs
 use crate::ast::*;
 use crate::ir::*;
+use crate::ops::BinaryOp;
+use crate::resolver::{bind_arguments, ParamSource};
+use crate::span::Span;
+use std::collections::HashMap;
 
-pub fn lower_module(ast: AstModule) -> IrModule {
-    let statements = ast
-        .statements
+/// Settings that change the IR lowering produces
+#[derive(Debug, Clone, Default)]
+pub struct LoweringOptions {
+    /// Leave out `assert` and `assert_eq` statements, operands included
+    pub strip_asserts: bool,
+}
+
+/// Lower a module that has already passed the `Resolver`
+pub fn lower_module(ast: AstModule, options: &LoweringOptions) -> IrModule {
+    let signatures: HashMap<String, Signature> = ast
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| {
+            let signature = Signature {
+                index,
+                params: function.params.clone(),
+            };
+            (function.name.clone(), signature)
+        })
+        .collect();
+
+    let functions = ast
+        .functions
         .into_iter()
-        .map(lower_statement)
+        .map(|function| {
+            let ir = IrFunction::new(function.name, function.params.len(), function.span);
+            let mut lowering = Lowering::new(ir, &signatures, options);
+            for (index, param) in function.params.iter().enumerate() {
+                let value = lowering.emit(InstructionKind::Param(index as u16), param.span);
+                let variable = lowering.declare(param.name.clone(), param.name_span);
+                lowering.write(variable, value);
+            }
+            let body_span = function.body.span;
+            let value = lowering.lower_block(function.body);
+            lowering.terminate(TerminatorKind::Return(value), body_span);
+            lowering.finish()
+        })
         .collect();
 
-    IrModule { statements }
-}
+    let main = IrFunction::new("main".to_string(), 0, ast.span);
+    let mut lowering = Lowering::new(main, &signatures, options);
+    for stmt in ast.statements {
+        lowering.lower_statement(stmt);
+    }
+    lowering.terminate(TerminatorKind::Halt, ast.span);
 
-fn lower_statement(stmt: AstStatement) -> IrStatement {
-    match stmt {
-        AstStatement::Accrete(expr) => IrStatement::Accrete(lower_expression(expr)),
+    IrModule {
+        functions,
+        main: lowering.finish(),
+        span: ast.span,
     }
 }
 
-fn lower_expression(expr: AstExpression) -> IrExpression {
-    match expr {
-        AstExpression::StringLiteral(s) => IrExpression::StringLiteral(s),
+/// What a call site needs to know about the function it calls
+struct Signature {
+    index: usize,
+    params: Vec<AstParam>,
+}
+
+/// A source variable, or a value lowering merges across branches. Reads
+/// and writes of variables become SSA temporaries and phis.
+type Variable = usize;
+
+/// Builds the blocks of one function, constructing SSA form on the fly as
+/// in Braun et al., "Simple and Efficient Construction of Static Single
+/// Assignment Form"
+struct Lowering<'a> {
+    function: IrFunction,
+    /// The block instructions are added to
+    current: BlockId,
+    predecessors: Vec<Vec<BlockId>>,
+    /// Blocks whose predecessors are all known
+    sealed: Vec<bool>,
+    /// Phis of unsealed blocks, given operands when the block is sealed
+    incomplete: Vec<Vec<(Variable, Temp)>>,
+    /// The value of each variable at the end of each block so far
+    definitions: HashMap<(Variable, BlockId), Temp>,
+    /// Where each variable is declared, for the phis that merge it
+    variables: Vec<Span>,
+    scopes: Vec<Vec<(Identifier, Variable)>>,
+    signatures: &'a HashMap<String, Signature>,
+    options: &'a LoweringOptions,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(
+        function: IrFunction,
+        signatures: &'a HashMap<String, Signature>,
+        options: &'a LoweringOptions,
+    ) -> Self {
+        let mut lowering = Lowering {
+            function,
+            current: BlockId::ENTRY,
+            predecessors: Vec::new(),
+            sealed: Vec::new(),
+            incomplete: Vec::new(),
+            definitions: HashMap::new(),
+            variables: Vec::new(),
+            scopes: vec![Vec::new()],
+            signatures,
+            options,
+        };
+        let entry = lowering.new_block();
+        lowering.seal(entry);
+        lowering
+    }
+
+    /// Remove phis that merge a single value and put the blocks in reverse
+    /// postorder, followed by any unreachable ones
+    fn finish(mut self) -> IrFunction {
+        debug_assert!(self.sealed.iter().all(|&sealed| sealed), "unsealed block");
+        self.function.remove_trivial_phis();
+        let mut order = self.function.reverse_postorder();
+        let mut reachable = vec![false; self.function.blocks.len()];
+        for block in &order {
+            reachable[block.index()] = true;
+        }
+        order.extend(self.function.block_ids().filter(|block| !reachable[block.index()]));
+        self.function.reorder_blocks(&order);
+        self.function
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        let id = BlockId(self.function.blocks.len() as u32);
+        self.function.blocks.push(BasicBlock::default());
+        self.predecessors.push(Vec::new());
+        self.sealed.push(false);
+        self.incomplete.push(Vec::new());
+        id
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    /// Record that no more predecessors of `block` will be added
+    fn seal(&mut self, block: BlockId) {
+        for (variable, phi) in std::mem::take(&mut self.incomplete[block.index()]) {
+            self.add_phi_operands(variable, phi, block);
+        }
+        self.sealed[block.index()] = true;
+    }
+
+    /// Add an instruction that defines a value to the current block
+    fn emit(&mut self, kind: InstructionKind, span: Span) -> Temp {
+        let dest = self.function.new_temp();
+        let instruction = Instruction::new(Some(dest), kind, span);
+        self.function.block_mut(self.current).instructions.push(instruction);
+        dest
+    }
+
+    /// Add an instruction that is only run for its effect
+    fn emit_effect(&mut self, kind: InstructionKind, span: Span) {
+        let instruction = Instruction::new(None, kind, span);
+        self.function.block_mut(self.current).instructions.push(instruction);
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> Temp {
+        self.emit(InstructionKind::Const(constant), span)
+    }
+
+    /// End the current block
+    fn terminate(&mut self, kind: TerminatorKind, span: Span) {
+        for successor in kind.successors() {
+            self.predecessors[successor.index()].push(self.current);
+        }
+        self.function.block_mut(self.current).terminator = Terminator::new(kind, span);
+    }
+
+    /// Continue in a block nothing jumps to, for code after a `return`
+    fn start_unreachable_block(&mut self) {
+        let block = self.new_block();
+        self.seal(block);
+        self.switch_to(block);
+    }
+
+    fn declare(&mut self, name: Identifier, span: Span) -> Variable {
+        let variable = self.fresh_variable(span);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name, variable));
+        }
+        variable
+    }
+
+    /// A variable no name refers to, for values lowering merges itself
+    fn fresh_variable(&mut self, span: Span) -> Variable {
+        self.variables.push(span);
+        self.variables.len() - 1
+    }
+
+    fn variable_of(&self, name: &Identifier) -> Variable {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(bound, _)| bound == name)
+            .map(|(_, variable)| *variable)
+            .unwrap_or_else(|| panic!("unresolved variable `{}` reached lowering", name))
+    }
+
+    fn write(&mut self, variable: Variable, value: Temp) {
+        self.definitions.insert((variable, self.current), value);
+    }
+
+    fn read(&mut self, variable: Variable) -> Temp {
+        self.read_in(variable, self.current)
+    }
+
+    fn read_in(&mut self, variable: Variable, block: BlockId) -> Temp {
+        if let Some(&value) = self.definitions.get(&(variable, block)) {
+            return value;
+        }
+        let predecessors = &self.predecessors[block.index()];
+        let value = if !self.sealed[block.index()] {
+            // More predecessors may follow; complete the phi when sealing
+            let phi = self.new_phi(variable, block);
+            self.incomplete[block.index()].push((variable, phi));
+            phi
+        } else if predecessors.len() == 1 {
+            let predecessor = predecessors[0];
+            self.read_in(variable, predecessor)
+        } else if predecessors.is_empty() {
+            // Only unreachable blocks can read a variable nothing wrote
+            let span = self.variables[variable];
+            let dest = self.function.new_temp();
+            let null = Instruction::new(Some(dest), InstructionKind::Const(Constant::Null), span);
+            self.function.block_mut(block).instructions.insert(0, null);
+            dest
+        } else {
+            // Define the phi first so reads through loops find it
+            let phi = self.new_phi(variable, block);
+            self.definitions.insert((variable, block), phi);
+            self.add_phi_operands(variable, phi, block);
+            phi
+        };
+        self.definitions.insert((variable, block), value);
+        value
+    }
+
+    fn new_phi(&mut self, variable: Variable, block: BlockId) -> Temp {
+        let dest = self.function.new_temp();
+        let phi = Phi {
+            dest,
+            incoming: Vec::new(),
+            span: self.variables[variable],
+        };
+        self.function.block_mut(block).phis.push(phi);
+        dest
+    }
+
+    fn add_phi_operands(&mut self, variable: Variable, phi: Temp, block: BlockId) {
+        for predecessor in self.predecessors[block.index()].clone() {
+            let value = self.read_in(variable, predecessor);
+            let phis = &mut self.function.block_mut(block).phis;
+            if let Some(phi) = phis.iter_mut().find(|p| p.dest == phi) {
+                phi.incoming.push((predecessor, value));
+            }
+        }
+    }
+
+    /// Branch on `condition` and merge the values the two arms produce
+    fn lower_branches(
+        &mut self,
+        condition: Temp,
+        span: Span,
+        if_true: impl FnOnce(&mut Self) -> Temp,
+        if_false: impl FnOnce(&mut Self) -> Temp,
+    ) -> Temp {
+        let then_block = self.new_block();
+        let else_block = self.new_block();
+        let join = self.new_block();
+        let branch = TerminatorKind::Branch {
+            condition,
+            then_block,
+            else_block,
+        };
+        self.terminate(branch, span);
+        self.seal(then_block);
+        self.seal(else_block);
+
+        let result = self.fresh_variable(span);
+        self.switch_to(then_block);
+        let value = if_true(self);
+        self.write(result, value);
+        self.terminate(TerminatorKind::Jump(join), span);
+
+        self.switch_to(else_block);
+        let value = if_false(self);
+        self.write(result, value);
+        self.terminate(TerminatorKind::Jump(join), span);
+
+        self.seal(join);
+        self.switch_to(join);
+        self.read(result)
+    }
+
+    fn lower_statement(&mut self, stmt: AstStatement) {
+        let span = stmt.span;
+        match stmt.kind {
+            AstStatementKind::Accrete(expr) => {
+                let value = self.lower_expression(expr);
+                self.emit_effect(InstructionKind::Print(value), span);
+            }
+            AstStatementKind::Assert { .. } | AstStatementKind::AssertEq { .. }
+                if self.options.strip_asserts => {}
+            AstStatementKind::Assert { condition, message } => {
+                let condition = self.lower_expression(condition);
+                let message = message.map(|m| self.lower_expression(m));
+                self.emit_effect(InstructionKind::Assert { condition, message }, span);
+            }
+            AstStatementKind::AssertEq { left, right } => {
+                let left = self.lower_expression(left);
+                let right = self.lower_expression(right);
+                self.emit_effect(InstructionKind::AssertEq { left, right }, span);
+            }
+            AstStatementKind::Let {
+                name,
+                name_span,
+                value,
+                ..
+            } => {
+                // The value is lowered before the name is in scope
+                let value = self.lower_expression(value);
+                let variable = self.declare(name, name_span);
+                self.write(variable, value);
+            }
+            AstStatementKind::Assign { name, value } => {
+                let value = self.lower_expression(value);
+                let variable = self.variable_of(&name);
+                self.write(variable, value);
+            }
+            AstStatementKind::Return { value } => {
+                let value = match value {
+                    Some(value) => self.lower_expression(value),
+                    None => self.constant(Constant::Null, span),
+                };
+                self.terminate(TerminatorKind::Return(value), span);
+                self.start_unreachable_block();
+            }
+            AstStatementKind::Expression(expr) => {
+                self.lower_expression(expr);
+            }
+            AstStatementKind::Error => panic!("parse error reached lowering"),
+        }
+    }
+
+    /// Arguments are evaluated in source order, then defaults
+    fn lower_call(&mut self, callee: String, args: Vec<AstArgument>, span: Span) -> Temp {
+        let signature = &self.signatures[&callee];
+        let (function, params) = (signature.index, signature.params.clone());
+        let sources = bind_arguments(&callee, &params, &args, span)
+            .expect("call arguments were checked by the resolver");
+
+        let spans: Vec<Span> = args.iter().map(|arg| arg.span).collect();
+        let values: Vec<Temp> = args
+            .into_iter()
+            .map(|arg| self.lower_expression(arg.value))
+            .collect();
+
+        let args = params
+            .into_iter()
+            .zip(sources)
+            .map(|(param, source)| match source {
+                ParamSource::Argument(index) => values[index],
+                ParamSource::Rest(indices) => {
+                    let list_span = match (indices.first(), indices.last()) {
+                        (Some(&first), Some(&last)) => spans[first].to(spans[last]),
+                        _ => span,
+                    };
+                    let items = indices.into_iter().map(|index| values[index]).collect();
+                    self.emit(InstructionKind::List(items), list_span)
+                }
+                ParamSource::Default => self.lower_expression(param.default.unwrap()),
+            })
+            .collect();
+
+        self.emit(InstructionKind::Call { function, args }, span)
+    }
+
+    fn lower_expression(&mut self, expr: AstExpression) -> Temp {
+        let span = expr.span;
+        match expr.kind {
+            AstExpressionKind::StringLiteral(s) => self.constant(Constant::String(s), span),
+            AstExpressionKind::IntegerLiteral(n) => self.constant(Constant::Integer(n), span),
+            AstExpressionKind::BooleanLiteral(b) => self.constant(Constant::Boolean(b), span),
+            AstExpressionKind::NullLiteral => self.constant(Constant::Null, span),
+            AstExpressionKind::Error => panic!("parse error reached lowering"),
+            AstExpressionKind::Variable(name) => {
+                let variable = self.variable_of(&name);
+                self.read(variable)
+            }
+            AstExpressionKind::Call { callee, args } => self.lower_call(callee, args, span),
+            AstExpressionKind::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let start = self.lower_expression(*start);
+                let end = self.lower_expression(*end);
+                let step = match step {
+                    Some(step) => self.lower_expression(*step),
+                    None => self.constant(Constant::Integer(1), span),
+                };
+                let range = InstructionKind::Range {
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                };
+                self.emit(range, span)
+            }
+            AstExpressionKind::Slice { target, range } => {
+                let target = self.lower_expression(*target);
+                let range = self.lower_expression(*range);
+                self.emit(InstructionKind::Slice { target, range }, span)
+            }
+            AstExpressionKind::Interpolation(parts) => {
+                let parts = parts.into_iter().map(|part| self.lower_expression(part)).collect();
+                self.emit(InstructionKind::Concat(parts), span)
+            }
+            AstExpressionKind::Binary { op, left, right } => {
+                let left = self.lower_expression(*left);
+                let right = self.lower_expression(*right);
+                self.emit(InstructionKind::Binary { op, left, right }, span)
+            }
+            AstExpressionKind::Unary { op, operand } => {
+                let operand = self.lower_expression(*operand);
+                self.emit(InstructionKind::Unary { op, operand }, span)
+            }
+            AstExpressionKind::Coalesce { value, fallback } => {
+                let value = self.lower_expression(*value);
+                let is_null = self.emit(InstructionKind::IsNull(value), span);
+                self.lower_branches(
+                    is_null,
+                    span,
+                    |lowering| lowering.lower_expression(*fallback),
+                    |_| value,
+                )
+            }
+            AstExpressionKind::Block(block) => self.lower_block(block),
+            AstExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.lower_expression(*condition);
+                self.lower_branches(
+                    condition,
+                    span,
+                    |lowering| lowering.lower_block(then_branch),
+                    |lowering| lowering.lower_else(else_branch, span),
+                )
+            }
+            AstExpressionKind::IfLet {
+                name,
+                name_span,
+                value,
+                then_branch,
+                else_branch,
+            } => {
+                let value = self.lower_expression(*value);
+                let is_null = self.emit(InstructionKind::IsNull(value), span);
+                self.lower_branches(
+                    is_null,
+                    span,
+                    |lowering| lowering.lower_else(else_branch, span),
+                    |lowering| {
+                        lowering.scopes.push(Vec::new());
+                        let variable = lowering.declare(name, name_span);
+                        lowering.write(variable, value);
+                        let value = lowering.lower_block(then_branch);
+                        lowering.scopes.pop();
+                        value
+                    },
+                )
+            }
+            AstExpressionKind::Match { scrutinee, arms } => self.lower_match(*scrutinee, arms, span),
+            AstExpressionKind::For {
+                variable,
+                variable_span,
+                iterable,
+                body,
+            } => {
+                let iterable = self.lower_expression(*iterable);
+                let iterator = self.emit(InstructionKind::IterStart(iterable), span);
+                let header = self.new_block();
+                self.terminate(TerminatorKind::Jump(header), span);
+
+                self.switch_to(header);
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                let item = self.function.new_temp();
+                let next = TerminatorKind::Next {
+                    iterator,
+                    item,
+                    body: body_block,
+                    exit,
+                };
+                self.terminate(next, span);
+                self.seal(body_block);
+                self.seal(exit);
+
+                self.switch_to(body_block);
+                self.scopes.push(Vec::new());
+                let variable = self.declare(variable, variable_span);
+                self.write(variable, item);
+                let body_span = body.span;
+                self.lower_block(body);
+                self.scopes.pop();
+                self.terminate(TerminatorKind::Jump(header), body_span);
+                // The back edge was the last predecessor of the header
+                self.seal(header);
+
+                self.switch_to(exit);
+                self.constant(Constant::Null, span)
+            }
+        }
+    }
+
+    fn lower_else(&mut self, else_branch: Option<Box<AstExpression>>, span: Span) -> Temp {
+        match else_branch {
+            Some(else_branch) => self.lower_expression(*else_branch),
+            None => self.constant(Constant::Null, span),
+        }
+    }
+
+    /// Arms are tried in order; arms after a wildcard can never run
+    fn lower_match(&mut self, scrutinee: AstExpression, arms: Vec<AstMatchArm>, span: Span) -> Temp {
+        let scrutinee = self.lower_expression(scrutinee);
+        let result = self.fresh_variable(span);
+        let join = self.new_block();
+        let mut default = None;
+        for arm in arms {
+            let pattern = match arm.pattern {
+                AstPattern::Literal(pattern) => pattern,
+                AstPattern::Wildcard(_) => {
+                    default = Some(arm.body);
+                    break;
+                }
+            };
+            let pattern_span = pattern.span;
+            let pattern = self.lower_expression(pattern);
+            let equal = InstructionKind::Binary {
+                op: BinaryOp::Equal,
+                left: scrutinee,
+                right: pattern,
+            };
+            let condition = self.emit(equal, pattern_span);
+            let arm_block = self.new_block();
+            let next = self.new_block();
+            let branch = TerminatorKind::Branch {
+                condition,
+                then_block: arm_block,
+                else_block: next,
+            };
+            self.terminate(branch, pattern_span);
+            self.seal(arm_block);
+            self.seal(next);
+
+            self.switch_to(arm_block);
+            let value = self.lower_expression(arm.body);
+            self.write(result, value);
+            self.terminate(TerminatorKind::Jump(join), arm.span);
+            self.switch_to(next);
+        }
+        let value = match default {
+            Some(body) => self.lower_expression(body),
+            None => self.constant(Constant::Null, span),
+        };
+        self.write(result, value);
+        self.terminate(TerminatorKind::Jump(join), span);
+        self.seal(join);
+        self.switch_to(join);
+        self.read(result)
+    }
+
+    fn lower_block(&mut self, block: AstBlock) -> Temp {
+        self.scopes.push(Vec::new());
+        for stmt in block.statements {
+            self.lower_statement(stmt);
+        }
+        let value = match block.value {
+            Some(value) => self.lower_expression(*value),
+            None => self.constant(Constant::Null, block.span),
+        };
+        self.scopes.pop();
+        value
     }
 }
@@ -0,0 +1,608 @@
+// artificial-language/artificial-core/src/macros.rs
+use crate::codes::{self, ErrorCode};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{InterpolationPart, Token, TokenKind};
+use crate::lints;
+use crate::span::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Maximum nesting of macro invocations inside macro templates
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// One invocation of a macro, which the spans of the text it wrote refer to
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroOrigin {
+    /// Name of the expanded macro
+    pub macro_name: String,
+    /// The invocation, from the macro's name to the closing parenthesis.
+    /// When the invocation came from another expansion, its `expansion`
+    /// says which.
+    pub call_span: Span,
+    /// Name of the macro at its definition
+    pub def_span: Span,
+}
+
+/// Every macro expansion of a program, indexed by `Span::expansion`
+#[derive(Debug, Clone, Default)]
+pub struct Expansions {
+    origins: Vec<MacroOrigin>,
+}
+
+impl Expansions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, expansion: usize) -> Option<&MacroOrigin> {
+        self.origins.get(expansion)
+    }
+
+    /// Label the invocation and definition of the expansion that wrote the
+    /// diagnostic's span, and of the expansions that invoked it in turn
+    pub fn label(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let mut expansion = diagnostic.span.and_then(|span| span.expansion);
+        while let Some(origin) = expansion.and_then(|expansion| self.get(expansion)) {
+            diagnostic = diagnostic
+                .with_label(
+                    origin.call_span,
+                    format!("in this expansion of macro `{}`", origin.macro_name),
+                )
+                .with_label(origin.def_span, format!("macro `{}` defined here", origin.macro_name));
+            expansion = origin.call_span.expansion;
+        }
+        diagnostic
+    }
+
+    /// The span from the start of `a` to the end of `b`. When they come
+    /// from different expansions, their offsets are unrelated, so the
+    /// result covers the invocation that holds them both.
+    pub fn join(&self, a: Span, b: Span) -> Span {
+        if a.expansion == b.expansion {
+            return a.to(b);
+        }
+        let outer = self.call_sites(b);
+        for a in self.call_sites(a) {
+            if let Some(&b) = outer.iter().find(|b| b.expansion == a.expansion) {
+                return if b.start < a.start { b.to(a) } else { a.to(b) };
+            }
+        }
+        a.to(b)
+    }
+
+    /// `span`, then the invocation of the expansion that wrote it, and so
+    /// on out to the user's text
+    fn call_sites(&self, span: Span) -> Vec<Span> {
+        let mut spans = vec![span];
+        let mut expansion = span.expansion;
+        while let Some(origin) = expansion.and_then(|expansion| self.get(expansion)) {
+            spans.push(origin.call_span);
+            expansion = origin.call_span.expansion;
+        }
+        spans
+    }
+
+    fn push(&mut self, origin: MacroOrigin) -> usize {
+        self.origins.push(origin);
+        self.origins.len() - 1
+    }
+}
+
+#[derive(Debug)]
+pub struct MacroError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+}
+
+impl MacroError {
+    fn at(token: &Token, code: ErrorCode, message: String) -> Self {
+        MacroError {
+            code,
+            message,
+            span: token.span,
+        }
+    }
+}
+
+impl MacroError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.code, self.message.clone()).with_span(self.span)
+    }
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Macro error at {}: {}", self.span, self.message)
+    }
+}
+
+/// A declarative macro: `macro name(a, b) => { template }`
+#[derive(Debug, Clone)]
+struct MacroDefinition {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Token>,
+    span: Span,
+    /// Whether the definition came from a macro expansion
+    expanded: bool,
+    used: bool,
+}
+
+/// Expands macro definitions and invocations in a token stream
+pub struct MacroExpander {
+    macros: HashMap<String, MacroDefinition>,
+    expansions: Expansions,
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        MacroExpander {
+            macros: HashMap::new(),
+            expansions: Expansions::new(),
+        }
+    }
+
+    /// Remove macro definitions from the stream and replace every
+    /// invocation with its expanded template
+    pub fn expand(&mut self, tokens: Vec<Token>) -> Result<Vec<Token>, MacroError> {
+        self.expand_tokens(&tokens, 0)
+    }
+
+    /// The expansions made so far, which diagnostics need to point back
+    /// at the invocations
+    pub fn expansions(&self) -> &Expansions {
+        &self.expansions
+    }
+
+    /// Warnings for macros the user defined but never invoked
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        let mut unused: Vec<&MacroDefinition> = self
+            .macros
+            .values()
+            .filter(|definition| !definition.used && !definition.expanded)
+            .collect();
+        unused.sort_by_key(|definition| definition.span.start);
+        unused
+            .into_iter()
+            .map(|definition| {
+                lints::UNUSED_MACROS
+                    .warning(format!("Macro `{}` is never used", definition.name), definition.span)
+            })
+            .collect()
+    }
+
+    fn expand_tokens(&mut self, tokens: &[Token], depth: usize) -> Result<Vec<Token>, MacroError> {
+        let mut output = Vec::with_capacity(tokens.len());
+        let mut position = 0;
+
+        while position < tokens.len() {
+            let token = &tokens[position];
+            match &token.kind {
+                TokenKind::Macro => {
+                    position = self.read_definition(tokens, position)?;
+                }
+                // Only defined macros expand; other `name(` tokens are function calls
+                TokenKind::Identifier(name)
+                    if self.macros.contains_key(name)
+                        && matches!(
+                            tokens.get(position + 1).map(|t| &t.kind),
+                            Some(TokenKind::LeftParen)
+                        ) =>
+                {
+                    if depth >= MAX_EXPANSION_DEPTH {
+                        return Err(MacroError::at(
+                            token,
+                            codes::MACRO_NESTING_LIMIT,
+                            format!(
+                                "Macro expansion of `{}` exceeded the nesting limit of {}",
+                                name, MAX_EXPANSION_DEPTH
+                            ),
+                        ));
+                    }
+                    let definition = self.macros.get_mut(name).unwrap();
+                    definition.used = true;
+                    let definition = definition.clone();
+                    let (arguments, next) = read_arguments(tokens, position + 1)?;
+                    let call_span = token.span.to(tokens[next - 1].span);
+                    let expanded = self.instantiate(&definition, arguments, token, call_span)?;
+                    output.extend(self.expand_tokens(&expanded, depth + 1)?);
+                    position = next;
+                }
+                TokenKind::InterpolatedString(parts) => {
+                    let mut token = token.clone();
+                    let mut expanded = Vec::with_capacity(parts.len());
+                    for part in parts {
+                        expanded.push(match part {
+                            InterpolationPart::Code(tokens) => {
+                                InterpolationPart::Code(self.expand_tokens(tokens, depth)?)
+                            }
+                            text => text.clone(),
+                        });
+                    }
+                    token.kind = TokenKind::InterpolatedString(expanded);
+                    output.push(token);
+                    position += 1;
+                }
+                _ => {
+                    output.push(token.clone());
+                    position += 1;
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Parse `macro name(params) => { body }` starting at the `macro`
+    /// keyword; returns the position after the closing brace
+    fn read_definition(&mut self, tokens: &[Token], start: usize) -> Result<usize, MacroError> {
+        let mut position = start + 1;
+
+        let name_token = token_at(tokens, position);
+        let name = match &name_token.kind {
+            TokenKind::Identifier(name) => name.clone(),
+            other => {
+                return Err(MacroError::at(
+                    name_token,
+                    codes::MALFORMED_MACRO_DEFINITION,
+                    format!("Expected macro name, found {:?}", other),
+                ))
+            }
+        };
+        position += 1;
+
+        position = expect(tokens, position, &TokenKind::LeftParen)?;
+        let mut params: Vec<String> = Vec::new();
+        if !matches!(token_at(tokens, position).kind, TokenKind::RightParen) {
+            loop {
+                let param_token = token_at(tokens, position);
+                match &param_token.kind {
+                    TokenKind::Identifier(param) => {
+                        if params.contains(param) {
+                            return Err(MacroError::at(
+                                param_token,
+                                codes::DUPLICATE_MACRO_PARAMETER,
+                                format!("Duplicate macro parameter `{}`", param),
+                            ));
+                        }
+                        params.push(param.clone());
+                    }
+                    other => {
+                        return Err(MacroError::at(
+                            param_token,
+                            codes::MALFORMED_MACRO_DEFINITION,
+                            format!("Expected parameter name, found {:?}", other),
+                        ))
+                    }
+                }
+                position += 1;
+                if matches!(token_at(tokens, position).kind, TokenKind::Comma) {
+                    position += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        position = expect(tokens, position, &TokenKind::RightParen)?;
+        position = expect(tokens, position, &TokenKind::FatArrow)?;
+        let body_start = expect(tokens, position, &TokenKind::LeftBrace)?;
+
+        let mut depth = 1;
+        position = body_start;
+        loop {
+            let token = token_at(tokens, position);
+            match token.kind {
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                TokenKind::Eof => {
+                    return Err(MacroError::at(
+                        &tokens[start],
+                        codes::MALFORMED_MACRO_DEFINITION,
+                        format!("Unterminated body of macro `{}`", name),
+                    ))
+                }
+                _ => {}
+            }
+            position += 1;
+        }
+
+        let definition = MacroDefinition {
+            name: name.clone(),
+            params,
+            body: tokens[body_start..position].to_vec(),
+            span: name_token.span,
+            expanded: name_token.span.expansion.is_some(),
+            used: false,
+        };
+        self.macros.insert(name, definition);
+
+        Ok(position + 1)
+    }
+
+    /// Substitute arguments into a copy of the template
+    fn instantiate(
+        &mut self,
+        definition: &MacroDefinition,
+        arguments: Vec<Vec<Token>>,
+        call: &Token,
+        call_span: Span,
+    ) -> Result<Vec<Token>, MacroError> {
+        if arguments.len() != definition.params.len() {
+            return Err(MacroError::at(
+                call,
+                codes::MACRO_ARGUMENT_COUNT,
+                format!(
+                    "Macro `{}` expects {} argument(s), found {}",
+                    definition.name,
+                    definition.params.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+
+        let expansion = self.expansions.push(MacroOrigin {
+            macro_name: definition.name.clone(),
+            call_span,
+            def_span: definition.span,
+        });
+
+        let mut bound = Vec::new();
+        bound_names(&definition.body, &definition.params, &mut bound);
+        Ok(substitute(&definition.body, &definition.params, &arguments, &bound, expansion))
+    }
+}
+
+/// Collect the names `body` binds with `let`, `for` and function
+/// parameters, leaving out the macro's own parameters
+fn bound_names(body: &[Token], params: &[String], bound: &mut Vec<String>) {
+    // Depth of parentheses inside a function's parameter list
+    let mut param_depth: Option<usize> = None;
+    for (index, token) in body.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|index| &body[index].kind);
+        match &token.kind {
+            TokenKind::Identifier(name) => {
+                let binds = match previous {
+                    Some(TokenKind::Let | TokenKind::Mut | TokenKind::For) => true,
+                    Some(TokenKind::LeftParen | TokenKind::Comma | TokenKind::DotDotDot) => param_depth == Some(1),
+                    _ => false,
+                };
+                if binds && !params.contains(name) && !bound.contains(name) {
+                    bound.push(name.clone());
+                }
+            }
+            TokenKind::LeftParen => {
+                if param_depth.is_none() && index >= 2 && matches!(body[index - 2].kind, TokenKind::Fn) {
+                    param_depth = Some(0);
+                }
+                param_depth = param_depth.map(|depth| depth + 1);
+            }
+            TokenKind::RightParen => {
+                param_depth = param_depth.map(|depth| depth - 1).filter(|&depth| depth > 0);
+            }
+            TokenKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let InterpolationPart::Code(tokens) = part {
+                        bound_names(tokens, params, bound);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Copy `body`, replacing parameters with their arguments, including in
+/// the interpolations of string literals
+fn substitute(
+    body: &[Token],
+    params: &[String],
+    arguments: &[Vec<Token>],
+    bound: &[String],
+    expansion: usize,
+) -> Vec<Token> {
+    let mut expanded = Vec::with_capacity(body.len());
+    for token in body {
+        let param_index = match &token.kind {
+            TokenKind::Identifier(name) => params.iter().position(|p| p == name),
+            _ => None,
+        };
+        match param_index {
+            // Arguments keep the caller's positions and syntax context
+            Some(index) => expanded.extend(arguments[index].iter().cloned()),
+            None => {
+                let mut token = token.clone();
+                if let TokenKind::InterpolatedString(parts) = &mut token.kind {
+                    for part in parts {
+                        if let InterpolationPart::Code(tokens) = part {
+                            *tokens = substitute(tokens, params, arguments, bound, expansion);
+                        }
+                    }
+                }
+                // Names the template binds belong to this expansion, so
+                // they neither capture nor are captured by the caller's.
+                // Other names keep the context of the definition.
+                if matches!(&token.kind, TokenKind::Identifier(name) if bound.contains(name)) {
+                    token.context = Some(expansion);
+                }
+                token.span.expansion = Some(expansion);
+                expanded.push(token);
+            }
+        }
+    }
+    expanded
+}
+
+impl Default for MacroExpander {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn token_at(tokens: &[Token], position: usize) -> &Token {
+    tokens
+        .get(position)
+        .unwrap_or_else(|| tokens.last().unwrap())
+}
+
+fn expect(tokens: &[Token], position: usize, kind: &TokenKind) -> Result<usize, MacroError> {
+    let token = token_at(tokens, position);
+    if std::mem::discriminant(&token.kind) == std::mem::discriminant(kind) {
+        Ok(position + 1)
+    } else {
+        Err(MacroError::at(
+            token,
+            codes::MALFORMED_MACRO_DEFINITION,
+            format!("Expected {:?}, found {:?}", kind, token.kind),
+        ))
+    }
+}
+
+/// Split the comma-separated arguments of an invocation, starting at its
+/// opening parenthesis; returns the arguments and the position after `)`
+fn read_arguments(
+    tokens: &[Token],
+    open_paren: usize,
+) -> Result<(Vec<Vec<Token>>, usize), MacroError> {
+    let mut arguments = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0;
+    let mut position = open_paren + 1;
+
+    loop {
+        let token = token_at(tokens, position);
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBrace => depth += 1,
+            TokenKind::RightParen if depth == 0 => break,
+            TokenKind::RightParen | TokenKind::RightBrace => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                if current.is_empty() {
+                    return Err(MacroError::at(
+                        token,
+                        codes::MALFORMED_MACRO_INVOCATION,
+                        "Empty macro argument".to_string(),
+                    ));
+                }
+                arguments.push(std::mem::take(&mut current));
+                position += 1;
+                continue;
+            }
+            TokenKind::Eof => {
+                return Err(MacroError::at(
+                    &tokens[open_paren],
+                    codes::MALFORMED_MACRO_INVOCATION,
+                    "Unterminated macro invocation".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        current.push(token.clone());
+        position += 1;
+    }
+
+    if !current.is_empty() {
+        arguments.push(current);
+    } else if !arguments.is_empty() {
+        return Err(MacroError::at(
+            token_at(tokens, position),
+            codes::MALFORMED_MACRO_INVOCATION,
+            "Empty macro argument".to_string(),
+        ));
+    }
+
+    Ok((arguments, position + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstModule, AstStatementKind};
+    use crate::ir::{Constant, InstructionKind};
+    use crate::lexer::Lexer;
+    use crate::lowering::{lower_module, LoweringOptions};
+    use crate::parser::Parser;
+    use crate::passes::fold_constants;
+    use crate::resolver::Resolver;
+
+    fn parse(source: &str) -> (AstModule, Expansions) {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "{:?}", lex_errors);
+        let mut expander = MacroExpander::new();
+        let tokens = expander.expand(tokens).unwrap();
+        let (ast, errors) = Parser::new(tokens).with_expansions(expander.expansions()).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        (ast, expander.expansions().clone())
+    }
+
+    /// The constants `source` prints, in order, after folding
+    fn printed(source: &str) -> Vec<Constant> {
+        let (ast, _) = parse(source);
+        Resolver::new().resolve(&ast).unwrap();
+        let mut module = lower_module(ast, &LoweringOptions::default());
+        assert!(fold_constants(&mut module).is_empty());
+        let instructions: Vec<_> = module.main.blocks.iter().flat_map(|block| &block.instructions).collect();
+        let constant = |temp| {
+            instructions.iter().find_map(|instruction| match &instruction.kind {
+                InstructionKind::Const(constant) if instruction.dest == Some(temp) => Some(constant.clone()),
+                _ => None,
+            })
+        };
+        instructions
+            .iter()
+            .filter_map(|instruction| match instruction.kind {
+                InstructionKind::Print(value) => Some(constant(value).expect("printed value is not constant")),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn template_bindings_do_not_capture_the_callers() {
+        let source = "macro shadow(e) => { let v = 10\naccrete e }\nlet v = 1\nshadow(v)";
+        assert_eq!(printed(source), [Constant::Integer(1)]);
+    }
+
+    #[test]
+    fn template_bindings_are_not_visible_to_the_caller() {
+        let (ast, _) = parse("macro make() => { let secret = 1 }\nmake()\naccrete secret");
+        let error = Resolver::new().resolve(&ast).unwrap_err();
+        assert_eq!(error.code, codes::UNKNOWN_VARIABLE);
+    }
+
+    #[test]
+    fn templates_reference_names_in_scope_at_the_definition() {
+        let source = "let total = 5\nmacro show() => { accrete total\naccrete \"${total}\" }\nshow()";
+        assert_eq!(
+            printed(source),
+            [Constant::Integer(5), Constant::String("5".to_string())]
+        );
+    }
+
+    #[test]
+    fn loop_variables_and_parameters_of_templates_are_hygienic() {
+        let source = "let i = 7\nmacro each(e) => { for i in 0..3 { accrete e } }\neach(i)";
+        assert_eq!(printed(source), [Constant::Integer(7)]);
+        let mut bound = Vec::new();
+        let (tokens, _) = Lexer::new("fn f(a, b = g(c), ...rest) { let x = a }").tokenize();
+        bound_names(&tokens, &[], &mut bound);
+        assert_eq!(bound, ["a", "b", "rest", "x"]);
+    }
+
+    #[test]
+    fn spans_joining_a_template_and_an_argument_cover_the_invocation() {
+        let source = "macro inc(x) => { accrete x + 1 }\nlet a = 1\ninc(a) ";
+        let (ast, expansions) = parse(source);
+        let AstStatementKind::Accrete(value) = &ast.statements[1].kind else {
+            panic!("expected accrete, found {:?}", ast.statements[1].kind);
+        };
+        assert_eq!(value.span.expansion, None);
+        assert_eq!(&source[value.span.start..value.span.end], "inc(a)");
+        let origin = expansions.get(0).unwrap();
+        assert_eq!(&source[origin.call_span.start..origin.call_span.end], "inc(a)");
+    }
+}
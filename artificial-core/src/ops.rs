@@ -0,0 +1,188 @@
+// artificial-language/artificial-core/src/ops.rs
+//! The operators of the language and what they compute. Constant folding
+//! and the VM both evaluate operators here, so a folded expression has the
+//! value, or fails with the error, that it would have at run time.
+use crate::codes::{self, ErrorCode};
+use crate::ir::Constant;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl BinaryOp {
+    /// How the operator is written in source
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+        }
+    }
+
+    /// Whether evaluating the operator can fail; equality compares any
+    /// two values
+    pub fn can_fail(self) -> bool {
+        !matches!(self, BinaryOp::Equal | BinaryOp::NotEqual)
+    }
+}
+
+/// The IR mnemonic, such as `add`
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "sub",
+            BinaryOp::Multiply => "mul",
+            BinaryOp::Divide => "div",
+            BinaryOp::Remainder => "rem",
+            BinaryOp::Equal => "eq",
+            BinaryOp::NotEqual => "ne",
+            BinaryOp::Less => "lt",
+            BinaryOp::LessEqual => "le",
+            BinaryOp::Greater => "gt",
+            BinaryOp::GreaterEqual => "ge",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOp::Negate => write!(f, "neg"),
+        }
+    }
+}
+
+/// Why an operator could not be evaluated
+#[derive(Debug, Clone)]
+pub struct OpError {
+    /// `INTEGER_OVERFLOW`, `DIVISION_BY_ZERO` or `TYPE_MISMATCH`
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// The error for operands of the wrong types, named by `type_name`
+pub fn binary_type_mismatch(op: BinaryOp, left: &str, right: &str) -> OpError {
+    OpError {
+        code: codes::TYPE_MISMATCH,
+        message: format!("Cannot apply `{}` to {} and {}", op.symbol(), left, right),
+    }
+}
+
+pub fn unary_type_mismatch(op: UnaryOp, operand: &str) -> OpError {
+    OpError {
+        code: codes::TYPE_MISMATCH,
+        message: format!("Cannot apply unary `{}` to {}", op.symbol(), operand),
+    }
+}
+
+/// The name of a value's type in error messages
+pub fn type_name(value: &Constant) -> &'static str {
+    match value {
+        Constant::Null => "null",
+        Constant::String(_) => "a string",
+        Constant::Integer(_) => "an integer",
+        Constant::Boolean(_) => "a boolean",
+    }
+}
+
+/// Apply a binary operator. `+` adds integers and concatenates strings,
+/// the other arithmetic operators take integers, and comparisons order
+/// integers numerically and strings by their characters.
+pub fn evaluate_binary(op: BinaryOp, left: &Constant, right: &Constant) -> Result<Constant, OpError> {
+    use Constant::{Boolean, Integer, String};
+    match (op, left, right) {
+        (BinaryOp::Equal, _, _) => Ok(Boolean(left == right)),
+        (BinaryOp::NotEqual, _, _) => Ok(Boolean(left != right)),
+        (BinaryOp::Add, String(a), String(b)) => Ok(String(format!("{}{}", a, b))),
+        (BinaryOp::Less, String(a), String(b)) => Ok(Boolean(a < b)),
+        (BinaryOp::LessEqual, String(a), String(b)) => Ok(Boolean(a <= b)),
+        (BinaryOp::Greater, String(a), String(b)) => Ok(Boolean(a > b)),
+        (BinaryOp::GreaterEqual, String(a), String(b)) => Ok(Boolean(a >= b)),
+        (_, Integer(a), Integer(b)) => integer_binary(op, *a, *b),
+        _ => Err(binary_type_mismatch(op, type_name(left), type_name(right))),
+    }
+}
+
+fn integer_binary(op: BinaryOp, a: i64, b: i64) -> Result<Constant, OpError> {
+    let checked = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Subtract => a.checked_sub(b),
+        BinaryOp::Multiply => a.checked_mul(b),
+        BinaryOp::Divide | BinaryOp::Remainder if b == 0 => {
+            let what = if op == BinaryOp::Divide { "Division" } else { "Remainder" };
+            return Err(OpError {
+                code: codes::DIVISION_BY_ZERO,
+                message: format!("{} by zero in `{} {} {}`", what, a, op.symbol(), b),
+            });
+        }
+        // Division truncates toward zero, so the remainder has the sign
+        // of the dividend
+        BinaryOp::Divide => a.checked_div(b),
+        BinaryOp::Remainder => a.checked_rem(b),
+        BinaryOp::Equal => return Ok(Constant::Boolean(a == b)),
+        BinaryOp::NotEqual => return Ok(Constant::Boolean(a != b)),
+        BinaryOp::Less => return Ok(Constant::Boolean(a < b)),
+        BinaryOp::LessEqual => return Ok(Constant::Boolean(a <= b)),
+        BinaryOp::Greater => return Ok(Constant::Boolean(a > b)),
+        BinaryOp::GreaterEqual => return Ok(Constant::Boolean(a >= b)),
+    };
+    checked.map(Constant::Integer).ok_or_else(|| OpError {
+        code: codes::INTEGER_OVERFLOW,
+        message: format!("Integer overflow in `{} {} {}`", a, op.symbol(), b),
+    })
+}
+
+pub fn evaluate_unary(op: UnaryOp, operand: &Constant) -> Result<Constant, OpError> {
+    match (op, operand) {
+        (UnaryOp::Negate, Constant::Integer(n)) => {
+            n.checked_neg().map(Constant::Integer).ok_or_else(|| OpError {
+                code: codes::INTEGER_OVERFLOW,
+                message: format!("Integer overflow in `-({})`", n),
+            })
+        }
+        _ => Err(unary_type_mismatch(op, type_name(operand))),
+    }
+}
+
+/// How a value reads when printed or interpolated into a string
+pub fn to_text(value: &Constant) -> String {
+    match value {
+        Constant::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
@@ -1,84 +1,1018 @@
 // artificial-language/artificial-core/src/parser.rs
 use crate::ast::*;
-use crate::lexer::{Token, TokenKind};
+use crate::codes::{self, ErrorCode};
+use crate::diagnostic::{Diagnostic, Suggestion};
+use crate::lexer::{InterpolationPart, Token, TokenKind, KEYWORDS};
+use crate::lints::{self, Level};
+use crate::macros::Expansions;
+use crate::ops::{BinaryOp, UnaryOp};
+use crate::span::Span;
+use crate::suggest::closest;
 use std::fmt;
 
 #[derive(Debug)]
 pub struct ParseError {
+    pub code: ErrorCode,
     pub message: String,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
+    /// Edits that would fix the error; each doubles as a help line
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl ParseError {
+    fn at(token: &Token, code: ErrorCode, message: String) -> Self {
+        ParseError {
+            code,
+            message,
+            span: token.span,
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+impl ParseError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let mut diagnostic =
+            Diagnostic::error(self.code, self.message.clone()).with_span(self.span);
+        for suggestion in &self.suggestions {
+            diagnostic = diagnostic
+                .with_help(suggestion.message.clone())
+                .with_suggestion(suggestion.clone());
+        }
+        diagnostic
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Parse error at {}:{}: {}",
-            self.line, self.column, self.message
-        )
+        write!(f, "Parse error at {}: {}", self.span, self.message)
     }
 }
 
-pub struct Parser {
+pub struct Parser<'a> {
     tokens: Vec<Token>,
     position: usize,
+    errors: Vec<ParseError>,
+    attributes: Vec<AstAttribute>,
+    warnings: Vec<Diagnostic>,
+    expansions: Option<&'a Expansions>,
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, position: 0 }
+        Parser {
+            tokens,
+            position: 0,
+            errors: Vec::new(),
+            attributes: Vec::new(),
+            warnings: Vec::new(),
+            expansions: None,
+        }
+    }
+
+    /// The expansions the tokens came from, so that an expression joining
+    /// macro text with an argument spans the invocation
+    pub fn with_expansions(mut self, expansions: &'a Expansions) -> Self {
+        self.expansions = Some(expansions);
+        self
     }
 
-    pub fn parse_module(&mut self) -> Result<AstModule, ParseError> {
+    /// Lint warnings found while parsing, such as needless semicolons
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Parse the whole token stream. A statement that fails to parse is
+    /// reported and replaced by an `Error` node, and parsing resumes at the
+    /// next statement, so the module is always returned.
+    pub fn parse_module(&mut self) -> (AstModule, Vec<ParseError>) {
+        let start = self.current_token().span;
+        let mut functions = Vec::new();
         let mut statements = Vec::new();
 
         while !self.is_at_eof() {
-            statements.push(self.parse_statement()?);
+            let item_start = self.position;
+            let result = self.with_attributes(|parser| {
+                if parser.check(&TokenKind::Fn) {
+                    parser.parse_function().map(|function| functions.push(function))
+                } else {
+                    parser.parse_statement().map(|stmt| statements.push(stmt))
+                }
+            });
+            if let Err(error) = result {
+                statements.push(self.recover(error, item_start));
+            }
+        }
+
+        let module = AstModule {
+            functions,
+            statements,
+            attributes: std::mem::take(&mut self.attributes),
+            span: start.to(self.current_token().span),
+        };
+        (module, std::mem::take(&mut self.errors))
+    }
+
+    /// Record `error` and skip to the next statement boundary. Returns an
+    /// `Error` statement covering everything from `start` that was skipped.
+    fn recover(&mut self, error: ParseError, start: usize) -> AstStatement {
+        self.errors.push(error);
+        // Braces the failed statement opened still need closing
+        let depth = self.tokens[start..self.position]
+            .iter()
+            .fold(0usize, |depth, token| match token.kind {
+                TokenKind::LeftBrace => depth + 1,
+                TokenKind::RightBrace => depth.saturating_sub(1),
+                _ => depth,
+            });
+        self.synchronize(depth);
+        if self.position == start {
+            self.advance();
+        }
+        let span = self.span_from(self.tokens[start].span);
+        AstStatement::new(AstStatementKind::Error, span)
+    }
+
+    /// Skip tokens until the enclosing braces are closed and the next
+    /// statement can start: after a `;` or `}`, or before a keyword that
+    /// begins a statement, a `}` closing the current block, or end of input
+    fn synchronize(&mut self, mut depth: usize) {
+        loop {
+            match self.current_token().kind {
+                TokenKind::Eof => return,
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace if depth == 0 => return,
+                TokenKind::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                TokenKind::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::Accrete
+                | TokenKind::Assert
+                | TokenKind::AssertEq
+                | TokenKind::Let
+                | TokenKind::Fn
+                | TokenKind::Return
+                | TokenKind::If
+                | TokenKind::Match
+                | TokenKind::For
+                | TokenKind::Hash
+                    if depth == 0 =>
+                {
+                    return
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Parse any `#level(lints)` attributes, then the item or statement
+    /// they apply to with `parse`
+    fn with_attributes<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let start = self.current_token().span;
+        let mut attributes = Vec::new();
+        while self.check(&TokenKind::Hash) {
+            attributes.push(self.parse_attribute()?);
+        }
+        if !attributes.is_empty() && (self.is_at_eof() || self.check(&TokenKind::RightBrace)) {
+            return Err(ParseError::at(
+                self.current_token(),
+                codes::MALFORMED_ATTRIBUTE,
+                "Expected an item or statement after attribute".to_string(),
+            ));
+        }
+        let item = parse(self)?;
+        let target = self.span_from(start);
+        for attribute in &mut attributes {
+            attribute.target = target;
         }
+        self.attributes.extend(attributes);
+        Ok(item)
+    }
 
-        Ok(AstModule { statements })
+    /// `#allow(lint, ...)`, `#warn(...)` or `#deny(...)`. The target is
+    /// filled in once the item it applies to has been parsed.
+    fn parse_attribute(&mut self) -> Result<AstAttribute, ParseError> {
+        let start = self.consume(&TokenKind::Hash)?.span;
+        let name = self.consume_identifier("attribute name")?;
+        let level = Level::from_name(&identifier(&name).name).ok_or_else(|| {
+            ParseError::at(
+                &name,
+                codes::MALFORMED_ATTRIBUTE,
+                format!(
+                    "Unknown attribute `{}`; expected `allow`, `warn` or `deny`",
+                    identifier(&name)
+                ),
+            )
+        })?;
+        self.consume(&TokenKind::LeftParen)?;
+        let mut lints = Vec::new();
+        while !self.check(&TokenKind::RightParen) {
+            let token = self.consume_identifier("lint name")?;
+            lints.push((identifier(&token).name, token.span));
+            if !self.check(&TokenKind::Comma) {
+                break;
+            }
+            self.consume(&TokenKind::Comma)?;
+        }
+        self.consume(&TokenKind::RightParen)?;
+        let span = self.span_from(start);
+        Ok(AstAttribute {
+            level,
+            lints,
+            span,
+            target: span,
+        })
+    }
+
+    fn parse_function(&mut self) -> Result<AstFunction, ParseError> {
+        let start = self.consume(&TokenKind::Fn)?.span;
+        let name_token = self.consume_identifier("function name")?;
+        self.consume(&TokenKind::LeftParen)?;
+
+        let mut params: Vec<AstParam> = Vec::new();
+        while !self.check(&TokenKind::RightParen) {
+            let token = self.current_token().clone();
+            let param = self.parse_param()?;
+            if let Some(last) = params.last().filter(|p| p.variadic) {
+                return Err(ParseError::at(
+                    &token,
+                    codes::INVALID_PARAMETER_ORDER,
+                    format!(
+                        "Variadic parameter `{}` must be the last parameter",
+                        last.name
+                    ),
+                ));
+            }
+            if params.iter().any(|p| p.name.name == param.name.name) {
+                return Err(ParseError::at(
+                    &token,
+                    codes::DUPLICATE_PARAMETER,
+                    format!("Duplicate parameter `{}`", param.name),
+                ));
+            }
+            if param.default.is_none()
+                && !param.variadic
+                && params.iter().any(|p| p.default.is_some())
+            {
+                return Err(ParseError::at(
+                    &token,
+                    codes::INVALID_PARAMETER_ORDER,
+                    format!(
+                        "Parameter `{}` needs a default value because an earlier parameter has one",
+                        param.name
+                    ),
+                ));
+            }
+            params.push(param);
+            if !self.check(&TokenKind::Comma) {
+                break;
+            }
+            self.consume(&TokenKind::Comma)?;
+        }
+        self.consume(&TokenKind::RightParen)?;
+
+        let body = self.parse_block()?;
+        Ok(AstFunction {
+            name: identifier(&name_token).name,
+            name_span: name_token.span,
+            params,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    /// `name`, `name = default` or `...name`
+    fn parse_param(&mut self) -> Result<AstParam, ParseError> {
+        let start = self.current_token().span;
+        let variadic = self.check(&TokenKind::DotDotDot);
+        if variadic {
+            self.consume(&TokenKind::DotDotDot)?;
+        }
+        let token = self.consume_identifier("parameter name")?;
+        let default = if !variadic && self.check(&TokenKind::Assign) {
+            self.consume(&TokenKind::Assign)?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(AstParam {
+            name: identifier(&token),
+            name_span: token.span,
+            default,
+            variadic,
+            span: self.span_from(start),
+        })
     }
 
     fn parse_statement(&mut self) -> Result<AstStatement, ParseError> {
         if self.check(&TokenKind::Accrete) {
             self.parse_accrete_statement()
+        } else if self.check(&TokenKind::Assert) {
+            self.parse_assert_statement()
+        } else if self.check(&TokenKind::AssertEq) {
+            self.parse_assert_eq_statement()
+        } else if self.check(&TokenKind::Let) {
+            self.parse_let_statement()
+        } else if self.check(&TokenKind::Return) {
+            self.parse_return_statement()
+        } else if self.check(&TokenKind::Fn) {
+            // Parse the function anyway so recovery resumes after it
+            let error = ParseError::at(
+                self.current_token(),
+                codes::NESTED_FUNCTION,
+                "Functions can only be declared at the top level".to_string(),
+            );
+            self.parse_function()?;
+            Err(error)
+        } else if self.at_assignment() {
+            self.parse_assign_statement()
+        } else if self.starts_expression() {
+            let expr = self.parse_statement_expression()?;
+            let span = expr.span;
+            self.consume_optional_semicolon()?;
+            Ok(AstStatement::new(AstStatementKind::Expression(expr), span))
         } else {
             let token = self.current_token();
-            Err(ParseError {
-                message: format!("Expected statement, found {:?}", token.kind),
-                line: token.line,
-                column: token.column,
-            })
+            Err(ParseError::at(
+                token,
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected statement, found {:?}", token.kind),
+            ))
         }
     }
 
     fn parse_accrete_statement(&mut self) -> Result<AstStatement, ParseError> {
-        self.consume(&TokenKind::Accrete)?;
+        let start = self.consume(&TokenKind::Accrete)?.span;
         let expr = self.parse_expression()?;
-        // Semicolon is optional
+        let span = self.span_from(start);
+        self.consume_optional_semicolon()?;
+        Ok(AstStatement::new(AstStatementKind::Accrete(expr), span))
+    }
+
+    fn parse_let_statement(&mut self) -> Result<AstStatement, ParseError> {
+        let start = self.consume(&TokenKind::Let)?.span;
+        let mutable = self.check(&TokenKind::Mut);
+        if mutable {
+            self.consume(&TokenKind::Mut)?;
+        }
+        let token = self.consume_identifier("variable name")?;
+        self.consume(&TokenKind::Assign)?;
+        let value = self.parse_expression()?;
+        let span = self.span_from(start);
+        self.consume_optional_semicolon()?;
+        let kind = AstStatementKind::Let {
+            name: identifier(&token),
+            name_span: token.span,
+            mutable,
+            value,
+        };
+        Ok(AstStatement::new(kind, span))
+    }
+
+    fn parse_return_statement(&mut self) -> Result<AstStatement, ParseError> {
+        let start = self.consume(&TokenKind::Return)?.span;
+        let value = if self.starts_expression() {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let span = self.span_from(start);
+        if value.is_none() {
+            // `return; x` must not become `return x`
+            if self.check(&TokenKind::Semicolon) {
+                let semicolon = self.advance().clone();
+                if !self.starts_expression() {
+                    self.needless_semicolon(&semicolon);
+                }
+            }
+        } else {
+            self.consume_optional_semicolon()?;
+        }
+        Ok(AstStatement::new(AstStatementKind::Return { value }, span))
+    }
+
+    fn parse_assign_statement(&mut self) -> Result<AstStatement, ParseError> {
+        let token = self.consume_identifier("variable name")?;
+        self.consume(&TokenKind::Assign)?;
+        let value = self.parse_expression()?;
+        let span = self.span_from(token.span);
+        self.consume_optional_semicolon()?;
+        let kind = AstStatementKind::Assign {
+            name: identifier(&token),
+            value,
+        };
+        Ok(AstStatement::new(kind, span))
+    }
+
+    /// `name = ...` starts an assignment rather than an expression
+    fn at_assignment(&self) -> bool {
+        matches!(self.current_token().kind, TokenKind::Identifier(_))
+            && matches!(
+                self.tokens.get(self.position + 1).map(|t| &t.kind),
+                Some(TokenKind::Assign)
+            )
+    }
+
+    fn consume_identifier(&mut self, what: &str) -> Result<Token, ParseError> {
+        let token = self.current_token().clone();
+        if matches!(token.kind, TokenKind::Identifier(_)) {
+            self.advance();
+            Ok(token)
+        } else {
+            Err(ParseError::at(
+                &token,
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected {}, found {:?}", what, token.kind),
+            ))
+        }
+    }
+
+    fn parse_assert_statement(&mut self) -> Result<AstStatement, ParseError> {
+        let start = self.consume(&TokenKind::Assert)?.span;
+        let condition = self.parse_expression()?;
+        let message = if self.check(&TokenKind::Comma) {
+            self.consume(&TokenKind::Comma)?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let span = self.span_from(start);
+        self.consume_optional_semicolon()?;
+        Ok(AstStatement::new(
+            AstStatementKind::Assert { condition, message },
+            span,
+        ))
+    }
+
+    fn parse_assert_eq_statement(&mut self) -> Result<AstStatement, ParseError> {
+        let start = self.consume(&TokenKind::AssertEq)?.span;
+        let left = self.parse_expression()?;
+        self.consume(&TokenKind::Comma)?;
+        let right = self.parse_expression()?;
+        let span = self.span_from(start);
+        self.consume_optional_semicolon()?;
+        Ok(AstStatement::new(AstStatementKind::AssertEq { left, right }, span))
+    }
+
+    /// Statements may end with a semicolon, but it is optional. It is only
+    /// needed when the next statement would otherwise continue this one.
+    fn consume_optional_semicolon(&mut self) -> Result<(), ParseError> {
         if self.check(&TokenKind::Semicolon) {
-            self.consume(&TokenKind::Semicolon)?;
+            let before = self.position.checked_sub(1).map(|last| self.tokens[last].kind.clone());
+            let semicolon = self.consume(&TokenKind::Semicolon)?.clone();
+            if !self.continues_expression(before.as_ref()) {
+                self.needless_semicolon(&semicolon);
+            }
         }
-        Ok(AstStatement::Accrete(expr))
+        Ok(())
     }
 
+    /// Whether the current token would extend an expression that ends with
+    /// `before`
+    fn continues_expression(&self, before: Option<&TokenKind>) -> bool {
+        let kind = &self.current_token().kind;
+        // Without the semicolon, `b (2)` would call `b`
+        let calls = matches!(kind, TokenKind::LeftParen)
+            && matches!(
+                before,
+                Some(TokenKind::Identifier(_) | TokenKind::RightParen | TokenKind::RightBracket)
+            );
+        calls
+            || matches!(
+                kind,
+                TokenKind::LeftBracket
+                    | TokenKind::DotDot
+                    | TokenKind::DotDotEqual
+                    | TokenKind::Step
+                    | TokenKind::QuestionQuestion
+            )
+            || binary_operator(kind).is_some()
+    }
+
+    fn needless_semicolon(&mut self, semicolon: &Token) {
+        // The user cannot remove semicolons a macro wrote
+        if semicolon.span.expansion.is_some() {
+            return;
+        }
+        let warning = lints::NEEDLESS_SEMICOLONS
+            .warning("Unnecessary semicolon".to_string(), semicolon.span)
+            .with_help("remove this semicolon".to_string())
+            .with_suggestion(Suggestion {
+                message: "remove this semicolon".to_string(),
+                span: semicolon.span,
+                replacement: String::new(),
+            });
+        self.warnings.push(warning);
+    }
+
+    /// `comparison ("??" expression)?`; `??` binds loosest and groups to
+    /// the right
     fn parse_expression(&mut self) -> Result<AstExpression, ParseError> {
+        let value = self.parse_comparison()?;
+        if !self.check(&TokenKind::QuestionQuestion) {
+            return Ok(value);
+        }
+        self.consume(&TokenKind::QuestionQuestion)?;
+        let fallback = self.parse_expression()?;
+        let span = self.join(value.span, fallback.span);
+        let kind = AstExpressionKind::Coalesce {
+            value: Box::new(value),
+            fallback: Box::new(fallback),
+        };
+        Ok(AstExpression::new(kind, span))
+    }
+
+    /// `range (("==" | "!=" | "<" | "<=" | ">" | ">=") range)?`; comparisons
+    /// do not chain
+    fn parse_comparison(&mut self) -> Result<AstExpression, ParseError> {
+        let left = self.parse_range()?;
+        let Some(op) = self.comparison_operator() else {
+            return Ok(left);
+        };
+        self.advance();
+        let right = self.parse_range()?;
+        if self.comparison_operator().is_some() {
+            return Err(ParseError::at(
+                self.current_token(),
+                codes::UNEXPECTED_TOKEN,
+                "Comparison operators cannot be chained".to_string(),
+            ));
+        }
+        Ok(self.binary(op, left, right))
+    }
+
+    fn comparison_operator(&self) -> Option<BinaryOp> {
+        binary_operator(&self.current_token().kind).filter(|op| {
+            matches!(
+                op,
+                BinaryOp::Equal
+                    | BinaryOp::NotEqual
+                    | BinaryOp::Less
+                    | BinaryOp::LessEqual
+                    | BinaryOp::Greater
+                    | BinaryOp::GreaterEqual
+            )
+        })
+    }
+
+    /// `additive ( (".." | "..=") additive ("step" additive)? )?`
+    fn parse_range(&mut self) -> Result<AstExpression, ParseError> {
+        let start = self.parse_additive()?;
+        let inclusive = if self.check(&TokenKind::DotDot) {
+            false
+        } else if self.check(&TokenKind::DotDotEqual) {
+            true
+        } else {
+            return Ok(start);
+        };
+        self.advance();
+
+        let end = self.parse_additive()?;
+        let step = if self.check(&TokenKind::Step) {
+            self.consume(&TokenKind::Step)?;
+            Some(Box::new(self.parse_additive()?))
+        } else {
+            None
+        };
+        let span = self.span_from(start.span);
+        let kind = AstExpressionKind::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            step,
+            inclusive,
+        };
+        Ok(AstExpression::new(kind, span))
+    }
+
+    /// `multiplicative (("+" | "-") multiplicative)*`
+    fn parse_additive(&mut self) -> Result<AstExpression, ParseError> {
+        let mut expr = self.parse_multiplicative()?;
+        while let Some(op @ (BinaryOp::Add | BinaryOp::Subtract)) =
+            binary_operator(&self.current_token().kind)
+        {
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            expr = self.binary(op, expr, right);
+        }
+        Ok(expr)
+    }
+
+    /// `unary (("*" | "/" | "%") unary)*`
+    fn parse_multiplicative(&mut self) -> Result<AstExpression, ParseError> {
+        let mut expr = self.parse_unary()?;
+        while let Some(op @ (BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder)) =
+            binary_operator(&self.current_token().kind)
+        {
+            self.advance();
+            let right = self.parse_unary()?;
+            expr = self.binary(op, expr, right);
+        }
+        Ok(expr)
+    }
+
+    /// `"-" unary | postfix`
+    fn parse_unary(&mut self) -> Result<AstExpression, ParseError> {
+        if !self.check(&TokenKind::Minus) {
+            return self.parse_postfix();
+        }
+        let start = self.advance().span;
+        // The smallest integer has no positive counterpart to negate
+        if let Some(TokenKind::IntegerLiteral(n)) = self.tokens.get(self.position).map(|t| &t.kind) {
+            if *n == i64::MIN.unsigned_abs() {
+                let end = self.advance().span;
+                let kind = AstExpressionKind::IntegerLiteral(i64::MIN);
+                return Ok(AstExpression::new(kind, self.join(start, end)));
+            }
+        }
+        let operand = self.parse_unary()?;
+        let span = self.join(start, operand.span);
+        let kind = AstExpressionKind::Unary {
+            op: UnaryOp::Negate,
+            operand: Box::new(operand),
+        };
+        Ok(AstExpression::new(kind, span))
+    }
+
+    /// A primary expression followed by any number of `[range]` slices
+    fn parse_postfix(&mut self) -> Result<AstExpression, ParseError> {
+        let mut expr = self.parse_primary()?;
+        while self.check(&TokenKind::LeftBracket) {
+            self.consume(&TokenKind::LeftBracket)?;
+            let range = self.parse_expression()?;
+            self.consume(&TokenKind::RightBracket)?;
+            let span = self.span_from(expr.span);
+            let kind = AstExpressionKind::Slice {
+                target: Box::new(expr),
+                range: Box::new(range),
+            };
+            expr = AstExpression::new(kind, span);
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<AstExpression, ParseError> {
         let token = self.current_token().clone();
+        let literal = |kind| Ok(AstExpression::new(kind, token.span));
         match &token.kind {
             TokenKind::StringLiteral(s) => {
-                let string = s.clone();
                 self.advance();
-                Ok(AstExpression::StringLiteral(string))
+                literal(AstExpressionKind::StringLiteral(s.clone()))
+            }
+            TokenKind::InterpolatedString(parts) => {
+                self.advance();
+                let parts = parts
+                    .iter()
+                    .map(|part| match part {
+                        InterpolationPart::Text(text) => Ok(AstExpression::new(
+                            AstExpressionKind::StringLiteral(text.clone()),
+                            token.span,
+                        )),
+                        InterpolationPart::Code(tokens) => parse_interpolation(tokens.clone(), self.expansions),
+                    })
+                    .collect::<Result<_, _>>()?;
+                literal(AstExpressionKind::Interpolation(parts))
+            }
+            TokenKind::IntegerLiteral(n) => match i64::try_from(*n) {
+                Ok(n) => {
+                    self.advance();
+                    literal(AstExpressionKind::IntegerLiteral(n))
+                }
+                Err(_) => Err(ParseError::at(
+                    &token,
+                    codes::INTEGER_OUT_OF_RANGE,
+                    format!("Integer literal out of range: {}", n),
+                )),
+            },
+            TokenKind::True | TokenKind::False => {
+                self.advance();
+                literal(AstExpressionKind::BooleanLiteral(token.kind == TokenKind::True))
+            }
+            TokenKind::Null => {
+                self.advance();
+                literal(AstExpressionKind::NullLiteral)
+            }
+            // The lexer has already reported the malformed literal
+            TokenKind::Error => {
+                self.advance();
+                literal(AstExpressionKind::Error)
+            }
+            TokenKind::Identifier(_) => {
+                self.advance();
+                if self.check(&TokenKind::LeftParen) {
+                    return self.parse_call(&token);
+                }
+                literal(AstExpressionKind::Variable(identifier(&token)))
+            }
+            TokenKind::LeftBrace => {
+                let block = self.parse_block()?;
+                let span = block.span;
+                Ok(AstExpression::new(AstExpressionKind::Block(block), span))
             }
-            _ => Err(ParseError {
-                message: format!("Expected expression, found {:?}", token.kind),
-                line: token.line,
-                column: token.column,
-            }),
+            TokenKind::LeftParen => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.consume(&TokenKind::RightParen)?;
+                Ok(AstExpression::new(expr.kind, self.span_from(token.span)))
+            }
+            TokenKind::If => self.parse_if_expression(),
+            TokenKind::Match => self.parse_match_expression(),
+            TokenKind::For => self.parse_for_expression(),
+            _ => Err(ParseError::at(
+                &token,
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected expression, found {:?}", token.kind),
+            )),
+        }
+    }
+
+    /// Parses the argument list of a call to `callee`
+    fn parse_call(&mut self, callee: &Token) -> Result<AstExpression, ParseError> {
+        self.consume(&TokenKind::LeftParen)?;
+        let mut args = Vec::new();
+        while !self.check(&TokenKind::RightParen) {
+            let token = self.current_token().clone();
+            let named = matches!(token.kind, TokenKind::Identifier(_))
+                && matches!(
+                    self.tokens.get(self.position + 1).map(|t| &t.kind),
+                    Some(TokenKind::Colon)
+                );
+            let name = if named {
+                self.advance();
+                self.consume(&TokenKind::Colon)?;
+                Some(identifier(&token).name)
+            } else {
+                None
+            };
+            let value = self.parse_expression()?;
+            args.push(AstArgument {
+                name,
+                value,
+                span: self.span_from(token.span),
+            });
+            if !self.check(&TokenKind::Comma) {
+                break;
+            }
+            self.consume(&TokenKind::Comma)?;
+        }
+        self.consume(&TokenKind::RightParen)?;
+        let kind = AstExpressionKind::Call {
+            callee: identifier(callee).name,
+            args,
+        };
+        Ok(AstExpression::new(kind, self.span_from(callee.span)))
+    }
+
+    fn starts_expression(&self) -> bool {
+        matches!(
+            self.current_token().kind,
+            TokenKind::StringLiteral(_)
+                | TokenKind::InterpolatedString(_)
+                | TokenKind::IntegerLiteral(_)
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Null
+                | TokenKind::Error
+                | TokenKind::Identifier(_)
+                | TokenKind::LeftBrace
+                | TokenKind::LeftParen
+                | TokenKind::Minus
+                | TokenKind::If
+                | TokenKind::Match
+                | TokenKind::For
+        )
+    }
+
+    /// Parse the expression that starts a statement. Like in Rust, `if`,
+    /// `match`, `for` and blocks end the statement at their closing brace,
+    /// so a `-1` on the next line starts a new expression instead of
+    /// subtracting from them.
+    fn parse_statement_expression(&mut self) -> Result<AstExpression, ParseError> {
+        if matches!(
+            self.current_token().kind,
+            TokenKind::If | TokenKind::Match | TokenKind::For | TokenKind::LeftBrace
+        ) {
+            self.parse_primary()
+        } else {
+            self.parse_expression()
+        }
+    }
+
+    /// Parse `{ ... }`. An expression that is not followed by a semicolon
+    /// and closes the block becomes the block's value.
+    fn parse_block(&mut self) -> Result<AstBlock, ParseError> {
+        let start = self.consume(&TokenKind::LeftBrace)?.span;
+        let mut statements = Vec::new();
+        let mut value = None;
+
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_eof() {
+            let item_start = self.position;
+            let result =
+                self.with_attributes(|parser| parser.parse_block_item(&mut statements, &mut value));
+            if let Err(error) = result {
+                statements.push(self.recover(error, item_start));
+            }
+        }
+
+        self.consume(&TokenKind::RightBrace)?;
+        Ok(AstBlock {
+            statements,
+            value,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse one statement of a block, or the expression that ends it
+    fn parse_block_item(
+        &mut self,
+        statements: &mut Vec<AstStatement>,
+        value: &mut Option<Box<AstExpression>>,
+    ) -> Result<(), ParseError> {
+        if !self.starts_expression() || self.at_assignment() {
+            statements.push(self.parse_statement()?);
+            return Ok(());
+        }
+
+        let expr = self.parse_statement_expression()?;
+        let span = expr.span;
+        if self.check(&TokenKind::Semicolon) {
+            self.consume(&TokenKind::Semicolon)?;
+            statements.push(AstStatement::new(AstStatementKind::Expression(expr), span));
+        } else if self.check(&TokenKind::RightBrace) {
+            *value = Some(Box::new(expr));
+        } else if expr.is_block_like() {
+            statements.push(AstStatement::new(AstStatementKind::Expression(expr), span));
+        } else {
+            let token = self.current_token();
+            let mut error = ParseError::at(
+                token,
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected ';' or '}}' after expression, found {:?}", token.kind),
+            );
+            // `acrete x` reads as two expressions
+            if let AstExpressionKind::Variable(name) = &expr.kind {
+                if let Some(keyword) = closest(&name.name, KEYWORDS.iter().copied()) {
+                    error.suggestions.push(Suggestion {
+                        message: format!("did you mean the keyword `{}`?", keyword),
+                        span: expr.span,
+                        replacement: keyword.to_string(),
+                    });
+                }
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn parse_if_expression(&mut self) -> Result<AstExpression, ParseError> {
+        let start = self.consume(&TokenKind::If)?.span;
+        if self.check(&TokenKind::Let) {
+            return self.parse_if_let_expression(start);
+        }
+        let condition = self.parse_expression()?;
+        let then_branch = self.parse_block()?;
+        let else_branch = self.parse_else_branch()?;
+        let kind = AstExpressionKind::If {
+            condition: Box::new(condition),
+            then_branch,
+            else_branch,
+        };
+        Ok(AstExpression::new(kind, self.span_from(start)))
+    }
+
+    /// Parses the rest of `if let name = value { ... } else { ... }`
+    fn parse_if_let_expression(&mut self, start: Span) -> Result<AstExpression, ParseError> {
+        self.consume(&TokenKind::Let)?;
+        let token = self.consume_identifier("variable name")?;
+        self.consume(&TokenKind::Assign)?;
+        let value = self.parse_expression()?;
+        let then_branch = self.parse_block()?;
+        let else_branch = self.parse_else_branch()?;
+        let kind = AstExpressionKind::IfLet {
+            name: identifier(&token),
+            name_span: token.span,
+            value: Box::new(value),
+            then_branch,
+            else_branch,
+        };
+        Ok(AstExpression::new(kind, self.span_from(start)))
+    }
+
+    fn parse_else_branch(&mut self) -> Result<Option<Box<AstExpression>>, ParseError> {
+        if self.check(&TokenKind::Else) {
+            self.consume(&TokenKind::Else)?;
+            if self.check(&TokenKind::If) {
+                Ok(Some(Box::new(self.parse_if_expression()?)))
+            } else {
+                let block = self.parse_block()?;
+                let span = block.span;
+                Ok(Some(Box::new(AstExpression::new(AstExpressionKind::Block(block), span))))
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_match_expression(&mut self) -> Result<AstExpression, ParseError> {
+        let start = self.consume(&TokenKind::Match)?.span;
+        let scrutinee = self.parse_expression()?;
+        self.consume(&TokenKind::LeftBrace)?;
+
+        let mut arms = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_eof() {
+            let arm_start = self.current_token().span;
+            let pattern = self.parse_pattern()?;
+            self.consume(&TokenKind::FatArrow)?;
+            let body = self.parse_expression()?;
+            arms.push(AstMatchArm {
+                pattern,
+                body,
+                span: self.span_from(arm_start),
+            });
+            if self.check(&TokenKind::Comma) {
+                self.consume(&TokenKind::Comma)?;
+            }
+        }
+
+        self.consume(&TokenKind::RightBrace)?;
+        let kind = AstExpressionKind::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        };
+        Ok(AstExpression::new(kind, self.span_from(start)))
+    }
+
+    fn parse_for_expression(&mut self) -> Result<AstExpression, ParseError> {
+        let start = self.consume(&TokenKind::For)?.span;
+        let token = self.consume_identifier("loop variable")?;
+        self.consume(&TokenKind::In)?;
+        let iterable = self.parse_expression()?;
+        let body = self.parse_block()?;
+        let kind = AstExpressionKind::For {
+            variable: identifier(&token),
+            variable_span: token.span,
+            iterable: Box::new(iterable),
+            body,
+        };
+        Ok(AstExpression::new(kind, self.span_from(start)))
+    }
+
+    fn parse_pattern(&mut self) -> Result<AstPattern, ParseError> {
+        if self.check(&TokenKind::Underscore) {
+            let span = self.consume(&TokenKind::Underscore)?.span;
+            return Ok(AstPattern::Wildcard(span));
+        }
+        let token = self.current_token().clone();
+        let pattern = self.parse_expression()?;
+        match pattern.kind {
+            AstExpressionKind::StringLiteral(_)
+            | AstExpressionKind::IntegerLiteral(_)
+            | AstExpressionKind::BooleanLiteral(_)
+            | AstExpressionKind::NullLiteral => Ok(AstPattern::Literal(pattern)),
+            _ => Err(ParseError::at(
+                &token,
+                codes::INVALID_PATTERN,
+                "Match patterns must be literals or `_`".to_string(),
+            )),
+        }
+    }
+
+    /// The span from `start` to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        match self.position.checked_sub(1) {
+            Some(last) => self.join(start, self.tokens[last].span),
+            None => start,
+        }
+    }
+
+    /// The span from the start of `start` to the end of `end`
+    fn join(&self, start: Span, end: Span) -> Span {
+        match self.expansions {
+            Some(expansions) => expansions.join(start, end),
+            None => start.to(end),
         }
     }
 
+    fn binary(&self, op: BinaryOp, left: AstExpression, right: AstExpression) -> AstExpression {
+        let span = self.join(left.span, right.span);
+        let kind = AstExpressionKind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        AstExpression::new(kind, span)
+    }
+
     fn current_token(&self) -> &Token {
         self.tokens
             .get(self.position)
@@ -105,11 +1039,251 @@ impl Parser {
             Ok(self.advance())
         } else {
             let token = self.current_token();
-            Err(ParseError {
-                message: format!("Expected {:?}, found {:?}", kind, token.kind),
-                line: token.line,
-                column: token.column,
-            })
+            Err(ParseError::at(
+                token,
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected {:?}, found {:?}", kind, token.kind),
+            ))
+        }
+    }
+}
+
+/// The binary operator a token stands for
+fn binary_operator(kind: &TokenKind) -> Option<BinaryOp> {
+    match kind {
+        TokenKind::Plus => Some(BinaryOp::Add),
+        TokenKind::Minus => Some(BinaryOp::Subtract),
+        TokenKind::Star => Some(BinaryOp::Multiply),
+        TokenKind::Slash => Some(BinaryOp::Divide),
+        TokenKind::Percent => Some(BinaryOp::Remainder),
+        TokenKind::EqualEqual => Some(BinaryOp::Equal),
+        TokenKind::BangEqual => Some(BinaryOp::NotEqual),
+        TokenKind::Less => Some(BinaryOp::Less),
+        TokenKind::LessEqual => Some(BinaryOp::LessEqual),
+        TokenKind::Greater => Some(BinaryOp::Greater),
+        TokenKind::GreaterEqual => Some(BinaryOp::GreaterEqual),
+        _ => None,
+    }
+}
+
+/// Parse the tokens of one `${...}` part of an interpolated string
+fn parse_interpolation(tokens: Vec<Token>, expansions: Option<&Expansions>) -> Result<AstExpression, ParseError> {
+    let mut parser = Parser::new(tokens);
+    parser.expansions = expansions;
+    let expr = parser.parse_expression()?;
+    if !parser.is_at_eof() {
+        let token = parser.current_token();
+        return Err(ParseError::at(
+            token,
+            codes::UNEXPECTED_TOKEN,
+            format!("Expected `}}` to end the interpolation, found {:?}", token.kind),
+        ));
+    }
+    Ok(expr)
+}
+
+/// Build an `Identifier` from an identifier token, keeping the macro
+/// expansion whose bindings it refers to
+fn identifier(token: &Token) -> Identifier {
+    let name = match &token.kind {
+        TokenKind::Identifier(name) => name.clone(),
+        other => format!("{:?}", other),
+    };
+    Identifier {
+        name,
+        expansion: token.context,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse(source: &str) -> AstModule {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "{:?}", lex_errors);
+        let (module, errors) = Parser::new(tokens).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        module
+    }
+
+    /// Parse `source`, which has syntax errors, and return what was kept
+    fn parse_with_errors(source: &str) -> (AstModule, Vec<ParseError>) {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "{:?}", lex_errors);
+        Parser::new(tokens).parse_module()
+    }
+
+    /// The integer an `accrete` statement prints, or `None` for an error
+    /// node
+    fn printed(statement: &AstStatement) -> Option<i64> {
+        match &statement.kind {
+            AstStatementKind::Accrete(AstExpression {
+                kind: AstExpressionKind::IntegerLiteral(value),
+                ..
+            }) => Some(*value),
+            AstStatementKind::Error => None,
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    /// The statements and value of the body of the only function
+    fn body(source: &str) -> AstBlock {
+        parse(source).functions.remove(0).body
+    }
+
+    fn is_negated_one(expr: &AstExpression) -> bool {
+        match &expr.kind {
+            AstExpressionKind::Unary {
+                op: UnaryOp::Negate,
+                operand,
+            } => matches!(operand.kind, AstExpressionKind::IntegerLiteral(1)),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn negative_value_after_if_starts_a_new_expression() {
+        let body = body("fn f(c) {\n    if c { accrete \"then\" }\n    -1\n}");
+        assert_eq!(body.statements.len(), 1);
+        assert!(matches!(
+            &body.statements[0].kind,
+            AstStatementKind::Expression(expr) if matches!(expr.kind, AstExpressionKind::If { .. })
+        ));
+        assert!(body.value.is_some_and(|value| is_negated_one(&value)));
+    }
+
+    #[test]
+    fn negative_value_after_for_starts_a_new_expression() {
+        let body = body("fn find(items, wanted) {\n    for item in items {\n        if item == wanted { return item }\n    }\n    -1\n}");
+        assert_eq!(body.statements.len(), 1);
+        assert!(matches!(
+            &body.statements[0].kind,
+            AstStatementKind::Expression(expr) if matches!(expr.kind, AstExpressionKind::For { .. })
+        ));
+        assert!(body.value.is_some_and(|value| is_negated_one(&value)));
+    }
+
+    #[test]
+    fn top_level_statement_ends_after_block() {
+        let module = parse("{ accrete 1 }\n-1");
+        assert_eq!(module.statements.len(), 2);
+        assert!(matches!(
+            &module.statements[1].kind,
+            AstStatementKind::Expression(expr) if is_negated_one(expr)
+        ));
+    }
+
+    #[test]
+    fn block_like_expression_continues_inside_an_expression() {
+        let module = parse("let v = if true { 1 } else { 2 } - 1");
+        assert!(matches!(
+            &module.statements[0].kind,
+            AstStatementKind::Let { value, .. } if matches!(value.kind, AstExpressionKind::Binary { op: BinaryOp::Subtract, .. })
+        ));
+    }
+
+    #[test]
+    fn reports_every_syntax_error_and_resumes_at_the_next_statement() {
+        let source = "accrete 1\nlet = 2\naccrete 3\naccrete )\naccrete 5\n";
+        let (module, errors) = parse_with_errors(source);
+        let found: Vec<_> = errors.iter().map(|e| (e.span.line, e.span.column, &source[e.span.start..e.span.end])).collect();
+        assert_eq!(found, [(2, 5, "="), (4, 9, ")")]);
+        let printed: Vec<_> = module.statements.iter().map(printed).collect();
+        assert_eq!(printed, [Some(1), None, Some(3), None, Some(5)]);
+        let skipped: Vec<_> = module
+            .statements
+            .iter()
+            .filter(|statement| matches!(statement.kind, AstStatementKind::Error))
+            .map(|statement| &source[statement.span.start..statement.span.end])
+            .collect();
+        assert_eq!(skipped, ["let = 2", "accrete )"]);
+    }
+
+    #[test]
+    fn recovers_inside_blocks_and_keeps_later_items() {
+        let source = "fn f() {\n    let x =\n    accrete 6\n    accrete x +\n}\naccrete 7\n";
+        let (mut module, errors) = parse_with_errors(source);
+        let found: Vec<_> = errors.iter().map(|e| (e.span.line, &source[e.span.start..e.span.end])).collect();
+        assert_eq!(found, [(3, "accrete"), (5, "}")]);
+        let body = module.functions.remove(0).body;
+        let printed_in_body: Vec<_> = body.statements.iter().map(printed).collect();
+        assert_eq!(printed_in_body, [None, Some(6), None]);
+        let printed: Vec<_> = module.statements.iter().map(printed).collect();
+        assert_eq!(printed, [Some(7)]);
+    }
+
+    #[test]
+    fn unexpected_characters_are_skipped() {
+        let (tokens, lex_errors) = Lexer::new("accrete 7 @ 8\naccrete 9 ^\naccrete 10").tokenize();
+        let found: Vec<_> = lex_errors.iter().map(|e| (e.code, e.span.line, e.span.column)).collect();
+        assert_eq!(found, [(codes::UNEXPECTED_CHARACTER, 1, 11), (codes::UNEXPECTED_CHARACTER, 2, 11)]);
+        // What is left is `accrete 7`, `8`, `accrete 9` and `accrete 10`
+        let (module, errors) = Parser::new(tokens).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(module.statements.len(), 4);
+        assert_eq!(printed(&module.statements[3]), Some(10));
+    }
+
+    /// The needless semicolon warnings for `source`
+    fn semicolon_warnings(source: &str) -> Vec<Diagnostic> {
+        let (tokens, _) = Lexer::new(source).tokenize();
+        let mut parser = Parser::new(tokens);
+        let (_, errors) = parser.parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        parser.take_warnings()
+    }
+
+    /// The module as `Debug` prints it, without spans
+    fn shape(module: &AstModule) -> String {
+        let mut text = format!("{:?}", module);
+        while let Some(start) = text.find("Span {") {
+            let end = start + text[start..].find('}').unwrap();
+            text.replace_range(start..=end, "Span");
+        }
+        text
+    }
+
+    #[test]
+    fn semicolon_before_a_parenthesis_is_needed_after_a_name() {
+        for source in ["let b = 1\nlet a = b;\n(2)", "let s = \"ab\"\naccrete s[0..1];\n(2)", "accrete (1);\n(2)"] {
+            assert!(semicolon_warnings(source).is_empty(), "{}", source);
+        }
+        // Removing it would turn `b` into the callee
+        assert!(shape(&parse("let b = 1\nlet a = b\n(2)")).contains("Call"));
+    }
+
+    #[test]
+    fn removing_a_needless_semicolon_keeps_the_meaning() {
+        for source in ["let a = 1;\n(2)", "accrete \"x\";\n(2)", "let b = 1\nlet a = b;\naccrete 2"] {
+            let warnings = semicolon_warnings(source);
+            assert_eq!(warnings.len(), 1, "{}", source);
+            assert_eq!(warnings[0].lint, Some("needless_semicolons"));
+            let suggestion = &warnings[0].suggestions[0];
+            let fixed = format!(
+                "{}{}{}",
+                &source[..suggestion.span.start],
+                suggestion.replacement,
+                &source[suggestion.span.end..]
+            );
+            assert_eq!(shape(&parse(&fixed)), shape(&parse(source)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn smallest_integer_literal_needs_its_minus_sign() {
+        let module = parse("accrete -9223372036854775808");
+        assert!(matches!(
+            &module.statements[0].kind,
+            AstStatementKind::Accrete(AstExpression { kind: AstExpressionKind::IntegerLiteral(i64::MIN), .. })
+        ));
+        for source in ["accrete 9223372036854775808", "accrete -(9223372036854775808)", "accrete 1 -9223372036854775808"] {
+            let (_, errors) = parse_with_errors(source);
+            assert_eq!(errors.len(), 1, "{}", source);
+            assert_eq!(errors[0].code, codes::INTEGER_OUT_OF_RANGE);
         }
+        let (_, lex_errors) = Lexer::new("accrete -9223372036854775809").tokenize();
+        assert_eq!(lex_errors[0].code, codes::INTEGER_OUT_OF_RANGE);
     }
 }
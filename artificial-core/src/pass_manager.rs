@@ -0,0 +1,313 @@
+// artificial-language/artificial-core/src/pass_manager.rs
+//! Runs the optimization passes over a module in the order they were
+//! registered. The same manager drives the IR passes here and the bytecode
+//! passes of `artificial-vm`; which ones run is decided by the
+//! optimization level and by per-pass flags.
+use crate::diagnostic::{Diagnostic, Severity};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How hard the compiler optimizes, from `-O0` to `-O2`, or `-Os` for
+/// small bytecode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    /// No optimization
+    O0,
+    /// Constant folding, dead code elimination and peephole rewrites
+    O1,
+    /// Everything, including inlining
+    #[default]
+    O2,
+    /// `-O1` plus inlining of functions no bigger than a call
+    Os,
+}
+
+impl OptLevel {
+    /// Parse the part of a flag after `-O`
+    pub fn parse(level: &str) -> Option<OptLevel> {
+        match level {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            "s" => Some(OptLevel::Os),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptLevel::O0 => write!(f, "-O0"),
+            OptLevel::O1 => write!(f, "-O1"),
+            OptLevel::O2 => write!(f, "-O2"),
+            OptLevel::Os => write!(f, "-Os"),
+        }
+    }
+}
+
+/// A transformation of a module of type `M`
+pub trait Pass<M> {
+    /// The name used by `--enable-pass`, `--disable-pass` and
+    /// `--print-after`
+    fn name(&self) -> &'static str;
+
+    /// Transform the module, returning warnings and errors for the user.
+    /// Errors stop the passes after this one.
+    fn run(&mut self, module: &mut M) -> Vec<Diagnostic>;
+}
+
+/// Why the passes stopped
+#[derive(Debug)]
+pub enum PassError {
+    /// A pass found errors in the program
+    Diagnostics(Vec<Diagnostic>),
+    /// A pass left the module invalid, which is a compiler bug
+    Invalid { pass: &'static str, message: String },
+}
+
+/// How long one run of a pass took
+#[derive(Debug, Clone, Copy)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// Checks that a module is well formed, describing the problem if not
+pub type Verifier<M> = fn(&M) -> Result<(), String>;
+
+struct Registered<M> {
+    pass: Box<dyn Pass<M>>,
+    enabled: bool,
+}
+
+pub struct PassManager<M> {
+    passes: Vec<Registered<M>>,
+    /// Checks the module after every pass that ran
+    verifier: Option<Verifier<M>>,
+    print_after: Vec<String>,
+    timings: Vec<PassTiming>,
+}
+
+impl<M: fmt::Display> PassManager<M> {
+    pub fn new() -> Self {
+        PassManager {
+            passes: Vec::new(),
+            verifier: None,
+            print_after: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    /// Check the module with `verifier` after each pass
+    pub fn with_verifier(verifier: Verifier<M>) -> Self {
+        PassManager {
+            verifier: Some(verifier),
+            ..Self::new()
+        }
+    }
+
+    /// Register a pass to run after those already registered. Disabled
+    /// passes can be enabled by name later.
+    pub fn add(&mut self, pass: impl Pass<M> + 'static, enabled: bool) {
+        self.passes.push(Registered {
+            pass: Box::new(pass),
+            enabled,
+        });
+    }
+
+    /// The names of the registered passes, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|registered| registered.pass.name()).collect()
+    }
+
+    pub fn has_pass(&self, name: &str) -> bool {
+        self.passes.iter().any(|registered| registered.pass.name() == name)
+    }
+
+    /// Enable or disable every pass called `name`; returns whether there
+    /// is one
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for registered in &mut self.passes {
+            if registered.pass.name() == name {
+                registered.enabled = enabled;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Print the module to stdout each time the pass `name` has run
+    pub fn print_after(&mut self, name: &str) {
+        self.print_after.push(name.to_string());
+    }
+
+    /// Run the enabled passes in order, returning their warnings
+    pub fn run(&mut self, module: &mut M) -> Result<Vec<Diagnostic>, PassError> {
+        let mut warnings = Vec::new();
+        for registered in &mut self.passes {
+            if !registered.enabled {
+                continue;
+            }
+            let name = registered.pass.name();
+            let start = Instant::now();
+            let diagnostics = registered.pass.run(module);
+            self.timings.push(PassTiming {
+                name,
+                duration: start.elapsed(),
+            });
+
+            let (errors, pass_warnings): (Vec<_>, Vec<_>) = diagnostics
+                .into_iter()
+                .partition(|diagnostic| diagnostic.severity == Severity::Error);
+            if !errors.is_empty() {
+                return Err(PassError::Diagnostics(errors));
+            }
+            warnings.extend(pass_warnings);
+            if let Some(verifier) = self.verifier {
+                verifier(module).map_err(|message| PassError::Invalid { pass: name, message })?;
+            }
+            if self.print_after.iter().any(|pass| pass == name) {
+                println!("; after {}", name);
+                print!("{}", module);
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// How long each pass took, in the order they ran
+    pub fn timings(&self) -> &[PassTiming] {
+        &self.timings
+    }
+}
+
+impl<M: fmt::Display> Default for PassManager<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codes;
+
+    /// The values the passes appended, in order
+    #[derive(Default)]
+    struct Trace(Vec<&'static str>);
+
+    impl fmt::Display for Trace {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "{}", self.0.join(" "))
+        }
+    }
+
+    /// Appends its name to the trace, then reports `diagnostics`
+    struct Record {
+        name: &'static str,
+        diagnostics: Vec<Diagnostic>,
+    }
+
+    impl Record {
+        fn new(name: &'static str) -> Self {
+            Record { name, diagnostics: Vec::new() }
+        }
+    }
+
+    impl Pass<Trace> for Record {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn run(&mut self, module: &mut Trace) -> Vec<Diagnostic> {
+            module.0.push(self.name);
+            std::mem::take(&mut self.diagnostics)
+        }
+    }
+
+    fn manager() -> PassManager<Trace> {
+        let mut manager = PassManager::new();
+        manager.add(Record::new("fold"), true);
+        manager.add(Record::new("inline"), false);
+        manager.add(Record::new("dce"), true);
+        manager
+    }
+
+    fn trace(mut manager: PassManager<Trace>) -> Vec<&'static str> {
+        let mut module = Trace::default();
+        manager.run(&mut module).unwrap();
+        module.0
+    }
+
+    #[test]
+    fn runs_enabled_passes_in_order() {
+        let manager = manager();
+        assert_eq!(manager.names(), ["fold", "inline", "dce"]);
+        assert_eq!(trace(manager), ["fold", "dce"]);
+    }
+
+    #[test]
+    fn passes_can_be_enabled_and_disabled_by_name() {
+        let mut manager = manager();
+        assert!(manager.set_enabled("inline", true));
+        assert!(manager.set_enabled("fold", false));
+        assert_eq!(trace(manager), ["inline", "dce"]);
+    }
+
+    #[test]
+    fn unknown_pass_names_are_reported() {
+        let mut manager = manager();
+        assert!(!manager.has_pass("licm"));
+        assert!(!manager.set_enabled("licm", true));
+        assert!(manager.has_pass("dce"));
+        assert_eq!(trace(manager), ["fold", "dce"]);
+    }
+
+    #[test]
+    fn errors_stop_later_passes_and_warnings_are_collected() {
+        let mut manager = PassManager::new();
+        let mut warn = Record::new("warn");
+        warn.diagnostics.push(Diagnostic::warning(codes::UNREACHABLE_CODE, "Unreachable".to_string()));
+        manager.add(warn, true);
+        let mut fail = Record::new("fail");
+        fail.diagnostics.push(Diagnostic::error(codes::INTEGER_OUT_OF_RANGE, "Too big".to_string()));
+        manager.add(fail, true);
+        manager.add(Record::new("never"), true);
+
+        let mut module = Trace::default();
+        match manager.run(&mut module) {
+            Err(PassError::Diagnostics(errors)) => assert_eq!(errors[0].message, "Too big"),
+            other => panic!("expected the error of `fail`, got {:?}", other),
+        }
+        assert_eq!(module.0, ["warn", "fail"]);
+
+        let mut manager = PassManager::new();
+        let mut warn = Record::new("warn");
+        warn.diagnostics.push(Diagnostic::warning(codes::UNREACHABLE_CODE, "Unreachable".to_string()));
+        manager.add(warn, true);
+        let warnings = manager.run(&mut Trace::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(manager.timings().len(), 1);
+    }
+
+    #[test]
+    fn the_verifier_names_the_pass_that_broke_the_module() {
+        let mut manager = PassManager::with_verifier(|module: &Trace| {
+            if module.0.contains(&"inline") {
+                Err("inlined".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        manager.add(Record::new("fold"), true);
+        manager.add(Record::new("inline"), true);
+        manager.add(Record::new("dce"), true);
+        let mut module = Trace::default();
+        match manager.run(&mut module) {
+            Err(PassError::Invalid { pass, message }) => assert_eq!((pass, message.as_str()), ("inline", "inlined")),
+            other => panic!("expected `inline` to be blamed, got {:?}", other),
+        }
+        assert_eq!(module.0, ["fold", "inline"]);
+    }
+}
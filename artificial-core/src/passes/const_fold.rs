@@ -0,0 +1,297 @@
+// artificial-language/artificial-core/src/passes/const_fold.rs
+//! Constant folding and propagation. Operators whose operands are known
+//! at compile time are evaluated with `ops`, exactly as the VM would.
+//! Values are propagated through phis along the edges that can execute, as
+//! in Wegman and Zadeck's sparse conditional constant propagation. Branches
+//! on known conditions are left to dead code elimination, which removes
+//! the arm that cannot run.
+use crate::diagnostic::Diagnostic;
+use crate::ir::*;
+use crate::ops::{self, OpError};
+use crate::pass_manager::Pass;
+use crate::span::Span;
+use std::collections::HashSet;
+
+/// Fold the constants of every function. Operations that are certain to
+/// fail when they run, such as a constant division by zero, are returned
+/// as errors.
+pub fn fold_constants(module: &mut IrModule) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    for function in std::iter::once(&mut module.main).chain(&mut module.functions) {
+        let analysis = Analysis::run(function);
+        errors.extend(analysis.errors(function));
+        analysis.rewrite(function);
+    }
+    errors
+}
+
+/// What is known about the value of a temporary
+#[derive(Debug, Clone, PartialEq)]
+enum Lattice {
+    /// Not computed yet; no executable code has defined it
+    Undefined,
+    Constant(Constant),
+    /// Different on different runs, or not known at compile time
+    Varying,
+}
+
+impl Lattice {
+    fn meet(self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undefined, other) => other.clone(),
+            (this, Lattice::Undefined) => this,
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == *b => Lattice::Constant(a),
+            _ => Lattice::Varying,
+        }
+    }
+}
+
+struct Analysis {
+    values: Vec<Lattice>,
+    executable: Vec<bool>,
+    edges: HashSet<(BlockId, BlockId)>,
+}
+
+impl Analysis {
+    /// Iterate to a fixpoint. Values only ever move from `Undefined`
+    /// towards `Varying`, so this terminates.
+    fn run(function: &IrFunction) -> Self {
+        let mut analysis = Analysis {
+            values: vec![Lattice::Undefined; function.temp_count as usize],
+            executable: vec![false; function.blocks.len()],
+            edges: HashSet::new(),
+        };
+        analysis.executable[0] = true;
+        let order = function.reverse_postorder();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in &order {
+                if !analysis.executable[id.index()] {
+                    continue;
+                }
+                let block = function.block(id);
+                for phi in &block.phis {
+                    let value = phi
+                        .incoming
+                        .iter()
+                        .filter(|(pred, _)| analysis.edges.contains(&(*pred, id)))
+                        .fold(Lattice::Undefined, |value, (_, temp)| {
+                            value.meet(&analysis.values[temp.index()])
+                        });
+                    changed |= analysis.update(phi.dest, value);
+                }
+                for instruction in &block.instructions {
+                    if let Some(dest) = instruction.dest {
+                        let value = analysis.evaluate(&instruction.kind);
+                        changed |= analysis.update(dest, value);
+                    }
+                }
+                for successor in analysis.successors(&block.terminator.kind) {
+                    changed |= analysis.edges.insert((id, successor));
+                    changed |= !std::mem::replace(&mut analysis.executable[successor.index()], true);
+                }
+                if let TerminatorKind::Next { item, .. } = block.terminator.kind {
+                    changed |= analysis.update(item, Lattice::Varying);
+                }
+            }
+        }
+        analysis
+    }
+
+    fn update(&mut self, temp: Temp, value: Lattice) -> bool {
+        if self.values[temp.index()] == value {
+            return false;
+        }
+        self.values[temp.index()] = value;
+        true
+    }
+
+    fn constant(&self, temp: Temp) -> Option<&Constant> {
+        match &self.values[temp.index()] {
+            Lattice::Constant(constant) => Some(constant),
+            _ => None,
+        }
+    }
+
+    /// The value of an instruction given what is known of its operands
+    fn evaluate(&self, kind: &InstructionKind) -> Lattice {
+        let folded = |result: Result<Constant, OpError>| match result {
+            Ok(constant) => Lattice::Constant(constant),
+            Err(_) => Lattice::Varying,
+        };
+        match kind {
+            InstructionKind::Const(constant) => Lattice::Constant(constant.clone()),
+            InstructionKind::IsNull(_)
+            | InstructionKind::Binary { .. }
+            | InstructionKind::Unary { .. }
+            | InstructionKind::Concat(_) => {
+                let operands = kind.operands();
+                if operands.iter().any(|op| self.values[op.index()] == Lattice::Varying) {
+                    return Lattice::Varying;
+                }
+                let Some(constants) = operands
+                    .iter()
+                    .map(|op| self.constant(*op))
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    return Lattice::Undefined;
+                };
+                match kind {
+                    InstructionKind::IsNull(_) => {
+                        Lattice::Constant(Constant::Boolean(*constants[0] == Constant::Null))
+                    }
+                    InstructionKind::Binary { op, .. } => {
+                        folded(ops::evaluate_binary(*op, constants[0], constants[1]))
+                    }
+                    InstructionKind::Unary { op, .. } => folded(ops::evaluate_unary(*op, constants[0])),
+                    _ => {
+                        let text = constants.into_iter().map(ops::to_text).collect();
+                        Lattice::Constant(Constant::String(text))
+                    }
+                }
+            }
+            _ => Lattice::Varying,
+        }
+    }
+
+    /// The successors a terminator can transfer control to
+    fn successors(&self, kind: &TerminatorKind) -> Vec<BlockId> {
+        match kind {
+            TerminatorKind::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => match &self.values[condition.index()] {
+                Lattice::Undefined => Vec::new(),
+                Lattice::Constant(Constant::Boolean(true)) => vec![*then_block],
+                Lattice::Constant(Constant::Boolean(false)) => vec![*else_block],
+                // Any other constant fails at run time; keep both edges
+                _ => vec![*then_block, *else_block],
+            },
+            other => other.successors(),
+        }
+    }
+
+    /// Operators in executable code whose constant operands make them fail
+    fn errors(&self, function: &IrFunction) -> Vec<Diagnostic> {
+        let mut errors = Vec::new();
+        for id in function.block_ids().filter(|id| self.executable[id.index()]) {
+            for instruction in &function.block(id).instructions {
+                let operands = instruction.kind.operands();
+                let constants: Option<Vec<&Constant>> =
+                    operands.iter().map(|op| self.constant(*op)).collect();
+                let result = match (&instruction.kind, constants.as_deref()) {
+                    (InstructionKind::Binary { op, .. }, Some([left, right])) => {
+                        ops::evaluate_binary(*op, left, right)
+                    }
+                    (InstructionKind::Unary { op, .. }, Some([operand])) => {
+                        ops::evaluate_unary(*op, operand)
+                    }
+                    _ => continue,
+                };
+                if let Err(error) = result {
+                    errors.push(
+                        Diagnostic::error(error.code, error.message)
+                            .with_span(instruction.span)
+                            .with_note(
+                                "the operands are known at compile time, so this fails every time it runs"
+                                    .to_string(),
+                            ),
+                    );
+                }
+            }
+        }
+        errors
+    }
+
+    fn rewrite(&self, function: &mut IrFunction) {
+        for id in function.block_ids().collect::<Vec<_>>() {
+            if !self.executable[id.index()] {
+                continue;
+            }
+            let block = std::mem::take(function.block_mut(id));
+            let mut instructions = Vec::with_capacity(block.instructions.len());
+
+            // Phis of a single known value become constants
+            let mut phis = Vec::with_capacity(block.phis.len());
+            for phi in block.phis {
+                match self.constant(phi.dest) {
+                    Some(constant) => instructions.push(constant_instruction(phi.dest, constant, phi.span)),
+                    None => phis.push(phi),
+                }
+            }
+
+            for mut instruction in block.instructions {
+                let known = instruction.dest.and_then(|dest| self.constant(dest));
+                match (known, &mut instruction.kind) {
+                    (Some(_), InstructionKind::Const(_)) => {}
+                    (Some(constant), kind) => *kind = InstructionKind::Const(constant.clone()),
+                    (None, InstructionKind::Concat(parts)) => {
+                        *parts = self.merge_constant_parts(function, parts, instruction.span, &mut instructions);
+                    }
+                    (None, _) => {}
+                }
+                instructions.push(instruction);
+            }
+
+            *function.block_mut(id) = BasicBlock {
+                phis,
+                instructions,
+                terminator: block.terminator,
+            };
+        }
+    }
+
+    /// Join runs of known parts of an interpolation into one constant,
+    /// emitted into `instructions`; returns the new parts
+    fn merge_constant_parts(
+        &self,
+        function: &mut IrFunction,
+        parts: &[Temp],
+        span: Span,
+        instructions: &mut Vec<Instruction>,
+    ) -> Vec<Temp> {
+        let mut merged = Vec::with_capacity(parts.len());
+        let mut run: Vec<Temp> = Vec::new();
+        let mut flush = |run: &mut Vec<Temp>, merged: &mut Vec<Temp>| {
+            if run.len() > 1 {
+                let text = run.iter().map(|part| ops::to_text(self.constant(*part).unwrap())).collect();
+                let dest = function.new_temp();
+                instructions.push(constant_instruction(dest, &Constant::String(text), span));
+                merged.push(dest);
+            } else {
+                merged.append(run);
+            }
+            run.clear();
+        };
+        for &part in parts {
+            if self.constant(part).is_some() {
+                run.push(part);
+            } else {
+                flush(&mut run, &mut merged);
+                merged.push(part);
+            }
+        }
+        flush(&mut run, &mut merged);
+        merged
+    }
+}
+
+fn constant_instruction(dest: Temp, constant: &Constant, span: Span) -> Instruction {
+    Instruction::new(Some(dest), InstructionKind::Const(constant.clone()), span)
+}
+
+/// `fold_constants` as a pass
+pub struct ConstantFolding;
+
+impl Pass<IrModule> for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "const-fold"
+    }
+
+    fn run(&mut self, module: &mut IrModule) -> Vec<Diagnostic> {
+        fold_constants(module)
+    }
+}
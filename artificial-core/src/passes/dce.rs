@@ -0,0 +1,299 @@
+// artificial-language/artificial-core/src/passes/dce.rs
+//! Dead code elimination. Branches on constant conditions become jumps,
+//! blocks nothing can reach are dropped, values nothing uses are no longer
+//! computed, and functions `main` never calls are removed. Every slot and
+//! instruction saved here is also saved in each bundled binary.
+use crate::diagnostic::Diagnostic;
+use crate::ir::*;
+use crate::lints;
+use crate::pass_manager::Pass;
+use crate::span::Span;
+
+/// Remove the dead code of every function, returning `unreachable_code`
+/// warnings for user code behind conditions that are always true or false.
+/// Code after a `return` is reported by the resolver instead.
+pub fn eliminate_dead_code(module: &mut IrModule) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    for function in std::iter::once(&mut module.main).chain(&mut module.functions) {
+        warnings.extend(fold_constant_branches(function));
+        let order = function.reverse_postorder();
+        function.reorder_blocks(&order);
+        // Phis lose their inputs from dropped blocks
+        function.remove_trivial_phis();
+        merge_straight_lines(function);
+        remove_unused_values(function);
+    }
+    remove_uncalled_functions(module);
+    warnings
+}
+
+/// Turn branches on constant booleans into jumps and warn about the code
+/// only the untaken side could reach
+fn fold_constant_branches(function: &mut IrFunction) -> Vec<Diagnostic> {
+    // The value of each constant condition and where it came from. Folding
+    // has made null checks constants too, so the label below does not say
+    // which way the condition goes: `if let` branches on `is_null`.
+    let mut constants: Vec<Option<(bool, Span)>> = vec![None; function.temp_count as usize];
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let (Some(dest), InstructionKind::Const(Constant::Boolean(value))) =
+                (instruction.dest, &instruction.kind)
+            {
+                constants[dest.index()] = Some((*value, instruction.span));
+            }
+        }
+    }
+
+    let reachable_before = reachable(function);
+    // The untaken target of each folded branch, the condition's span and
+    // the branch's
+    let mut untaken_edges = Vec::new();
+    for id in function.block_ids() {
+        let TerminatorKind::Branch {
+            condition,
+            then_block,
+            else_block,
+        } = function.block(id).terminator.kind
+        else {
+            continue;
+        };
+        let Some((value, span)) = constants[condition.index()] else {
+            continue;
+        };
+        let (taken, untaken) = if value { (then_block, else_block) } else { (else_block, then_block) };
+        if untaken != taken {
+            for phi in &mut function.block_mut(untaken).phis {
+                phi.incoming.retain(|(pred, _)| *pred != id);
+            }
+        }
+        let terminator = &mut function.block_mut(id).terminator;
+        terminator.kind = TerminatorKind::Jump(taken);
+        untaken_edges.push((id, untaken, span, terminator.span));
+    }
+
+    let reachable_after = reachable(function);
+    let mut reported = vec![false; function.blocks.len()];
+    let mut warnings = Vec::new();
+    for (from, untaken, condition_span, branch_span) in untaken_edges {
+        // Branches inside code that is itself dead are covered by the
+        // warning for the outer branch. A branch a macro wrote may depend
+        // on its arguments, and the user cannot change it at the call site.
+        if !reachable_after[from.index()] || branch_span.expansion.is_some() {
+            continue;
+        }
+        let mut span: Option<Span> = None;
+        let mut has_code = false;
+        let mut stack = vec![untaken];
+        while let Some(id) = stack.pop() {
+            let index = id.index();
+            if reachable_after[index] || !reachable_before[index] || reported[index] {
+                continue;
+            }
+            reported[index] = true;
+            let block = function.block(id);
+            for instruction in &block.instructions {
+                // A lone constant, such as the `null` of an `if` without
+                // `else`, is not worth a warning
+                has_code |= !matches!(instruction.kind, InstructionKind::Const(_));
+                span = Some(match span {
+                    Some(span) => cover(span, instruction.span),
+                    None => instruction.span,
+                });
+            }
+            stack.extend(block.terminator.kind.successors());
+        }
+        if let (true, Some(span)) = (has_code, span) {
+            let warning = lints::UNREACHABLE_CODE
+                .warning("Unreachable code".to_string(), span)
+                .with_label(condition_span, "this condition is known at compile time".to_string());
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+fn reachable(function: &IrFunction) -> Vec<bool> {
+    let mut reachable = vec![false; function.blocks.len()];
+    for id in function.reverse_postorder() {
+        reachable[id.index()] = true;
+    }
+    reachable
+}
+
+/// The smallest span that covers both
+fn cover(a: Span, b: Span) -> Span {
+    if b.start < a.start {
+        b.to(a)
+    } else {
+        a.to(b)
+    }
+}
+
+/// Append each block to the block that jumps to it when that is its only
+/// predecessor, which joins the chains of empty blocks folded branches
+/// leave behind
+fn merge_straight_lines(function: &mut IrFunction) {
+    let mut predecessors = function.predecessors();
+    let mut merged = false;
+    for id in function.block_ids() {
+        while let TerminatorKind::Jump(next) = function.block(id).terminator.kind {
+            let only_predecessor = predecessors[next.index()] == [id];
+            if next == id || next == BlockId::ENTRY || !only_predecessor {
+                break;
+            }
+            let block = std::mem::take(function.block_mut(next));
+            // A single predecessor leaves no phis after trivial phi removal
+            debug_assert!(block.phis.is_empty(), "phi in {} with one predecessor", next);
+            for successor in block.terminator.kind.successors() {
+                for pred in &mut predecessors[successor.index()] {
+                    if *pred == next {
+                        *pred = id;
+                    }
+                }
+                for phi in &mut function.block_mut(successor).phis {
+                    for (pred, _) in &mut phi.incoming {
+                        if *pred == next {
+                            *pred = id;
+                        }
+                    }
+                }
+            }
+            predecessors[next.index()].clear();
+            let target = function.block_mut(id);
+            target.instructions.extend(block.instructions);
+            target.terminator = block.terminator;
+            merged = true;
+        }
+    }
+    if merged {
+        let order = function.reverse_postorder();
+        function.reorder_blocks(&order);
+    }
+}
+
+/// Remove phis and instructions without side effects whose values are
+/// never used. A value is used if an effect, a terminator or another used
+/// value reads it, so loop phis that only feed each other go too, along
+/// with the local slots and copies they would need.
+fn remove_unused_values(function: &mut IrFunction) {
+    let temp_count = function.temp_count as usize;
+    let mut operands: Vec<Vec<Temp>> = vec![Vec::new(); temp_count];
+    let mut worklist = Vec::new();
+    for block in &function.blocks {
+        for phi in &block.phis {
+            operands[phi.dest.index()] = phi.incoming.iter().map(|(_, value)| *value).collect();
+        }
+        for instruction in &block.instructions {
+            match instruction.dest {
+                Some(dest) if !instruction.kind.has_side_effects() => {
+                    operands[dest.index()] = instruction.kind.operands();
+                }
+                _ => worklist.extend(instruction.kind.operands()),
+            }
+        }
+        worklist.extend(block.terminator.kind.operands());
+    }
+
+    let mut used = vec![false; temp_count];
+    while let Some(temp) = worklist.pop() {
+        if !std::mem::replace(&mut used[temp.index()], true) {
+            worklist.append(&mut operands[temp.index()]);
+        }
+    }
+
+    for block in &mut function.blocks {
+        block.phis.retain(|phi| used[phi.dest.index()]);
+        block.instructions.retain(|instruction| match instruction.dest {
+            Some(dest) => used[dest.index()] || instruction.kind.has_side_effects(),
+            None => true,
+        });
+    }
+}
+
+/// Drop the functions `main` cannot reach through calls, renumbering the
+/// calls to those that are left
+fn remove_uncalled_functions(module: &mut IrModule) {
+    let mut called = vec![false; module.functions.len()];
+    let mut worklist = module.main.callees();
+    while let Some(index) = worklist.pop() {
+        if !std::mem::replace(&mut called[index], true) {
+            worklist.extend(module.functions[index].callees());
+        }
+    }
+    if called.iter().all(|&called| called) {
+        return;
+    }
+
+    let mut renumbered = Vec::with_capacity(called.len());
+    let mut next = 0;
+    for &called in &called {
+        renumbered.push(called.then(|| {
+            next += 1;
+            next - 1
+        }));
+    }
+    let mut index = 0;
+    module.functions.retain(|_| {
+        index += 1;
+        called[index - 1]
+    });
+    for function in std::iter::once(&mut module.main).chain(&mut module.functions) {
+        for block in &mut function.blocks {
+            for instruction in &mut block.instructions {
+                if let InstructionKind::Call { function, .. } = &mut instruction.kind {
+                    *function = renumbered[*function].expect("a called function calls a removed one");
+                }
+            }
+        }
+    }
+}
+
+/// `eliminate_dead_code` as a pass
+pub struct DeadCodeElimination;
+
+impl Pass<IrModule> for DeadCodeElimination {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&mut self, module: &mut IrModule) -> Vec<Diagnostic> {
+        eliminate_dead_code(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::lowering::{lower_module, LoweringOptions};
+    use crate::macros::MacroExpander;
+    use crate::parser::Parser;
+    use crate::passes::fold_constants;
+
+    /// The `unreachable_code` warnings for `source` after folding
+    fn unreachable_warnings(source: &str) -> Vec<Diagnostic> {
+        let (tokens, _) = Lexer::new(source).tokenize();
+        let tokens = MacroExpander::new().expand(tokens).unwrap();
+        let (ast, errors) = Parser::new(tokens).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut module = lower_module(ast, &LoweringOptions::default());
+        assert!(fold_constants(&mut module).is_empty());
+        eliminate_dead_code(&mut module)
+    }
+
+    #[test]
+    fn reports_branches_the_user_wrote() {
+        let warnings = unreachable_warnings("if false { accrete 1 }\nmacro m(x) => { accrete x }\nif false { m(2) }");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|warning| warning.lint == Some("unreachable_code")));
+    }
+
+    #[test]
+    fn ignores_branches_a_macro_wrote() {
+        let warnings = unreachable_warnings(
+            "macro log(on, msg) => { if on { accrete msg } }\nlog(false, \"b\")\n\
+             macro safe_div(a, b) => { if b == 0 { 0 } else { a / b } }\naccrete safe_div(1, 0)",
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
@@ -0,0 +1,274 @@
+// artificial-language/artificial-core/src/passes/inline.rs
+//! Function inlining. Calls to small functions are replaced by a copy of
+//! the callee's blocks, which saves the VM a call frame and lets constant
+//! folding see the arguments. The copies keep the callee's spans, so a
+//! runtime error in inlined code still points into the function it came
+//! from.
+use crate::diagnostic::Diagnostic;
+use crate::ir::*;
+use crate::pass_manager::Pass;
+use crate::passes::{eliminate_dead_code, fold_constants};
+
+/// The size of the largest function inlined unless told otherwise
+pub const DEFAULT_INLINE_THRESHOLD: usize = 20;
+
+/// The threshold under `-Os`: functions about as small as the code that
+/// calls them
+pub const SIZE_INLINE_THRESHOLD: usize = 4;
+
+/// Settings for `inline_functions`
+#[derive(Debug, Clone)]
+pub struct InlineOptions {
+    /// Inline functions of at most this many instructions and phis,
+    /// parameters not counted; 0 turns inlining off
+    pub threshold: usize,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        InlineOptions {
+            threshold: DEFAULT_INLINE_THRESHOLD,
+        }
+    }
+}
+
+/// Inline the calls to small functions, returning how many were inlined.
+/// Functions that can call themselves, directly or through others, are
+/// never inlined. Functions left uncalled are removed by dead code
+/// elimination.
+pub fn inline_functions(module: &mut IrModule, options: &InlineOptions) -> usize {
+    if options.threshold == 0 {
+        return 0;
+    }
+    let calls: Vec<Vec<usize>> = module.functions.iter().map(IrFunction::callees).collect();
+    let recursive = recursive_functions(&calls);
+    let mut inlinable = vec![false; module.functions.len()];
+    let mut inlined = 0;
+    // Callees come first, so each function is measured with its own calls
+    // already inlined and no call is ever inlined twice
+    for index in bottom_up(&calls) {
+        // Not inlinable yet, so the placeholder is never read
+        let placeholder = IrFunction::new(String::new(), 0, module.functions[index].span);
+        let mut function = std::mem::replace(&mut module.functions[index], placeholder);
+        inlined += inline_calls(&mut function, &module.functions, &inlinable);
+        inlinable[index] = !recursive[index]
+            && size(&function) <= options.threshold
+            && !returns_inside_loop(&function);
+        module.functions[index] = function;
+    }
+    inlined + inline_calls(&mut module.main, &module.functions, &inlinable)
+}
+
+/// `inline_functions` as a pass. Inlined code is folded and cleaned up
+/// again with the caller's arguments.
+pub struct Inlining {
+    options: InlineOptions,
+}
+
+impl Inlining {
+    pub fn new() -> Self {
+        Inlining {
+            options: InlineOptions::default(),
+        }
+    }
+
+    pub fn with_options(options: InlineOptions) -> Self {
+        Inlining { options }
+    }
+}
+
+impl Default for Inlining {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass<IrModule> for Inlining {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    /// Problems were reported by the passes before, where the user wrote
+    /// the code. Operations that fail for the arguments of one call are
+    /// left to fail at run time, when that call is reached.
+    fn run(&mut self, module: &mut IrModule) -> Vec<Diagnostic> {
+        if inline_functions(module, &self.options) > 0 {
+            fold_constants(module);
+            eliminate_dead_code(module);
+        }
+        Vec::new()
+    }
+}
+
+/// The functions in an order where every function comes after those it
+/// calls, except along cycles
+fn bottom_up(calls: &[Vec<usize>]) -> Vec<usize> {
+    let mut visited = vec![false; calls.len()];
+    let mut order = Vec::with_capacity(calls.len());
+    for root in 0..calls.len() {
+        if std::mem::replace(&mut visited[root], true) {
+            continue;
+        }
+        let mut stack = vec![(root, calls[root].clone())];
+        while let Some((function, pending)) = stack.last_mut() {
+            match pending.pop() {
+                Some(next) if !visited[next] => {
+                    visited[next] = true;
+                    stack.push((next, calls[next].clone()));
+                }
+                Some(_) => {}
+                None => {
+                    order.push(*function);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Whether each function can reach a call to itself
+fn recursive_functions(calls: &[Vec<usize>]) -> Vec<bool> {
+    (0..calls.len())
+        .map(|function| {
+            let mut visited = vec![false; calls.len()];
+            let mut worklist = calls[function].clone();
+            while let Some(callee) = worklist.pop() {
+                if callee == function {
+                    return true;
+                }
+                if !std::mem::replace(&mut visited[callee], true) {
+                    worklist.extend(&calls[callee]);
+                }
+            }
+            false
+        })
+        .collect()
+}
+
+fn size(function: &IrFunction) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| {
+            let instructions = block
+                .instructions
+                .iter()
+                .filter(|instruction| !matches!(instruction.kind, InstructionKind::Param(_)))
+                .count();
+            block.phis.len() + instructions
+        })
+        .sum()
+}
+
+/// Whether the function can return from inside a `for` loop. The VM
+/// drops such a loop's iteration when the call frame ends; inlined, the
+/// return would become a jump that leaves it behind.
+fn returns_inside_loop(function: &IrFunction) -> bool {
+    function.block_ids().any(|header| {
+        let TerminatorKind::Next { body, .. } = function.block(header).terminator.kind else {
+            return false;
+        };
+        // The loop is what the body reaches before coming back to the header
+        let mut visited = vec![false; function.blocks.len()];
+        visited[header.index()] = true;
+        let mut stack = vec![body];
+        while let Some(id) = stack.pop() {
+            if std::mem::replace(&mut visited[id.index()], true) {
+                continue;
+            }
+            let terminator = &function.block(id).terminator.kind;
+            if matches!(terminator, TerminatorKind::Return(_)) {
+                return true;
+            }
+            stack.extend(terminator.successors());
+        }
+        false
+    })
+}
+
+/// Inline the calls of `caller` to inlinable functions
+fn inline_calls(caller: &mut IrFunction, functions: &[IrFunction], inlinable: &[bool]) -> usize {
+    let mut inlined = 0;
+    // Inlining adds blocks at the end, which are scanned in turn: the
+    // callee's copies and the rest of the block after the call
+    let mut index = 0;
+    while index < caller.blocks.len() {
+        let id = BlockId(index as u32);
+        let call = caller.block(id).instructions.iter().position(|instruction| {
+            matches!(instruction.kind, InstructionKind::Call { function, .. } if inlinable[function])
+        });
+        if let Some(at) = call {
+            inline_call(caller, id, at, functions);
+            inlined += 1;
+        }
+        index += 1;
+    }
+    inlined
+}
+
+/// Replace the call at `at` in block `id` with a copy of the callee. The
+/// block jumps to the copy of its entry, each `return` jumps to the rest
+/// of the block, and a phi there receives the returned value.
+fn inline_call(caller: &mut IrFunction, id: BlockId, at: usize, functions: &[IrFunction]) {
+    let rest = caller.split_block(id, at);
+    let call = caller.block_mut(rest).instructions.remove(0);
+    let InstructionKind::Call { function, args } = call.kind else {
+        unreachable!("inlining a non-call instruction");
+    };
+    let callee = &functions[function];
+
+    let temp_base = caller.temp_count;
+    caller.temp_count += callee.temp_count;
+    let mut temps: Vec<Temp> = (0..callee.temp_count).map(|temp| Temp(temp_base + temp)).collect();
+    let block_base = caller.blocks.len() as u32;
+    let block = |id: BlockId| BlockId(block_base + id.0);
+    for instruction in callee.blocks.iter().flat_map(|block| &block.instructions) {
+        if let (Some(dest), InstructionKind::Param(index)) = (instruction.dest, &instruction.kind) {
+            temps[dest.index()] = args[*index as usize];
+        }
+    }
+    let temp = |temp: &mut Temp| *temp = temps[temp.index()];
+
+    let mut returns = Vec::new();
+    for (index, original) in callee.blocks.iter().enumerate() {
+        let mut copy = original.clone();
+        for phi in &mut copy.phis {
+            temp(&mut phi.dest);
+            for (pred, value) in &mut phi.incoming {
+                *pred = block(*pred);
+                temp(value);
+            }
+        }
+        copy.instructions
+            .retain(|instruction| !matches!(instruction.kind, InstructionKind::Param(_)));
+        for instruction in &mut copy.instructions {
+            if let Some(dest) = &mut instruction.dest {
+                temp(dest);
+            }
+            instruction.kind.operands_mut().into_iter().for_each(temp);
+        }
+        let terminator = &mut copy.terminator.kind;
+        terminator.operands_mut().into_iter().for_each(temp);
+        for successor in terminator.successors_mut() {
+            *successor = block(*successor);
+        }
+        if let TerminatorKind::Next { item, .. } = terminator {
+            temp(item);
+        }
+        if let TerminatorKind::Return(value) = *terminator {
+            returns.push((BlockId(block_base + index as u32), value));
+            *terminator = TerminatorKind::Jump(rest);
+        }
+        caller.blocks.push(copy);
+    }
+
+    caller.block_mut(id).terminator = Terminator::new(TerminatorKind::Jump(block(BlockId::ENTRY)), call.span);
+    if let Some(dest) = call.dest {
+        caller.block_mut(rest).phis.push(Phi {
+            dest,
+            incoming: returns,
+            span: call.span,
+        });
+    }
+}
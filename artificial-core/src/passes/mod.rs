@@ -0,0 +1,35 @@
+// artificial-language/artificial-core/src/passes/mod.rs
+//! Optimization passes over the IR. Every pass takes valid IR and leaves
+//! it valid, so passes can run in any order.
+use crate::ir::IrModule;
+use crate::pass_manager::{OptLevel, PassManager};
+
+pub mod const_fold;
+pub mod dce;
+pub mod inline;
+
+pub use const_fold::{fold_constants, ConstantFolding};
+pub use dce::{eliminate_dead_code, DeadCodeElimination};
+pub use inline::{
+    inline_functions, InlineOptions, Inlining, DEFAULT_INLINE_THRESHOLD, SIZE_INLINE_THRESHOLD,
+};
+
+/// Every IR pass in the order they run, enabled as `level` asks. The IR is
+/// validated after each pass.
+pub fn ir_pass_manager(level: OptLevel, inline_threshold: Option<usize>) -> PassManager<IrModule> {
+    let mut manager = PassManager::with_verifier(|module| {
+        crate::validator::validate(module).map_err(|e| e.to_string())
+    });
+    let threshold = inline_threshold.unwrap_or(match level {
+        OptLevel::Os => SIZE_INLINE_THRESHOLD,
+        _ => DEFAULT_INLINE_THRESHOLD,
+    });
+    let optimize = level != OptLevel::O0;
+    manager.add(ConstantFolding, optimize);
+    manager.add(DeadCodeElimination, optimize);
+    manager.add(
+        Inlining::with_options(InlineOptions { threshold }),
+        matches!(level, OptLevel::O2 | OptLevel::Os),
+    );
+    manager
+}
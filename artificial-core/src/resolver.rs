@@ -0,0 +1,810 @@
+// artificial-language/artificial-core/src/resolver.rs
+use crate::ast::*;
+use crate::codes::{self, ErrorCode};
+use crate::diagnostic::{Diagnostic, Suggestion};
+use crate::lexer::KEYWORDS;
+use crate::lints;
+use crate::span::Span;
+use crate::suggest::{closest, edit_distance};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A secondary position that explains an error, such as a declaration
+#[derive(Debug, Clone)]
+pub struct RelatedLocation {
+    pub message: String,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct ResolveError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+    pub related: Vec<RelatedLocation>,
+    pub help: Option<String>,
+    /// Edits that would fix the error
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl ResolveError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let mut diagnostic =
+            Diagnostic::error(self.code, self.message.clone()).with_span(self.span);
+        for related in &self.related {
+            diagnostic = diagnostic.with_label(related.span, related.message.clone());
+        }
+        diagnostic.help = self.help.clone();
+        diagnostic.suggestions = self.suggestions.clone();
+        diagnostic
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Resolve error at {}: {}",
+            self.span, self.message
+        )?;
+        for related in &self.related {
+            write!(f, "; {} at {}", related.message, related.span)?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, " (help: {})", help)?;
+        }
+        Ok(())
+    }
+}
+
+/// A name bound in some scope
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: Identifier,
+    pub mutable: bool,
+    pub kind: SymbolKind,
+    /// Where the name is bound
+    pub span: Span,
+    /// Whether the value is ever read
+    pub used: bool,
+}
+
+impl Symbol {
+    pub fn new(name: Identifier, mutable: bool, kind: SymbolKind, span: Span) -> Self {
+        Symbol {
+            name,
+            mutable,
+            kind,
+            span,
+            used: false,
+        }
+    }
+
+    /// Bindings the user wrote and did not mark as intentionally unused
+    /// with a leading `_`; names from macro expansions are not theirs to fix
+    fn is_linted(&self) -> bool {
+        self.name.expansion.is_none() && !self.name.name.starts_with('_')
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Let,
+    LoopVariable,
+    Parameter,
+}
+
+/// Lexically nested scopes of bound names
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    scopes: Vec<Vec<Symbol>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable { scopes: vec![Vec::new()] }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    /// Close the innermost scope, returning the symbols bound in it
+    pub fn pop_scope(&mut self) -> Vec<Symbol> {
+        self.scopes.pop().unwrap_or_default()
+    }
+
+    pub fn define(&mut self, symbol: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(symbol);
+        }
+    }
+
+    /// Find the innermost binding of `name`
+    pub fn lookup(&self, name: &Identifier) -> Option<&Symbol> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|symbol| &symbol.name == name)
+    }
+
+    /// Find the innermost binding of `name` and record that it is read
+    pub fn mark_used(&mut self, name: &Identifier) -> Option<&Symbol> {
+        let symbol = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|symbol| &symbol.name == name)?;
+        symbol.used = true;
+        Some(symbol)
+    }
+
+    /// Every binding in scope, innermost first
+    pub fn visible(&self) -> impl Iterator<Item = &Symbol> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev())
+    }
+}
+
+/// Where a parameter gets its value from in a particular call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamSource {
+    /// The call argument at this index
+    Argument(usize),
+    /// The parameter's default value
+    Default,
+    /// A list of the call arguments at these indices
+    Rest(Vec<usize>),
+}
+
+/// Match the arguments of a call to `name` against its parameters,
+/// returning one source per parameter
+pub fn bind_arguments(
+    name: &str,
+    params: &[AstParam],
+    args: &[AstArgument],
+    span: Span,
+) -> Result<Vec<ParamSource>, Box<ResolveError>> {
+    let error = |message: String, span: Span| {
+        Box::new(ResolveError {
+            code: codes::ARGUMENT_MISMATCH,
+            message,
+            span,
+            related: Vec::new(),
+            help: Some(parameter_list(name, params)),
+            suggestions: Vec::new(),
+        })
+    };
+
+    let mut sources: Vec<Option<ParamSource>> = vec![None; params.len()];
+    let variadic = params.iter().position(|p| p.variadic);
+    let positional_slots = variadic.unwrap_or(params.len());
+    let mut positional = 0;
+    let mut seen_named = false;
+
+    for (index, arg) in args.iter().enumerate() {
+        match &arg.name {
+            None if seen_named => {
+                return Err(error(
+                    "Positional argument after a named argument".to_string(),
+                    arg.span,
+                ))
+            }
+            None if positional < positional_slots => {
+                sources[positional] = Some(ParamSource::Argument(index));
+                positional += 1;
+            }
+            None => match variadic {
+                Some(rest) => match &mut sources[rest] {
+                    Some(ParamSource::Rest(indices)) => indices.push(index),
+                    slot => *slot = Some(ParamSource::Rest(vec![index])),
+                },
+                None => {
+                    return Err(error(
+                        format!(
+                            "Function `{}` takes {} argument(s), found {}",
+                            name,
+                            params.len(),
+                            args.len()
+                        ),
+                        arg.span,
+                    ))
+                }
+            },
+            Some(arg_name) => {
+                seen_named = true;
+                let param = params
+                    .iter()
+                    .position(|p| !p.variadic && &p.name.name == arg_name)
+                    .ok_or_else(|| {
+                        error(
+                            format!("Function `{}` has no parameter named `{}`", name, arg_name),
+                            arg.span,
+                        )
+                    })?;
+                if sources[param].is_some() {
+                    return Err(error(
+                        format!("Argument `{}` is given more than once", arg_name),
+                        arg.span,
+                    ));
+                }
+                sources[param] = Some(ParamSource::Argument(index));
+            }
+        }
+    }
+
+    params
+        .iter()
+        .zip(sources)
+        .map(|(param, source)| match source {
+            Some(source) => Ok(source),
+            None if param.variadic => Ok(ParamSource::Rest(Vec::new())),
+            None if param.default.is_some() => Ok(ParamSource::Default),
+            None => Err(error(
+                format!("Missing argument `{}` in call to `{}`", param.name, name),
+                span,
+            )),
+        })
+        .collect()
+}
+
+/// Help text naming every parameter of `name`
+fn parameter_list(name: &str, params: &[AstParam]) -> String {
+    if params.is_empty() {
+        return format!("`{}` takes no parameters", name);
+    }
+    let names: Vec<String> = params
+        .iter()
+        .map(|p| {
+            if p.variadic {
+                format!("`...{}`", p.name)
+            } else {
+                format!("`{}`", p.name)
+            }
+        })
+        .collect();
+    format!("valid parameters of `{}` are {}", name, names.join(", "))
+}
+
+/// Checks that every name used in a module is bound, before lowering,
+/// and collects lint warnings about bindings, functions that are never
+/// called and unreachable code
+pub struct Resolver<'a> {
+    symbols: SymbolTable,
+    functions: HashMap<&'a str, &'a AstFunction>,
+    /// Whether `return` is allowed at this point
+    in_function: bool,
+    /// The function being resolved, or `None` for the top level
+    caller: Option<String>,
+    /// Every caller and callee pair, for finding functions never called
+    calls: Vec<(Option<String>, String)>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new() -> Self {
+        Resolver {
+            symbols: SymbolTable::new(),
+            functions: HashMap::new(),
+            in_function: false,
+            caller: None,
+            calls: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Resolve `module`, returning the lint warnings found along the way
+    pub fn resolve(mut self, module: &'a AstModule) -> Result<Vec<Diagnostic>, Box<ResolveError>> {
+        // Functions are visible everywhere, including before their declaration
+        for function in &module.functions {
+            if let Some(previous) = self.functions.insert(&function.name, function) {
+                return Err(Box::new(ResolveError {
+                    code: codes::DUPLICATE_FUNCTION,
+                    message: format!("Function `{}` is declared more than once", function.name),
+                    span: function.name_span,
+                    related: vec![RelatedLocation {
+                        message: "first declared".to_string(),
+                        span: previous.name_span,
+                    }],
+                    help: None,
+                    suggestions: Vec::new(),
+                }));
+            }
+        }
+        for function in &module.functions {
+            self.resolve_function(function)?;
+        }
+        self.resolve_statements(&module.statements, None)?;
+        let globals = self.symbols.pop_scope();
+        self.report_unused(globals);
+        self.report_uncalled(&module.functions);
+        Ok(self.warnings)
+    }
+
+    /// Warn about every function the top level cannot reach through calls
+    fn report_uncalled(&mut self, functions: &[AstFunction]) {
+        let mut called: Vec<&str> = Vec::new();
+        let mut callers = vec![None];
+        while let Some(caller) = callers.pop() {
+            for (from, to) in &self.calls {
+                if from.as_deref() == caller && !called.contains(&to.as_str()) {
+                    called.push(to);
+                    callers.push(Some(to.as_str()));
+                }
+            }
+        }
+        for function in functions {
+            if called.contains(&function.name.as_str()) || function.name.starts_with('_') {
+                continue;
+            }
+            let warning = lints::UNUSED_FUNCTIONS
+                .warning(format!("Function `{}` is never called", function.name), function.name_span)
+                .with_help(format!(
+                    "if this is intentional, prefix it with an underscore: `_{}`",
+                    function.name
+                ))
+                .with_suggestion(Suggestion {
+                    message: "prefix it with an underscore".to_string(),
+                    span: function.name_span.shrink_to_start(),
+                    replacement: "_".to_string(),
+                });
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Function bodies see only their parameters and other functions
+    fn resolve_function(&mut self, function: &AstFunction) -> Result<(), Box<ResolveError>> {
+        let outer = std::mem::replace(&mut self.symbols, SymbolTable::new());
+        // Defaults are evaluated when the function is called, so calls in
+        // them count as calls from the function
+        self.caller = Some(function.name.clone());
+        for param in &function.params {
+            if let Some(default) = &param.default {
+                // Defaults are evaluated at the call site, where no
+                // parameter is bound yet
+                self.resolve_expression(default).map_err(|mut e| {
+                    e.help.get_or_insert_with(|| {
+                        "default values cannot refer to variables".to_string()
+                    });
+                    e
+                })?;
+            }
+        }
+        for param in &function.params {
+            self.define(Symbol::new(
+                param.name.clone(),
+                false,
+                SymbolKind::Parameter,
+                param.name_span,
+            ));
+        }
+        self.in_function = true;
+        let result = self.resolve_block(&function.body);
+        self.in_function = false;
+        self.caller = None;
+        let mut params = std::mem::replace(&mut self.symbols, outer);
+        self.report_unused(params.pop_scope());
+        result
+    }
+
+    /// Bind `symbol` in the innermost scope, warning when it hides an
+    /// earlier binding of the same name
+    fn define(&mut self, symbol: Symbol) {
+        if let Some(previous) = self.symbols.lookup(&symbol.name).filter(|_| symbol.is_linted()) {
+            let warning = lints::SHADOWED_BINDINGS
+                .warning(format!("`{}` shadows an earlier binding", symbol.name), symbol.span)
+                .with_label(previous.span, format!("previous binding of `{}`", symbol.name))
+                .with_help("consider a different name".to_string());
+            self.warnings.push(warning);
+        }
+        self.symbols.define(symbol);
+    }
+
+    /// Warn about every binding in `symbols` that is never read
+    fn report_unused(&mut self, symbols: Vec<Symbol>) {
+        for symbol in symbols {
+            if symbol.used || !symbol.is_linted() {
+                continue;
+            }
+            let what = match symbol.kind {
+                SymbolKind::Parameter => "parameter",
+                SymbolKind::Let | SymbolKind::LoopVariable => "variable",
+            };
+            let warning = lints::UNUSED_VARIABLES
+                .warning(format!("Unused {} `{}`", what, symbol.name), symbol.span)
+                .with_help(format!(
+                    "if this is intentional, prefix it with an underscore: `_{}`",
+                    symbol.name
+                ))
+                .with_suggestion(Suggestion {
+                    message: "prefix it with an underscore".to_string(),
+                    span: symbol.span.shrink_to_start(),
+                    replacement: "_".to_string(),
+                });
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Resolve the statements of a block and then its value, warning about
+    /// anything that follows a `return`
+    fn resolve_statements(
+        &mut self,
+        statements: &[AstStatement],
+        value: Option<&AstExpression>,
+    ) -> Result<(), Box<ResolveError>> {
+        for statement in statements {
+            self.resolve_statement(statement)?;
+        }
+        if let Some(value) = value {
+            self.resolve_expression(value)?;
+        }
+
+        let returns = statements
+            .iter()
+            .position(|stmt| matches!(stmt.kind, AstStatementKind::Return { .. }));
+        if let Some(index) = returns {
+            let unreachable = statements[index + 1..]
+                .iter()
+                .map(|stmt| stmt.span)
+                .chain(value.map(|value| value.span));
+            let first = unreachable.clone().next();
+            if let (Some(first), Some(last)) = (first, unreachable.last()) {
+                let warning = lints::UNREACHABLE_CODE
+                    .warning("Unreachable code".to_string(), first.to(last))
+                    .with_label(
+                        statements[index].span,
+                        "any code following this `return` is unreachable".to_string(),
+                    );
+                self.warnings.push(warning);
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn about the arms after a `_` arm, which lowering drops because
+    /// no value reaches them
+    fn report_arms_after_wildcard(&mut self, arms: &[AstMatchArm]) {
+        let wildcard = arms
+            .iter()
+            .position(|arm| matches!(arm.pattern, AstPattern::Wildcard(_)));
+        let Some(index) = wildcard.filter(|&index| arms[index].span.expansion.is_none()) else {
+            return;
+        };
+        if let (Some(first), Some(last)) = (arms.get(index + 1), arms.last()) {
+            let warning = lints::UNREACHABLE_CODE
+                .warning("Unreachable match arm".to_string(), first.span.to(last.span))
+                .with_label(
+                    arms[index].span,
+                    "this arm matches any value, so the arms after it are never reached".to_string(),
+                );
+            self.warnings.push(warning);
+        }
+    }
+
+    fn resolve_statement(&mut self, stmt: &AstStatement) -> Result<(), Box<ResolveError>> {
+        match &stmt.kind {
+            AstStatementKind::Error => Ok(()),
+            AstStatementKind::Accrete(expr) | AstStatementKind::Expression(expr) => {
+                self.resolve_expression(expr)
+            }
+            AstStatementKind::Assert { condition, message } => {
+                self.resolve_expression(condition)?;
+                if let Some(message) = message {
+                    self.resolve_expression(message)?;
+                }
+                Ok(())
+            }
+            AstStatementKind::AssertEq { left, right } => {
+                self.resolve_expression(left)?;
+                self.resolve_expression(right)
+            }
+            AstStatementKind::Let {
+                name,
+                name_span,
+                mutable,
+                value,
+            } => {
+                // The binding is not visible in its own initializer
+                self.resolve_expression(value)?;
+                self.define(Symbol::new(name.clone(), *mutable, SymbolKind::Let, *name_span));
+                Ok(())
+            }
+            AstStatementKind::Assign { name, value } => {
+                self.resolve_expression(value)?;
+                let symbol = self
+                    .symbols
+                    .lookup(name)
+                    .ok_or_else(|| self.unknown_variable(name, stmt.span))?;
+                if symbol.mutable {
+                    return Ok(());
+                }
+                let (declared, help, suggestions) = match symbol.kind {
+                    SymbolKind::Let => (
+                        format!("`{}` declared immutable", name),
+                        Some(format!("make it mutable with `let mut {}`", name)),
+                        vec![Suggestion {
+                            message: "declare the variable mutable".to_string(),
+                            span: symbol.span.shrink_to_start(),
+                            replacement: "mut ".to_string(),
+                        }],
+                    ),
+                    SymbolKind::LoopVariable => {
+                        (format!("`{}` bound by this loop", name), None, Vec::new())
+                    }
+                    SymbolKind::Parameter => {
+                        (format!("`{}` is a parameter", name), None, Vec::new())
+                    }
+                };
+                Err(Box::new(ResolveError {
+                    code: codes::ASSIGN_TO_IMMUTABLE,
+                    message: format!("Cannot assign to immutable variable `{}`", name),
+                    span: stmt.span,
+                    related: vec![RelatedLocation {
+                        message: declared,
+                        span: symbol.span,
+                    }],
+                    help,
+                    suggestions,
+                }))
+            }
+            AstStatementKind::Return { value } => {
+                if !self.in_function {
+                    return Err(Box::new(ResolveError {
+                        code: codes::RETURN_OUTSIDE_FUNCTION,
+                        message: "`return` outside of a function".to_string(),
+                        span: stmt.span,
+                        related: Vec::new(),
+                        help: None,
+                        suggestions: Vec::new(),
+                    }));
+                }
+                match value {
+                    Some(value) => self.resolve_expression(value),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &AstExpression) -> Result<(), Box<ResolveError>> {
+        match &expr.kind {
+            AstExpressionKind::StringLiteral(_)
+            | AstExpressionKind::IntegerLiteral(_)
+            | AstExpressionKind::BooleanLiteral(_)
+            | AstExpressionKind::NullLiteral
+            | AstExpressionKind::Error => Ok(()),
+            AstExpressionKind::Variable(name) => {
+                if self.symbols.mark_used(name).is_some() {
+                    Ok(())
+                } else {
+                    Err(self.unknown_variable(name, expr.span))
+                }
+            }
+            AstExpressionKind::Call { callee, args } => {
+                let function = *self.functions.get(callee.as_str()).ok_or_else(|| {
+                    let mut error = Box::new(ResolveError {
+                        code: codes::UNKNOWN_FUNCTION,
+                        message: format!("Unknown function `{}`", callee),
+                        span: expr.span,
+                        related: Vec::new(),
+                        help: None,
+                        suggestions: Vec::new(),
+                    });
+                    let mut names: Vec<&str> = self.functions.keys().copied().collect();
+                    names.sort_unstable();
+                    if let Some(found) = closest(callee, names) {
+                        // The callee is the first token of the call
+                        let span = Span {
+                            end: expr.span.start + callee.len(),
+                            ..expr.span
+                        };
+                        did_you_mean(&mut error, "function", found, span);
+                    }
+                    error
+                })?;
+                self.calls.push((self.caller.clone(), callee.clone()));
+                for arg in args {
+                    self.resolve_expression(&arg.value)?;
+                }
+                bind_arguments(callee, &function.params, args, expr.span).map_err(|mut e| {
+                    e.related.push(RelatedLocation {
+                        message: format!("`{}` declared", callee),
+                        span: function.name_span,
+                    });
+                    e
+                })?;
+                Ok(())
+            }
+            AstExpressionKind::Range {
+                start, end, step, ..
+            } => {
+                self.resolve_expression(start)?;
+                self.resolve_expression(end)?;
+                if let Some(step) = step {
+                    self.resolve_expression(step)?;
+                }
+                Ok(())
+            }
+            AstExpressionKind::Slice { target, range } => {
+                self.resolve_expression(target)?;
+                self.resolve_expression(range)
+            }
+            AstExpressionKind::Interpolation(parts) => {
+                parts.iter().try_for_each(|part| self.resolve_expression(part))
+            }
+            AstExpressionKind::Binary { left, right, .. } => {
+                self.resolve_expression(left)?;
+                self.resolve_expression(right)
+            }
+            AstExpressionKind::Unary { operand, .. } => self.resolve_expression(operand),
+            AstExpressionKind::Coalesce { value, fallback } => {
+                self.resolve_expression(value)?;
+                self.resolve_expression(fallback)
+            }
+            AstExpressionKind::Block(block) => self.resolve_block(block),
+            AstExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition)?;
+                self.resolve_block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.resolve_expression(else_branch)?;
+                }
+                Ok(())
+            }
+            AstExpressionKind::IfLet {
+                name,
+                name_span,
+                value,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(value)?;
+                self.symbols.push_scope();
+                self.define(Symbol::new(name.clone(), false, SymbolKind::Let, *name_span));
+                let result = self.resolve_block(then_branch);
+                let bound = self.symbols.pop_scope();
+                result?;
+                self.report_unused(bound);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_expression(else_branch)?;
+                }
+                Ok(())
+            }
+            AstExpressionKind::Match { scrutinee, arms } => {
+                self.resolve_expression(scrutinee)?;
+                for arm in arms {
+                    if let AstPattern::Literal(pattern) = &arm.pattern {
+                        self.resolve_expression(pattern)?;
+                    }
+                    self.resolve_expression(&arm.body)?;
+                }
+                self.report_arms_after_wildcard(arms);
+                Ok(())
+            }
+            AstExpressionKind::For {
+                variable,
+                variable_span,
+                iterable,
+                body,
+            } => {
+                self.resolve_expression(iterable)?;
+                self.symbols.push_scope();
+                self.define(Symbol::new(
+                    variable.clone(),
+                    false,
+                    SymbolKind::LoopVariable,
+                    *variable_span,
+                ));
+                let result = self.resolve_block(body);
+                let bound = self.symbols.pop_scope();
+                result?;
+                self.report_unused(bound);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reports `name` as unbound, suggesting a visible variable or a
+    /// keyword with a similar spelling
+    fn unknown_variable(&self, name: &Identifier, span: Span) -> Box<ResolveError> {
+        let mut error = Box::new(ResolveError {
+            code: codes::UNKNOWN_VARIABLE,
+            message: format!("Unknown variable `{}`", name),
+            span,
+            related: Vec::new(),
+            help: None,
+            suggestions: Vec::new(),
+        });
+        // Names introduced by a different macro expansion are not reachable
+        let variables = self
+            .symbols
+            .visible()
+            .filter(|symbol| symbol.name.expansion == name.expansion)
+            .map(|symbol| symbol.name.name.as_str());
+        let name_span = Span {
+            end: span.start + name.name.len(),
+            ..span
+        };
+        let variable = closest(&name.name, variables);
+        let keyword = closest(&name.name, KEYWORDS.iter().copied());
+        let distance = |found: &str| edit_distance(&name.name, found);
+        match (variable, keyword) {
+            (Some(variable), Some(keyword)) if distance(keyword) < distance(variable) => {
+                did_you_mean(&mut error, "keyword", keyword, name_span)
+            }
+            (Some(variable), _) => did_you_mean(&mut error, "variable", variable, name_span),
+            (None, Some(keyword)) => did_you_mean(&mut error, "keyword", keyword, name_span),
+            (None, None) => {}
+        }
+        error
+    }
+
+    fn resolve_block(&mut self, block: &AstBlock) -> Result<(), Box<ResolveError>> {
+        self.symbols.push_scope();
+        let result = self.resolve_statements(&block.statements, block.value.as_deref());
+        let bound = self.symbols.pop_scope();
+        result?;
+        self.report_unused(bound);
+        Ok(())
+    }
+}
+
+/// Add a "did you mean" help and the matching fix to `error`
+fn did_you_mean(error: &mut ResolveError, what: &str, name: &str, span: Span) {
+    error.help = Some(format!("did you mean the {} `{}`?", what, name));
+    error.suggestions.push(Suggestion {
+        message: format!("replace with `{}`", name),
+        span,
+        replacement: name.to_string(),
+    });
+}
+
+impl Default for Resolver<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::macros::MacroExpander;
+    use crate::parser::Parser;
+
+    /// The warnings resolving `source` produces
+    fn warnings(source: &str) -> Vec<Diagnostic> {
+        let (tokens, _) = Lexer::new(source).tokenize();
+        let tokens = MacroExpander::new().expand(tokens).unwrap();
+        let (ast, errors) = Parser::new(tokens).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        Resolver::new().resolve(&ast).unwrap()
+    }
+
+    #[test]
+    fn reports_match_arms_after_a_wildcard() {
+        let source = "accrete match 2 {\n    _ => \"many\",\n    2 => \"two\",\n    3 => \"three\",\n}";
+        let warnings = warnings(source);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        let warning = &warnings[0];
+        assert_eq!(warning.lint, Some("unreachable_code"));
+        let span = warning.span.unwrap();
+        assert_eq!(&source[span.start..span.end], "2 => \"two\",\n    3 => \"three\"");
+        assert_eq!(warning.labels[0].span.line, 2);
+    }
+
+    #[test]
+    fn accepts_a_wildcard_as_the_last_arm() {
+        assert!(warnings("accrete match 2 {\n    2 => \"two\",\n    _ => \"many\",\n}").is_empty());
+    }
+
+    #[test]
+    fn ignores_match_arms_a_macro_wrote() {
+        let source = "macro pick(x) => { match x { _ => 0, 1 => 1 } }\naccrete pick(1)";
+        assert!(warnings(source).is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+// artificial-language/artificial-core/src/span.rs
+use std::fmt;
+
+/// Identifies a source file; the file passed to the compiler is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(pub u32);
+
+/// A range of source text. `start` and `end` are byte offsets, `line` and
+/// `column` locate `start` for humans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    /// The macro expansion that wrote the text, as an index into
+    /// `Expansions`; text the user wrote has none
+    pub expansion: Option<usize>,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span {
+            file,
+            start,
+            end,
+            line,
+            column,
+            expansion: None,
+        }
+    }
+
+    /// The span from the start of `self` to the end of `other`. When one of
+    /// them was written by a macro and the other was not, their offsets are
+    /// unrelated, so the result is `self` inside the expansion;
+    /// `Expansions::join` covers the invocation instead.
+    pub fn to(self, other: Span) -> Span {
+        if self.expansion != other.expansion {
+            return Span {
+                expansion: self.expansion.or(other.expansion),
+                ..self
+            };
+        }
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    /// The empty span at the start of `self`, where text can be inserted
+    pub fn shrink_to_start(self) -> Span {
+        Span {
+            end: self.start,
+            ..self
+        }
+    }
+
+    /// Whether the span covers no text, as for end of input
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,367 @@
+// artificial-language/artificial-core/src/validator.rs
+//! Checks that IR is well formed: in SSA form, with consistent blocks,
+//! phis and calls. Lowering and later passes should only ever produce
+//! valid IR, so a failure here is a compiler bug, not a user error.
+use crate::ir::*;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct ValidationError {
+    pub function: String,
+    pub block: Option<BlockId>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "in `{}`, {}: {}", self.function, block, self.message),
+            None => write!(f, "in `{}`: {}", self.function, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Validate every function of a module
+pub fn validate(module: &IrModule) -> Result<(), ValidationError> {
+    validate_function(module, &module.main, true)?;
+    for function in &module.functions {
+        validate_function(module, function, false)?;
+    }
+    Ok(())
+}
+
+/// Where a temporary is defined
+#[derive(Debug, Clone, Copy)]
+struct Definition {
+    block: BlockId,
+    /// 0 for phis and `Next` items, `i + 1` for instruction `i`
+    position: usize,
+    is_iterator: bool,
+}
+
+struct Validator<'a> {
+    module: &'a IrModule,
+    function: &'a IrFunction,
+    definitions: Vec<Option<Definition>>,
+    idom: Vec<Option<BlockId>>,
+}
+
+fn validate_function(
+    module: &IrModule,
+    function: &IrFunction,
+    is_main: bool,
+) -> Result<(), ValidationError> {
+    let mut validator = Validator {
+        module,
+        function,
+        definitions: vec![None; function.temp_count as usize],
+        idom: Vec::new(),
+    };
+    if function.blocks.is_empty() {
+        return Err(validator.error(None, "no entry block".to_string()));
+    }
+    validator.check_structure(is_main)?;
+    validator.collect_definitions()?;
+    validator.idom = function.dominators();
+    validator.check_uses()
+}
+
+impl Validator<'_> {
+    fn error(&self, block: Option<BlockId>, message: String) -> ValidationError {
+        ValidationError {
+            function: self.function.name.clone(),
+            block,
+            message,
+        }
+    }
+
+    /// Block targets, phi inputs, calls and terminators
+    fn check_structure(&self, is_main: bool) -> Result<(), ValidationError> {
+        let block_count = self.function.blocks.len();
+        for id in self.function.block_ids() {
+            let block = self.function.block(id);
+            for successor in block.terminator.kind.successors() {
+                if successor.index() >= block_count {
+                    return Err(self.error(Some(id), format!("jump to missing block {}", successor)));
+                }
+            }
+            match block.terminator.kind {
+                TerminatorKind::Return(_) if is_main => {
+                    return Err(self.error(Some(id), "`return` outside a function".to_string()));
+                }
+                TerminatorKind::Next { body, exit, .. } if body == exit => {
+                    return Err(self.error(Some(id), "`next` body and exit are the same block".to_string()));
+                }
+                _ => {}
+            }
+            for instruction in &block.instructions {
+                match &instruction.kind {
+                    InstructionKind::Call { function, args } => {
+                        let Some(callee) = self.module.functions.get(*function) else {
+                            return Err(self.error(Some(id), format!("call to missing function {}", function)));
+                        };
+                        if args.len() != callee.param_count {
+                            let message = format!(
+                                "`{}` takes {} arguments but the call passes {}",
+                                callee.name,
+                                callee.param_count,
+                                args.len()
+                            );
+                            return Err(self.error(Some(id), message));
+                        }
+                    }
+                    InstructionKind::Param(index) if *index as usize >= self.function.param_count => {
+                        return Err(self.error(Some(id), format!("missing parameter {}", index)));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let predecessors = self.function.predecessors();
+        if !predecessors[0].is_empty() {
+            return Err(self.error(Some(BlockId::ENTRY), "the entry block has predecessors".to_string()));
+        }
+        for id in self.function.block_ids() {
+            let mut expected = predecessors[id.index()].clone();
+            expected.sort();
+            for phi in &self.function.block(id).phis {
+                let mut incoming: Vec<BlockId> = phi.incoming.iter().map(|(pred, _)| *pred).collect();
+                incoming.sort();
+                if incoming != expected {
+                    let message = format!("the inputs of {} do not match the predecessors", phi.dest);
+                    return Err(self.error(Some(id), message));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every temporary must be defined exactly once
+    fn collect_definitions(&mut self) -> Result<(), ValidationError> {
+        let function = self.function;
+        for id in function.block_ids() {
+            let block = function.block(id);
+            let phis = block.phis.iter().map(|phi| (phi.dest, 0, false));
+            let instructions = block.instructions.iter().enumerate().filter_map(|(index, instruction)| {
+                let is_iterator = matches!(instruction.kind, InstructionKind::IterStart(_));
+                instruction.dest.map(|dest| (dest, index + 1, is_iterator))
+            });
+            for (dest, position, is_iterator) in phis.chain(instructions) {
+                self.define(dest, id, position, is_iterator)?;
+            }
+            if let TerminatorKind::Next { item, body, .. } = block.terminator.kind {
+                // The item only exists once the loop body is entered
+                self.define(item, body, 0, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn define(
+        &mut self,
+        temp: Temp,
+        block: BlockId,
+        position: usize,
+        is_iterator: bool,
+    ) -> Result<(), ValidationError> {
+        match self.definitions.get(temp.index()) {
+            None => Err(self.error(Some(block), format!("{} is out of range", temp))),
+            Some(Some(_)) => Err(self.error(Some(block), format!("{} is defined more than once", temp))),
+            Some(None) => {
+                self.definitions[temp.index()] = Some(Definition {
+                    block,
+                    position,
+                    is_iterator,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn dominates(&self, dominator: BlockId, mut block: BlockId) -> bool {
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.idom[block.index()] {
+                Some(idom) if idom != block => block = idom,
+                _ => return false,
+            }
+        }
+    }
+
+    fn definition(&self, temp: Temp, block: BlockId) -> Result<Definition, ValidationError> {
+        self.definitions
+            .get(temp.index())
+            .copied()
+            .flatten()
+            .ok_or_else(|| self.error(Some(block), format!("{} is used but never defined", temp)))
+    }
+
+    /// Definitions must dominate their uses in reachable blocks
+    fn check_use(&self, temp: Temp, block: BlockId, position: usize) -> Result<(), ValidationError> {
+        let definition = self.definition(temp, block)?;
+        if self.idom[block.index()].is_none() {
+            return Ok(());
+        }
+        let dominated = if definition.block == block {
+            definition.position < position
+        } else {
+            self.dominates(definition.block, block)
+        };
+        if dominated {
+            Ok(())
+        } else {
+            Err(self.error(Some(block), format!("{} is used where its definition does not reach", temp)))
+        }
+    }
+
+    fn check_uses(&self) -> Result<(), ValidationError> {
+        for id in self.function.block_ids() {
+            let block = self.function.block(id);
+            for phi in &block.phis {
+                for &(pred, value) in &phi.incoming {
+                    // A phi input is used at the end of its predecessor
+                    self.check_use(value, pred, usize::MAX)?;
+                }
+            }
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                for operand in instruction.kind.operands() {
+                    self.check_use(operand, id, index + 1)?;
+                }
+            }
+            let end = block.instructions.len() + 1;
+            if let TerminatorKind::Next { iterator, .. } = block.terminator.kind {
+                if !self.definition(iterator, id)?.is_iterator {
+                    return Err(self.error(Some(id), format!("{} is not an iterator", iterator)));
+                }
+            }
+            for operand in block.terminator.kind.operands() {
+                self.check_use(operand, id, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::lowering::{lower_module, LoweringOptions};
+    use crate::parser::Parser;
+    use crate::span::Span;
+
+    fn lower(source: &str) -> IrModule {
+        let (tokens, _) = Lexer::new(source).tokenize();
+        let (ast, errors) = Parser::new(tokens).parse_module();
+        assert!(errors.is_empty(), "{:?}", errors);
+        lower_module(ast, &LoweringOptions::default())
+    }
+
+    fn module(main: IrFunction) -> IrModule {
+        IrModule {
+            functions: Vec::new(),
+            main,
+            span: Span::default(),
+        }
+    }
+
+    fn instruction(dest: Option<Temp>, kind: InstructionKind) -> Instruction {
+        Instruction::new(dest, kind, Span::default())
+    }
+
+    /// `accrete if true { 1 } else { 2 }`: a branch to two blocks that
+    /// join at a phi
+    fn diamond() -> IrFunction {
+        let mut main = IrFunction::new("main".to_string(), 0, Span::default());
+        let temps: Vec<Temp> = (0..4).map(|_| main.new_temp()).collect();
+        let block = |instructions, terminator| BasicBlock {
+            phis: Vec::new(),
+            instructions,
+            terminator: Terminator::new(terminator, Span::default()),
+        };
+        main.blocks = vec![
+            block(
+                vec![instruction(Some(temps[0]), InstructionKind::Const(Constant::Boolean(true)))],
+                TerminatorKind::Branch {
+                    condition: temps[0],
+                    then_block: BlockId(1),
+                    else_block: BlockId(2),
+                },
+            ),
+            block(
+                vec![instruction(Some(temps[1]), InstructionKind::Const(Constant::Integer(1)))],
+                TerminatorKind::Jump(BlockId(3)),
+            ),
+            block(
+                vec![instruction(Some(temps[2]), InstructionKind::Const(Constant::Integer(2)))],
+                TerminatorKind::Jump(BlockId(3)),
+            ),
+            block(vec![instruction(None, InstructionKind::Print(temps[3]))], TerminatorKind::Halt),
+        ];
+        main.blocks[3].phis.push(Phi {
+            dest: temps[3],
+            incoming: vec![(BlockId(1), temps[1]), (BlockId(2), temps[2])],
+            span: Span::default(),
+        });
+        main
+    }
+
+    fn error(main: IrFunction) -> String {
+        validate(&module(main)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn lowering_produces_valid_ssa() {
+        let ir = lower(
+            "fn count(n) {\n    let mut total = 0\n    for i in 0..n {\n        if i % 2 == 0 { total = total + i } else { total = total - 1 }\n    }\n    total\n}\n\
+             let mut x = 1\nlet y = match x { 1 => \"one\", _ => \"other\" }\n\
+             if let z = null { x = 2 }\naccrete count(x) ?? y",
+        );
+        validate(&ir).unwrap();
+        // `total` is merged after the `if` and at the head of the loop
+        let phis: usize = ir.functions[0].blocks.iter().map(|block| block.phis.len()).sum();
+        assert!(phis >= 2, "{}", ir);
+    }
+
+    #[test]
+    fn accepts_a_phi_per_predecessor() {
+        validate(&module(diamond())).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_phi_missing_an_input() {
+        let mut main = diamond();
+        main.blocks[3].phis[0].incoming.pop();
+        assert_eq!(error(main), "in `main`, bb3: the inputs of %3 do not match the predecessors");
+    }
+
+    #[test]
+    fn rejects_a_use_its_definition_does_not_reach() {
+        let mut main = diamond();
+        main.blocks[3].instructions[0].kind = InstructionKind::Print(Temp(1));
+        assert_eq!(error(main), "in `main`, bb3: %1 is used where its definition does not reach");
+    }
+
+    #[test]
+    fn rejects_a_second_definition() {
+        let mut main = diamond();
+        main.blocks[2].instructions[0].dest = Some(Temp(1));
+        assert_eq!(error(main), "in `main`, bb2: %1 is defined more than once");
+    }
+
+    #[test]
+    fn rejects_broken_terminators() {
+        let mut main = diamond();
+        main.blocks[1].terminator.kind = TerminatorKind::Jump(BlockId(9));
+        assert_eq!(error(main), "in `main`, bb1: jump to missing block bb9");
+
+        let mut main = diamond();
+        main.blocks[3].terminator.kind = TerminatorKind::Return(Temp(3));
+        assert_eq!(error(main), "in `main`, bb3: `return` outside a function");
+    }
+}
@@ -0,0 +1,101 @@
+// artificial-language/artificial-vm/benches/engines.rs
+//! Times the stack and the register engine on the programs in
+//! `benches/programs`, after checking that both print the same output.
+//! Run with `cargo bench -p artificial-vm`; arguments keep only the
+//! programs whose name contains one of them.
+use artificial_core::{
+    ir_pass_manager, lower_module, IrModule, Lexer, LoweringOptions, MacroExpander, OptLevel, Parser,
+    Resolver,
+};
+use artificial_vm::{
+    bytecode_pass_manager, BytecodeCompiler, BytecodeModule, RegisterCompiler, RegisterMachine, RegisterModule,
+    VirtualMachine,
+};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Runs of each program per engine; the median is reported
+const RUNS: usize = 15;
+
+fn main() {
+    let filters: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs");
+    let mut programs: Vec<_> = fs::read_dir(&directory)
+        .expect("benches/programs is missing")
+        .map(|entry| entry.expect("unreadable directory entry").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "art"))
+        .collect();
+    programs.sort();
+
+    println!("{:<12} {:>12} {:>12} {:>9}", "program", "stack", "register", "speedup");
+    for path in programs {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).expect("unreadable program");
+        let ir = compile(&source).unwrap_or_else(|message| panic!("{}: {}", name, message));
+        let (stack, stack_output) = time_stack(&ir);
+        let (register, register_output) = time_register(&ir);
+        assert_eq!(stack_output, register_output, "{}: the engines disagree", name);
+        println!(
+            "{:<12} {:>12.2?} {:>12.2?} {:>8.2}x",
+            name,
+            stack,
+            register,
+            stack.as_secs_f64() / register.as_secs_f64()
+        );
+    }
+}
+
+/// Lower and optimize a program as `ALC` does by default
+fn compile(source: &str) -> Result<IrModule, String> {
+    let (tokens, lex_errors) = Lexer::new(source).tokenize();
+    if let Some(error) = lex_errors.first() {
+        return Err(error.to_diagnostic().message);
+    }
+    let tokens = MacroExpander::new().expand(tokens).map_err(|e| e.to_diagnostic().message)?;
+    let (ast, parse_errors) = Parser::new(tokens).parse_module();
+    if let Some(error) = parse_errors.first() {
+        return Err(error.to_diagnostic().message);
+    }
+    Resolver::new().resolve(&ast).map_err(|e| e.to_diagnostic().message)?;
+    let mut ir = lower_module(ast, &LoweringOptions::default());
+    ir_pass_manager(OptLevel::default(), None)
+        .run(&mut ir)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(ir)
+}
+
+fn time_stack(ir: &IrModule) -> (Duration, String) {
+    let mut bytecode: BytecodeModule = BytecodeCompiler::new()
+        .compile(ir)
+        .unwrap_or_else(|errors| panic!("{}", errors[0].message));
+    bytecode_pass_manager(OptLevel::default(), false)
+        .run(&mut bytecode)
+        .expect("bytecode passes failed");
+    let mut vm = VirtualMachine::with_captured_output();
+    let duration = median(|| vm.execute(&bytecode).expect("stack engine failed"));
+    (duration, vm.get_output().to_string())
+}
+
+fn time_register(ir: &IrModule) -> (Duration, String) {
+    let code: RegisterModule = RegisterCompiler::new().compile(ir);
+    let mut vm = RegisterMachine::with_captured_output();
+    let duration = median(|| vm.execute(&code).expect("register engine failed"));
+    (duration, vm.get_output().to_string())
+}
+
+/// The median time of `RUNS` runs of `run`
+fn median(mut run: impl FnMut()) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
@@ -1,6 +1,12 @@
 // artificial-language/artificial-vm/src/bytecode.rs
+use artificial_core::{BinaryOp, Constant, Span};
+use std::collections::HashMap;
 use std::fmt;
 
+/// The most constants a module may have. Far more than any program needs,
+/// while keeping what the loader allocates for a damaged file bounded.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
 /// Opcodes for the Artificial Language Virtual Machine
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -17,6 +23,83 @@ pub enum Opcode {
     PrintLn = 0x04,
     /// Pop top of stack and discard
     Pop = 0x05,
+    /// Pop message and condition; fail unless the condition is true
+    /// (followed by u16 index of the source location constant)
+    Assert = 0x06,
+    /// Pop right and left; fail unless they are equal
+    /// (followed by u16 index of the source location constant)
+    AssertEq = 0x07,
+    /// Continue at an absolute instruction offset (followed by u32 target)
+    Jump = 0x08,
+    /// Pop a boolean and jump when it is false (followed by u32 target)
+    JumpIfFalse = 0x09,
+    /// Push a copy of the top of stack
+    Dup = 0x0A,
+    /// Pop two values and push whether they are equal
+    Equal = 0x0B,
+    /// Pop step, end and start and push a range
+    /// (followed by u8: 1 when the end is inclusive)
+    MakeRange = 0x0C,
+    /// Pop a range and a string and push the selected characters
+    Slice = 0x0D,
+    /// Push the value of a local slot (followed by u16 slot)
+    LoadLocal = 0x0E,
+    /// Pop a value into a local slot (followed by u16 slot)
+    StoreLocal = 0x0F,
+    /// Pop an iterable and start iterating over it
+    IterStart = 0x10,
+    /// Push the next item of the innermost iteration, or finish it and
+    /// jump when it is exhausted (followed by u32 target)
+    IterNext = 0x11,
+    /// Pop a value and push whether it is null
+    IsNull = 0x12,
+    /// Pop the arguments into the locals of a new frame and continue at
+    /// the function (followed by u32 target and u16 argument count)
+    Call = 0x13,
+    /// Pop the return value, drop the current frame and push the value
+    /// for the caller
+    Return = 0x14,
+    /// Pop items and push a list of them (followed by u16 item count)
+    MakeList = 0x15,
+    /// Pop right and left and push `left + right`: the sum of two
+    /// integers or the concatenation of two strings
+    Add = 0x16,
+    /// Pop right and left and push `left - right`
+    Subtract = 0x17,
+    /// Pop right and left and push `left * right`
+    Multiply = 0x18,
+    /// Pop right and left and push `left / right`, rounded toward zero
+    Divide = 0x19,
+    /// Pop right and left and push `left % right`
+    Remainder = 0x1A,
+    /// Pop two values and push whether they differ
+    NotEqual = 0x1B,
+    /// Pop right and left and push `left < right`
+    Less = 0x1C,
+    /// Pop right and left and push `left <= right`
+    LessEqual = 0x1D,
+    /// Pop right and left and push `left > right`
+    Greater = 0x1E,
+    /// Pop right and left and push `left >= right`
+    GreaterEqual = 0x1F,
+    /// Pop an integer and push its negation
+    Negate = 0x20,
+    /// Pop values and push their printed forms joined into one string
+    /// (followed by u16 value count)
+    Concat = 0x21,
+    /// Print a constant with newline; `PushConst` and `PrintLn` in one
+    /// (followed by u16 index)
+    PrintConst = 0x22,
+    /// Push `local + constant`; `LoadLocal`, `PushConst` and `Add` in one
+    /// (followed by u16 slot and u16 index)
+    AddLocalConst = 0x23,
+    /// Push constant from pool onto stack, for indices that do not fit in
+    /// `PushConst` (followed by u32 index)
+    PushConstWide = 0x24,
+    /// Pop the arguments into the locals of the current frame, replacing
+    /// it, and continue at the function; it returns to the caller's caller
+    /// (followed by u32 target and u16 argument count)
+    TailCall = 0x25,
 }
 
 impl Opcode {
@@ -28,18 +111,128 @@ impl Opcode {
             0x03 => Some(Opcode::Print),
             0x04 => Some(Opcode::PrintLn),
             0x05 => Some(Opcode::Pop),
+            0x06 => Some(Opcode::Assert),
+            0x07 => Some(Opcode::AssertEq),
+            0x08 => Some(Opcode::Jump),
+            0x09 => Some(Opcode::JumpIfFalse),
+            0x0A => Some(Opcode::Dup),
+            0x0B => Some(Opcode::Equal),
+            0x0C => Some(Opcode::MakeRange),
+            0x0D => Some(Opcode::Slice),
+            0x0E => Some(Opcode::LoadLocal),
+            0x0F => Some(Opcode::StoreLocal),
+            0x10 => Some(Opcode::IterStart),
+            0x11 => Some(Opcode::IterNext),
+            0x12 => Some(Opcode::IsNull),
+            0x13 => Some(Opcode::Call),
+            0x14 => Some(Opcode::Return),
+            0x15 => Some(Opcode::MakeList),
+            0x16 => Some(Opcode::Add),
+            0x17 => Some(Opcode::Subtract),
+            0x18 => Some(Opcode::Multiply),
+            0x19 => Some(Opcode::Divide),
+            0x1A => Some(Opcode::Remainder),
+            0x1B => Some(Opcode::NotEqual),
+            0x1C => Some(Opcode::Less),
+            0x1D => Some(Opcode::LessEqual),
+            0x1E => Some(Opcode::Greater),
+            0x1F => Some(Opcode::GreaterEqual),
+            0x20 => Some(Opcode::Negate),
+            0x21 => Some(Opcode::Concat),
+            0x22 => Some(Opcode::PrintConst),
+            0x23 => Some(Opcode::AddLocalConst),
+            0x24 => Some(Opcode::PushConstWide),
+            0x25 => Some(Opcode::TailCall),
+            _ => None,
+        }
+    }
+
+    /// Number of operand bytes following the opcode
+    pub fn operand_len(self) -> usize {
+        match self {
+            Opcode::MakeRange => 1,
+            Opcode::PushConst | Opcode::Assert | Opcode::AssertEq | Opcode::PrintConst => 2,
+            Opcode::LoadLocal | Opcode::StoreLocal | Opcode::MakeList | Opcode::Concat => 2,
+            Opcode::Jump | Opcode::JumpIfFalse | Opcode::IterNext | Opcode::AddLocalConst => 4,
+            Opcode::PushConstWide => 4,
+            Opcode::Call | Opcode::TailCall => 6,
+            _ => 0,
+        }
+    }
+
+    /// Net change in operand stack depth caused by the instruction; for
+    /// `IterNext` this is the effect when it does not jump, and for `Call`,
+    /// `TailCall`, `MakeList` and `Concat` it leaves out the values counted
+    /// by the operand
+    pub fn stack_effect(self) -> isize {
+        match self {
+            Opcode::Nop | Opcode::Halt | Opcode::Jump | Opcode::IsNull | Opcode::Negate => 0,
+            Opcode::TailCall => 0,
+            Opcode::PrintConst => 0,
+            Opcode::PushConst | Opcode::Dup | Opcode::LoadLocal | Opcode::IterNext => 1,
+            Opcode::AddLocalConst | Opcode::PushConstWide => 1,
+            Opcode::Call | Opcode::MakeList | Opcode::Concat => 1,
+            Opcode::Print | Opcode::PrintLn | Opcode::Pop | Opcode::Return => -1,
+            Opcode::JumpIfFalse | Opcode::Equal | Opcode::Slice => -1,
+            Opcode::Add | Opcode::Subtract | Opcode::Multiply | Opcode::Divide => -1,
+            Opcode::Remainder | Opcode::NotEqual | Opcode::Less | Opcode::LessEqual => -1,
+            Opcode::Greater | Opcode::GreaterEqual => -1,
+            Opcode::StoreLocal | Opcode::IterStart => -1,
+            Opcode::Assert | Opcode::AssertEq | Opcode::MakeRange => -2,
+        }
+    }
+
+    /// The opcode that applies a binary operator
+    pub fn for_binary(op: BinaryOp) -> Opcode {
+        match op {
+            BinaryOp::Add => Opcode::Add,
+            BinaryOp::Subtract => Opcode::Subtract,
+            BinaryOp::Multiply => Opcode::Multiply,
+            BinaryOp::Divide => Opcode::Divide,
+            BinaryOp::Remainder => Opcode::Remainder,
+            BinaryOp::Equal => Opcode::Equal,
+            BinaryOp::NotEqual => Opcode::NotEqual,
+            BinaryOp::Less => Opcode::Less,
+            BinaryOp::LessEqual => Opcode::LessEqual,
+            BinaryOp::Greater => Opcode::Greater,
+            BinaryOp::GreaterEqual => Opcode::GreaterEqual,
+        }
+    }
+
+    /// The binary operator the opcode applies, if it is one
+    pub fn binary_op(self) -> Option<BinaryOp> {
+        match self {
+            Opcode::Add => Some(BinaryOp::Add),
+            Opcode::Subtract => Some(BinaryOp::Subtract),
+            Opcode::Multiply => Some(BinaryOp::Multiply),
+            Opcode::Divide => Some(BinaryOp::Divide),
+            Opcode::Remainder => Some(BinaryOp::Remainder),
+            Opcode::Equal => Some(BinaryOp::Equal),
+            Opcode::NotEqual => Some(BinaryOp::NotEqual),
+            Opcode::Less => Some(BinaryOp::Less),
+            Opcode::LessEqual => Some(BinaryOp::LessEqual),
+            Opcode::Greater => Some(BinaryOp::Greater),
+            Opcode::GreaterEqual => Some(BinaryOp::GreaterEqual),
             _ => None,
         }
     }
 }
 
 /// Runtime values in the VM
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
     Null,
     String(String),
     Integer(i64),
     Boolean(bool),
+    /// A lazy integer range; its items are produced on demand
+    Range {
+        start: i64,
+        end: i64,
+        step: i64,
+        inclusive: bool,
+    },
+    List(Vec<Value>),
 }
 
 impl fmt::Display for Value {
@@ -49,10 +242,102 @@ impl fmt::Display for Value {
             Value::String(s) => write!(f, "{}", s),
             Value::Integer(n) => write!(f, "{}", n),
             Value::Boolean(b) => write!(f, "{}", b),
+            Value::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let op = if *inclusive { "..=" } else { ".." };
+                write!(f, "{}{}{}", start, op, end)?;
+                if *step != 1 {
+                    write!(f, " step {}", step)?;
+                }
+                Ok(())
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
 
+impl Value {
+    /// The value as a compile-time constant, or itself back if it has no
+    /// constant form
+    pub fn into_constant(self) -> Result<Constant, Value> {
+        match self {
+            Value::Null => Ok(Constant::Null),
+            Value::String(s) => Ok(Constant::String(s)),
+            Value::Integer(n) => Ok(Constant::Integer(n)),
+            Value::Boolean(b) => Ok(Constant::Boolean(b)),
+            other => Err(other),
+        }
+    }
+
+    /// The name of the value's type in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Range { .. } => "a range",
+            Value::List(_) => "a list",
+            Value::Null => "null",
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+        }
+    }
+}
+
+impl From<Constant> for Value {
+    fn from(constant: Constant) -> Self {
+        match constant {
+            Constant::Null => Value::Null,
+            Constant::String(s) => Value::String(s),
+            Constant::Integer(n) => Value::Integer(n),
+            Constant::Boolean(b) => Value::Boolean(b),
+        }
+    }
+}
+
+/// Maps instruction offsets back to the source they were compiled from
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Offsets where the span changes, in increasing order
+    entries: Vec<(usize, Span)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Record that the instructions from `offset` on come from `span`
+    pub fn mark(&mut self, offset: usize, span: Span) {
+        match self.entries.last_mut() {
+            Some((_, last)) if *last == span => {}
+            Some((last_offset, last)) if *last_offset == offset => *last = span,
+            _ => self.entries.push((offset, span)),
+        }
+    }
+
+    /// Span of the instruction at `offset`
+    pub fn span_at(&self, offset: usize) -> Option<Span> {
+        let index = self.entries.partition_point(|(start, _)| *start <= offset);
+        index.checked_sub(1).map(|i| self.entries[i].1)
+    }
+
+    pub fn entries(&self) -> &[(usize, Span)] {
+        &self.entries
+    }
+}
+
 /// Compiled bytecode module
 #[derive(Debug, Clone)]
 pub struct BytecodeModule {
@@ -60,6 +345,10 @@ pub struct BytecodeModule {
     pub constants: Vec<Value>,
     /// Raw bytecode instructions
     pub instructions: Vec<u8>,
+    /// Source spans of the instructions; not part of the serialized format
+    pub source_map: SourceMap,
+    /// The index of each constant in the pool, for deduplication
+    constant_indices: HashMap<Value, u32>,
 }
 
 impl BytecodeModule {
@@ -67,20 +356,51 @@ impl BytecodeModule {
         BytecodeModule {
             constants: Vec::new(),
             instructions: Vec::new(),
+            source_map: SourceMap::new(),
+            constant_indices: HashMap::new(),
         }
     }
 
-    /// Add a constant to the pool and return its index
-    pub fn add_constant(&mut self, value: Value) -> u16 {
-        // Check if constant already exists
-        for (i, existing) in self.constants.iter().enumerate() {
-            if existing == &value {
-                return i as u16;
-            }
+    /// A module with the given constant pool and instructions, as read
+    /// from a file
+    pub fn from_parts(constants: Vec<Value>, instructions: Vec<u8>) -> Self {
+        let mut constant_indices = HashMap::with_capacity(constants.len());
+        for (index, constant) in constants.iter().enumerate() {
+            constant_indices.entry(constant.clone()).or_insert(index as u32);
         }
-        let index = self.constants.len() as u16;
+        BytecodeModule {
+            constants,
+            instructions,
+            source_map: SourceMap::new(),
+            constant_indices,
+        }
+    }
+
+    /// Add a constant to the pool unless it is already there, and return
+    /// its index; `None` when the pool already holds `MAX_CONSTANTS`
+    pub fn add_constant(&mut self, value: Value) -> Option<u32> {
+        if let Some(&index) = self.constant_indices.get(&value) {
+            return Some(index);
+        }
+        if self.constants.len() >= MAX_CONSTANTS {
+            return None;
+        }
+        let index = self.constants.len() as u32;
+        self.constant_indices.insert(value.clone(), index);
         self.constants.push(value);
-        index
+        Some(index)
+    }
+
+    /// Emit the instruction that pushes constant `index`: `PushConst`, or
+    /// `PushConstWide` when the index needs more than 16 bits
+    pub fn emit_constant(&mut self, index: u32) {
+        match u16::try_from(index) {
+            Ok(index) => self.emit_with_operand(Opcode::PushConst, index),
+            Err(_) => {
+                self.instructions.push(Opcode::PushConstWide as u8);
+                self.instructions.extend_from_slice(&index.to_be_bytes());
+            }
+        }
     }
 
     /// Emit a single-byte opcode
@@ -94,6 +414,40 @@ impl BytecodeModule {
         self.instructions.push((operand >> 8) as u8);
         self.instructions.push((operand & 0xFF) as u8);
     }
+
+    /// Emit a jump with a placeholder target; returns the operand offset
+    /// to pass to `patch_jump`
+    pub fn emit_jump(&mut self, opcode: Opcode) -> usize {
+        self.instructions.push(opcode as u8);
+        let operand = self.instructions.len();
+        self.instructions.extend_from_slice(&[0; 4]);
+        operand
+    }
+
+    /// Point a previously emitted jump at the next instruction
+    pub fn patch_jump(&mut self, operand: usize) {
+        self.patch_jump_to(operand, self.instructions.len());
+    }
+
+    /// Point a previously emitted jump at `target`
+    pub fn patch_jump_to(&mut self, operand: usize, target: usize) {
+        let target = target as u32;
+        self.instructions[operand..operand + 4].copy_from_slice(&target.to_be_bytes());
+    }
+
+    /// Emit a `Call` or `TailCall` with a placeholder target; returns the
+    /// target operand offset to pass to `patch_jump_to`
+    pub fn emit_call(&mut self, opcode: Opcode, argc: u16) -> usize {
+        let operand = self.emit_jump(opcode);
+        self.instructions.extend_from_slice(&argc.to_be_bytes());
+        operand
+    }
+
+    /// Emit an opcode with a u8 operand
+    pub fn emit_with_byte(&mut self, opcode: Opcode, operand: u8) {
+        self.instructions.push(opcode as u8);
+        self.instructions.push(operand);
+    }
 }
 
 impl Default for BytecodeModule {
@@ -101,3 +455,114 @@ impl Default for BytecodeModule {
         Self::new()
     }
 }
+
+/// A listing of the instructions, one per line with its offset, as
+/// `--print-after` prints it:
+///
+/// ```text
+/// 0000  PrintConst 0  ; "Hello"
+/// 0003  Halt
+/// ```
+impl fmt::Display for BytecodeModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = &self.instructions;
+        let u16_at = |offset: usize| u16::from_be_bytes([code[offset], code[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([code[offset], code[offset + 1], code[offset + 2], code[offset + 3]])
+        };
+        let constant = |index: u32| match self.constants.get(index as usize) {
+            Some(Value::String(s)) => format!("{:?}", s),
+            Some(value) => value.to_string(),
+            None => "<missing>".to_string(),
+        };
+
+        let mut offset = 0;
+        while offset < code.len() {
+            write!(f, "{:04}  ", offset)?;
+            let opcode = match Opcode::from_u8(code[offset]) {
+                Some(opcode) if offset + opcode.operand_len() < code.len() => opcode,
+                _ => return writeln!(f, "<invalid 0x{:02X}>", code[offset]),
+            };
+            write!(f, "{:?}", opcode)?;
+            let operands = offset + 1;
+            match opcode {
+                Opcode::PushConst | Opcode::PrintConst => {
+                    let index = u16_at(operands) as u32;
+                    write!(f, " {}  ; {}", index, constant(index))?;
+                }
+                Opcode::PushConstWide => {
+                    let index = u32_at(operands);
+                    write!(f, " {}  ; {}", index, constant(index))?;
+                }
+                Opcode::AddLocalConst => {
+                    let index = u16_at(operands + 2) as u32;
+                    write!(f, " {}, {}  ; {}", u16_at(operands), index, constant(index))?;
+                }
+                Opcode::Jump | Opcode::JumpIfFalse | Opcode::IterNext => {
+                    write!(f, " {:04}", u32_at(operands))?;
+                }
+                Opcode::Call | Opcode::TailCall => {
+                    write!(f, " {:04}, {}", u32_at(operands), u16_at(operands + 4))?
+                }
+                Opcode::MakeRange => write!(f, " {}", code[operands])?,
+                _ if opcode.operand_len() == 2 => write!(f, " {}", u16_at(operands))?,
+                _ => {}
+            }
+            writeln!(f)?;
+            offset = operands + opcode.operand_len();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_constants_share_an_index() {
+        let mut module = BytecodeModule::new();
+        let one = module.add_constant(Value::Integer(1));
+        let name = module.add_constant(Value::String("one".to_string()));
+        assert_eq!(module.add_constant(Value::Integer(1)), one);
+        assert_eq!(module.add_constant(Value::String("one".to_string())), name);
+        assert_eq!(module.constants, [Value::Integer(1), Value::String("one".to_string())]);
+    }
+
+    #[test]
+    fn constants_of_different_types_are_kept_apart() {
+        let mut module = BytecodeModule::new();
+        let values = [
+            Value::Integer(1),
+            Value::String("1".to_string()),
+            Value::Boolean(true),
+            Value::Integer(0),
+            Value::Boolean(false),
+            Value::Null,
+        ];
+        for value in &values {
+            module.add_constant(value.clone());
+        }
+        assert_eq!(module.constants, values);
+    }
+
+    #[test]
+    fn modules_read_from_a_file_reuse_their_constants() {
+        let constants = vec![Value::Integer(7), Value::Integer(7), Value::Null];
+        let mut module = BytecodeModule::from_parts(constants, Vec::new());
+        assert_eq!(module.add_constant(Value::Integer(7)), Some(0));
+        assert_eq!(module.add_constant(Value::Null), Some(2));
+        assert_eq!(module.add_constant(Value::Integer(8)), Some(3));
+    }
+
+    #[test]
+    fn indices_past_16_bits_use_push_const_wide() {
+        let mut module = BytecodeModule::new();
+        module.emit_constant(u16::MAX as u32);
+        module.emit_constant(u16::MAX as u32 + 1);
+        assert_eq!(
+            module.instructions,
+            [Opcode::PushConst as u8, 0xFF, 0xFF, Opcode::PushConstWide as u8, 0, 1, 0, 0]
+        );
+    }
+}
@@ -1,46 +1,146 @@
 // artificial-language/artificial-vm/src/compiler.rs
-use crate::bytecode::{BytecodeModule, Opcode, Value};
-use artificial_core::{IrExpression, IrModule, IrStatement};
+use crate::bytecode::{BytecodeModule, Opcode, Value, MAX_CONSTANTS};
+use artificial_core::codes;
+use artificial_core::{
+    BlockId, Diagnostic, Instruction, InstructionKind, IrFunction, IrModule, Span, Temp, TerminatorKind,
+    UnaryOp,
+};
+
+/// Settings that affect code generation
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+    /// Source file name embedded in assertion locations
+    pub source_name: String,
+    /// Compile calls whose result is returned as `TailCall`, which reuses
+    /// the caller's frame
+    pub tail_calls: bool,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        CompilerOptions {
+            source_name: "<source>".to_string(),
+            tail_calls: true,
+        }
+    }
+}
 
 /// Compiles IR to bytecode
 pub struct BytecodeCompiler {
     module: BytecodeModule,
+    options: CompilerOptions,
+    /// Call target operands and the function each should point at
+    calls: Vec<(usize, usize)>,
+    /// Span of the instruction or terminator being compiled
+    current_span: Span,
+    /// The first error; compiling goes on without output that matters
+    error: Option<Diagnostic>,
 }
 
 impl BytecodeCompiler {
     pub fn new() -> Self {
+        Self::with_options(CompilerOptions::default())
+    }
+
+    pub fn with_options(options: CompilerOptions) -> Self {
         BytecodeCompiler {
             module: BytecodeModule::new(),
+            options,
+            calls: Vec::new(),
+            current_span: Span::default(),
+            error: None,
         }
     }
 
-    /// Compile an IR module to bytecode
-    pub fn compile(mut self, ir: &IrModule) -> BytecodeModule {
-        for statement in &ir.statements {
-            self.compile_statement(statement);
+    /// Compile an IR module to bytecode. The module's source map records
+    /// the span each instruction was compiled from. Fails when the program
+    /// needs more constants than a module can hold.
+    pub fn compile(mut self, ir: &IrModule) -> Result<BytecodeModule, Vec<Diagnostic>> {
+        // `Assert` and `AssertEq` take a 16-bit location index, so the
+        // locations go first in the pool
+        for function in std::iter::once(&ir.main).chain(&ir.functions) {
+            for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+                if let InstructionKind::Assert { .. } | InstructionKind::AssertEq { .. } = instruction.kind {
+                    self.current_span = instruction.span;
+                    self.location_constant(instruction.span);
+                }
+            }
         }
 
-        // Always end with Halt
-        self.module.emit(Opcode::Halt);
+        // Main runs first, from offset 0; function bodies follow it
+        FunctionCompiler::new(&mut self, &ir.main).compile();
+        let mut starts = Vec::with_capacity(ir.functions.len());
+        for function in &ir.functions {
+            starts.push(self.module.instructions.len());
+            FunctionCompiler::new(&mut self, function).compile();
+        }
+        for (operand, function) in std::mem::take(&mut self.calls) {
+            self.module.patch_jump_to(operand, starts[function]);
+        }
+
+        match self.error {
+            Some(error) => Err(vec![error]),
+            None => Ok(self.module),
+        }
+    }
 
-        self.module
+    fn emit(&mut self, opcode: Opcode) {
+        self.mark();
+        self.module.emit(opcode);
     }
 
-    fn compile_statement(&mut self, stmt: &IrStatement) {
-        match stmt {
-            IrStatement::Accrete(expr) => {
-                self.compile_expression(expr);
-                self.module.emit(Opcode::PrintLn);
+    fn emit_with_operand(&mut self, opcode: Opcode, operand: u16) {
+        self.mark();
+        self.module.emit_with_operand(opcode, operand);
+    }
+
+    fn emit_jump(&mut self, opcode: Opcode) -> usize {
+        self.mark();
+        self.module.emit_jump(opcode)
+    }
+
+    /// Attribute the next instruction to the current span
+    fn mark(&mut self) {
+        let offset = self.module.instructions.len();
+        self.module.source_map.mark(offset, self.current_span);
+    }
+
+    /// Push a constant with `PushConst` or `PushConstWide`
+    fn emit_constant(&mut self, value: Value) {
+        let index = self.constant(value);
+        self.mark();
+        self.module.emit_constant(index);
+    }
+
+    /// The pool index of a constant, added if it is new
+    fn constant(&mut self, value: Value) -> u32 {
+        match self.module.add_constant(value) {
+            Some(index) => index,
+            None => {
+                let message = format!("Too many constants: a module holds at most {}", MAX_CONSTANTS);
+                self.fail(message);
+                0
             }
         }
     }
 
-    fn compile_expression(&mut self, expr: &IrExpression) {
-        match expr {
-            IrExpression::StringLiteral(s) => {
-                let index = self.module.add_constant(Value::String(s.clone()));
-                self.module.emit_with_operand(Opcode::PushConst, index);
-            }
+    /// Store `file:line:column` in the constant pool so bytecode run
+    /// without its source can still report where an assertion failed
+    fn location_constant(&mut self, span: Span) -> u16 {
+        let location = format!("{}:{}", self.options.source_name, span);
+        let index = self.constant(Value::String(location));
+        u16::try_from(index).unwrap_or_else(|_| {
+            let message = format!("Too many assertions: at most {} distinct locations", u16::MAX as u32 + 1);
+            self.fail(message);
+            0
+        })
+    }
+
+    /// Record an error at the current span, unless one was already found
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            let error = Diagnostic::error(codes::TOO_MANY_CONSTANTS, message).with_span(self.current_span);
+            self.error = Some(error);
         }
     }
 }
@@ -50,3 +150,438 @@ impl Default for BytecodeCompiler {
         Self::new()
     }
 }
+
+/// Where the value of a temporary lives
+#[derive(Debug, Clone)]
+enum Home {
+    /// Not computed yet, or never read
+    Unassigned,
+    /// A constant, pushed again at every use instead of kept in a slot
+    Constant(Value),
+    /// A local slot; parameters arrive in slots `0..param_count`
+    Slot(u16),
+    /// The iterator of a loop, which lives in the VM's iteration stack
+    Iterator,
+}
+
+/// Compiles one function. Temporaries read once, later in the block that
+/// defines them, stay on the operand stack; all others are stored in local
+/// slots. Phis become copies on the edges into their block.
+struct FunctionCompiler<'a> {
+    compiler: &'a mut BytecodeCompiler,
+    function: &'a IrFunction,
+    homes: Vec<Home>,
+    uses: Vec<usize>,
+    stackable: Vec<bool>,
+    /// Temporaries on the operand stack, bottom first
+    pending: Vec<Temp>,
+    next_slot: u16,
+    block_starts: Vec<usize>,
+    /// Jump operands and the block each should point at
+    jumps: Vec<(usize, BlockId)>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(compiler: &'a mut BytecodeCompiler, function: &'a IrFunction) -> Self {
+        let temp_count = function.temp_count as usize;
+        let mut uses = vec![0; temp_count];
+        // The block of the only use of each temporary, if it is not a phi
+        let mut use_block: Vec<Option<BlockId>> = vec![None; temp_count];
+        let mut defined_in: Vec<Option<BlockId>> = vec![None; temp_count];
+        for id in function.block_ids() {
+            let block = function.block(id);
+            for phi in &block.phis {
+                for &(_, value) in &phi.incoming {
+                    uses[value.index()] += 1;
+                }
+            }
+            let instruction_operands = block.instructions.iter().flat_map(|i| i.kind.operands());
+            for operand in instruction_operands.chain(block.terminator.kind.operands()) {
+                uses[operand.index()] += 1;
+                use_block[operand.index()] = Some(id);
+            }
+            for instruction in &block.instructions {
+                if let Some(dest) = instruction.dest {
+                    defined_in[dest.index()] = Some(id);
+                }
+            }
+        }
+
+        let mut homes = vec![Home::Unassigned; temp_count];
+        let mut stackable = vec![false; temp_count];
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                let Some(dest) = instruction.dest else { continue };
+                match &instruction.kind {
+                    InstructionKind::Const(constant) => {
+                        homes[dest.index()] = Home::Constant(Value::from(constant.clone()));
+                    }
+                    InstructionKind::Param(index) => homes[dest.index()] = Home::Slot(*index),
+                    InstructionKind::IterStart(_) => homes[dest.index()] = Home::Iterator,
+                    _ => {
+                        let index = dest.index();
+                        stackable[index] = uses[index] == 1
+                            && use_block[index].is_some()
+                            && use_block[index] == defined_in[index];
+                    }
+                }
+            }
+        }
+
+        FunctionCompiler {
+            compiler,
+            function,
+            homes,
+            uses,
+            stackable,
+            pending: Vec::new(),
+            next_slot: function.param_count as u16,
+            block_starts: Vec::with_capacity(function.blocks.len()),
+            jumps: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) {
+        for id in self.function.block_ids() {
+            self.block_starts.push(self.compiler.module.instructions.len());
+            self.compile_block(id);
+        }
+        for (operand, block) in std::mem::take(&mut self.jumps) {
+            let target = self.block_starts[block.index()];
+            self.compiler.module.patch_jump_to(operand, target);
+        }
+    }
+
+    fn compile_block(&mut self, id: BlockId) {
+        let block = self.function.block(id);
+        for instruction in &block.instructions {
+            self.compiler.current_span = instruction.span;
+            if let InstructionKind::Call { function, args } = &instruction.kind {
+                if self.compiler.options.tail_calls && is_tail_call(self.function, id, instruction) {
+                    // Nothing follows in this frame, so the block ends here
+                    self.take_operands(args);
+                    self.compiler.mark();
+                    let operand = self.compiler.module.emit_call(Opcode::TailCall, args.len() as u16);
+                    self.compiler.calls.push((operand, *function));
+                    debug_assert!(self.pending.is_empty(), "values left on the stack in {}", id);
+                    return;
+                }
+            }
+            self.compile_instruction(&instruction.kind);
+            if let Some(dest) = instruction.dest {
+                self.define(dest);
+            }
+        }
+
+        let terminator = &block.terminator;
+        self.compiler.current_span = terminator.span;
+        match terminator.kind {
+            TerminatorKind::Jump(target) => self.compile_edge(id, target, true),
+            TerminatorKind::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.take_operands(&[condition]);
+                if self.has_copies(id, else_block) {
+                    let else_jump = self.compiler.emit_jump(Opcode::JumpIfFalse);
+                    self.compile_edge(id, then_block, false);
+                    self.compiler.module.patch_jump(else_jump);
+                    self.compile_edge(id, else_block, true);
+                } else {
+                    let else_jump = self.compiler.emit_jump(Opcode::JumpIfFalse);
+                    self.jumps.push((else_jump, else_block));
+                    self.compile_edge(id, then_block, true);
+                }
+            }
+            TerminatorKind::Next {
+                item, body, exit, ..
+            } => {
+                // The VM iterates over its innermost iteration, which is
+                // this loop's, so the iterator needs no code
+                let exit_jump = self.compiler.emit_jump(Opcode::IterNext);
+                if self.uses[item.index()] == 0 {
+                    self.compiler.emit(Opcode::Pop);
+                } else {
+                    let slot = self.slot(item);
+                    self.compiler.emit_with_operand(Opcode::StoreLocal, slot);
+                }
+                if self.has_copies(id, exit) {
+                    self.compile_edge(id, body, false);
+                    self.compiler.module.patch_jump(exit_jump);
+                    self.compile_edge(id, exit, true);
+                } else {
+                    self.jumps.push((exit_jump, exit));
+                    self.compile_edge(id, body, true);
+                }
+            }
+            TerminatorKind::Return(value) => {
+                self.take_operands(&[value]);
+                self.compiler.emit(Opcode::Return);
+            }
+            TerminatorKind::Halt => self.compiler.emit(Opcode::Halt),
+        }
+        debug_assert!(self.pending.is_empty(), "values left on the stack in {}", id);
+    }
+
+    fn compile_instruction(&mut self, kind: &InstructionKind) {
+        self.take_operands(&kind.operands());
+        match kind {
+            // Constants and parameters are loaded where they are used, and
+            // an iteration starts with its first `IterNext`
+            InstructionKind::Const(_) | InstructionKind::Param(_) => {}
+            InstructionKind::Call { function, args } => {
+                self.compiler.mark();
+                let operand = self.compiler.module.emit_call(Opcode::Call, args.len() as u16);
+                self.compiler.calls.push((operand, *function));
+            }
+            InstructionKind::List(items) => {
+                self.compiler
+                    .emit_with_operand(Opcode::MakeList, items.len() as u16);
+            }
+            InstructionKind::IsNull(_) => self.compiler.emit(Opcode::IsNull),
+            InstructionKind::Binary { op, .. } => self.compiler.emit(Opcode::for_binary(*op)),
+            InstructionKind::Unary { op, .. } => match op {
+                UnaryOp::Negate => self.compiler.emit(Opcode::Negate),
+            },
+            InstructionKind::Concat(parts) => {
+                self.compiler
+                    .emit_with_operand(Opcode::Concat, parts.len() as u16);
+            }
+            InstructionKind::Range { inclusive, .. } => {
+                self.compiler.mark();
+                self.compiler
+                    .module
+                    .emit_with_byte(Opcode::MakeRange, *inclusive as u8);
+            }
+            InstructionKind::Slice { .. } => self.compiler.emit(Opcode::Slice),
+            InstructionKind::IterStart(_) => self.compiler.emit(Opcode::IterStart),
+            InstructionKind::Print(_) => self.compiler.emit(Opcode::PrintLn),
+            InstructionKind::Assert { message, .. } => {
+                if message.is_none() {
+                    self.compiler.emit_constant(Value::Null);
+                }
+                let location = self.compiler.location_constant(self.compiler.current_span);
+                self.compiler.emit_with_operand(Opcode::Assert, location);
+            }
+            InstructionKind::AssertEq { .. } => {
+                let location = self.compiler.location_constant(self.compiler.current_span);
+                self.compiler.emit_with_operand(Opcode::AssertEq, location);
+            }
+        }
+    }
+
+    /// Put away the value an instruction just pushed
+    fn define(&mut self, dest: Temp) {
+        match self.homes[dest.index()] {
+            Home::Constant(_) | Home::Slot(_) | Home::Iterator => {}
+            Home::Unassigned if self.uses[dest.index()] == 0 => self.compiler.emit(Opcode::Pop),
+            Home::Unassigned if self.stackable[dest.index()] => self.pending.push(dest),
+            Home::Unassigned => {
+                let slot = self.slot(dest);
+                self.compiler.emit_with_operand(Opcode::StoreLocal, slot);
+            }
+        }
+    }
+
+    /// Arrange for `operands` to be the top values of the operand stack.
+    /// Values already there are used in place when they are on top in the
+    /// right order; otherwise they are stored to slots until they are.
+    fn take_operands(&mut self, operands: &[Temp]) {
+        loop {
+            let in_place = (0..=operands.len().min(self.pending.len())).rev().find(|&k| {
+                self.pending[self.pending.len() - k..] == operands[..k]
+                    && operands[k..].iter().all(|op| !self.pending.contains(op))
+            });
+            match in_place {
+                Some(k) => {
+                    self.pending.truncate(self.pending.len() - k);
+                    for &operand in &operands[k..] {
+                        self.load(operand);
+                    }
+                    return;
+                }
+                None => {
+                    let top = self.pending.pop().unwrap();
+                    let slot = self.slot(top);
+                    self.compiler.emit_with_operand(Opcode::StoreLocal, slot);
+                }
+            }
+        }
+    }
+
+    fn load(&mut self, temp: Temp) {
+        match &self.homes[temp.index()] {
+            Home::Constant(value) => {
+                let value = value.clone();
+                self.compiler.emit_constant(value);
+            }
+            Home::Slot(slot) => {
+                let slot = *slot;
+                self.compiler.emit_with_operand(Opcode::LoadLocal, slot);
+            }
+            Home::Iterator => unreachable!("iterator {} used as a value", temp),
+            Home::Unassigned => unreachable!("{} used before it is stored", temp),
+        }
+    }
+
+    /// The slot of a temporary, assigning one the first time
+    fn slot(&mut self, temp: Temp) -> u16 {
+        match self.homes[temp.index()] {
+            Home::Slot(slot) => slot,
+            _ => {
+                let slot = self.next_slot;
+                self.next_slot += 1;
+                self.homes[temp.index()] = Home::Slot(slot);
+                slot
+            }
+        }
+    }
+
+    /// Whether taking the edge from `from` to `to` copies values into phis
+    fn has_copies(&self, from: BlockId, to: BlockId) -> bool {
+        self.function
+            .block(to)
+            .phis
+            .iter()
+            .any(|phi| phi.incoming.iter().any(|(pred, _)| *pred == from))
+    }
+
+    /// Copy values into the phis of `to` and continue there. The copies
+    /// happen in parallel: every input is loaded before any phi is stored.
+    fn compile_edge(&mut self, from: BlockId, to: BlockId, may_fall_through: bool) {
+        let copies: Vec<(Temp, Temp)> = self
+            .function
+            .block(to)
+            .phis
+            .iter()
+            .filter_map(|phi| {
+                let (_, value) = phi.incoming.iter().find(|(pred, _)| *pred == from)?;
+                Some((phi.dest, *value))
+            })
+            .collect();
+        for &(_, value) in &copies {
+            self.load(value);
+        }
+        for &(dest, _) in copies.iter().rev() {
+            let slot = self.slot(dest);
+            self.compiler.emit_with_operand(Opcode::StoreLocal, slot);
+        }
+        if !(may_fall_through && to.index() == from.index() + 1) {
+            let jump = self.compiler.emit_jump(Opcode::Jump);
+            self.jumps.push((jump, to));
+        }
+    }
+}
+
+/// Whether `call`, in block `id`, is the last instruction of a path that
+/// returns its result unchanged: directly, or through blocks that only
+/// pass it on to a `return` by way of phis
+pub(crate) fn is_tail_call(function: &IrFunction, id: BlockId, call: &Instruction) -> bool {
+    let (Some(mut value), Some(last)) = (call.dest, function.block(id).instructions.last()) else {
+        return false;
+    };
+    if !std::ptr::eq(last, call) {
+        return false;
+    }
+    let mut from = id;
+    // Following more jumps than there are blocks means a cycle of empty
+    // blocks, which never returns
+    for _ in 0..function.blocks.len() {
+        match function.block(from).terminator.kind {
+            TerminatorKind::Return(returned) => return returned == value,
+            TerminatorKind::Jump(to) => {
+                let target = function.block(to);
+                if !target.instructions.is_empty() {
+                    return false;
+                }
+                if let Some(phi) = target.phis.iter().find(|phi| phi.incoming.contains(&(from, value))) {
+                    value = phi.dest;
+                }
+                from = to;
+            }
+            _ => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VirtualMachine;
+    use artificial_core::codes::ErrorCode;
+    use artificial_core::{lower_module, Lexer, LoweringOptions, Parser};
+
+    const COUNTDOWN: &str = "fn count(n) {\n    if n == 0 {\n        return \"done\"\n    }\n    return count(n - 1)\n}\naccrete count(20000)\n";
+
+    fn lower(source: &str) -> IrModule {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "{:?}", lex_errors);
+        let (ast, parse_errors) = Parser::new(tokens).parse_module();
+        assert!(parse_errors.is_empty(), "{:?}", parse_errors);
+        lower_module(ast, &LoweringOptions::default())
+    }
+
+    #[test]
+    fn a_full_constant_pool_is_an_error_at_the_new_constant() {
+        let mut compiler = BytecodeCompiler::new();
+        compiler.module.constants.resize(MAX_CONSTANTS, Value::Null);
+        let errors = compiler.compile(&lower("accrete \"full\"")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::TOO_MANY_CONSTANTS);
+        assert_eq!(errors[0].message, "Too many constants: a module holds at most 16777216");
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.line, span.column), (1, 1));
+    }
+
+    #[test]
+    fn assertion_locations_must_fit_in_16_bits() {
+        let source: String = (0..=u16::MAX as u32).map(|_| "assert true\n").collect();
+        let ir = lower(&source);
+        assert!(BytecodeCompiler::new().compile(&ir).is_ok());
+
+        let ir = lower(&(source + "assert true\n"));
+        let errors = BytecodeCompiler::new().compile(&ir).unwrap_err();
+        assert_eq!(errors[0].code, codes::TOO_MANY_CONSTANTS);
+        assert_eq!(errors[0].message, "Too many assertions: at most 65536 distinct locations");
+        assert_eq!(errors[0].span.unwrap().line, 65537);
+    }
+
+    /// Compile `source`, with or without tail calls, and run it
+    fn run(source: &str, tail_calls: bool) -> (BytecodeModule, Result<String, ErrorCode>) {
+        let options = CompilerOptions {
+            tail_calls,
+            ..CompilerOptions::default()
+        };
+        let bytecode = BytecodeCompiler::with_options(options).compile(&lower(source)).unwrap();
+        let mut vm = VirtualMachine::with_captured_output();
+        let result = vm.execute(&bytecode).map(|()| vm.get_output().to_string());
+        (bytecode, result.map_err(|error| error.code))
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        let (bytecode, result) = run(COUNTDOWN, true);
+        assert_eq!(result, Ok("done\n".to_string()));
+        let listing = bytecode.to_string();
+        assert!(listing.contains("TailCall"), "{}", listing);
+    }
+
+    #[test]
+    fn without_tail_calls_deep_recursion_overflows() {
+        let (bytecode, result) = run(COUNTDOWN, false);
+        assert_eq!(result, Err(codes::STACK_OVERFLOW));
+        assert!(!bytecode.to_string().contains("TailCall"));
+    }
+
+    #[test]
+    fn calls_outside_tail_position_keep_their_frame() {
+        let source = "fn sum(n) {\n    if n == 0 {\n        return 0\n    }\n    return n + sum(n - 1)\n}\naccrete sum(20000)\n";
+        let (bytecode, result) = run(source, true);
+        assert_eq!(result, Err(codes::STACK_OVERFLOW));
+        let listing = bytecode.to_string();
+        assert!(!listing.contains("TailCall"), "{}", listing);
+        assert_eq!(listing.matches("Call ").count(), 2, "{}", listing);
+    }
+}
@@ -1,11 +1,19 @@
 // artificial-language/artificial-vm/src/lib.rs
 pub mod bytecode;
 pub mod compiler;
+pub mod peephole;
+pub mod register;
+pub mod register_compiler;
+pub mod register_vm;
 pub mod vm;
 pub mod serializer;
 
 pub use bytecode::*;
 pub use compiler::*;
+pub use peephole::*;
+pub use register::*;
+pub use register_compiler::*;
+pub use register_vm::*;
 pub use vm::*;
 pub use serializer::*;
 
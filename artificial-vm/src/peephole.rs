@@ -0,0 +1,507 @@
+// artificial-language/artificial-vm/src/peephole.rs
+//! Peephole optimization of compiled bytecode. Short instruction sequences
+//! that do nothing are removed and common ones are fused into
+//! superinstructions, which the VM runs in one dispatch. Jump targets and
+//! the source map are rewritten to match.
+use crate::bytecode::{BytecodeModule, Opcode, SourceMap};
+use artificial_core::{Diagnostic, OptLevel, Pass, PassManager, Span};
+use std::fmt;
+
+/// The rewrites the optimizer knows, in the order they are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeepholeRule {
+    /// `Nop`
+    RemoveNop,
+    /// `PushConst`, `PushConstWide`, `LoadLocal` or `Dup` followed by `Pop`
+    PushPop,
+    /// `Jump` to the instruction right after it
+    JumpToNext,
+    /// `PushConst` and `PrintLn` become `PrintConst`
+    PrintConst,
+    /// `LoadLocal`, `PushConst` and `Add` become `AddLocalConst`
+    AddLocalConst,
+}
+
+impl PeepholeRule {
+    pub const ALL: [PeepholeRule; 5] = [
+        PeepholeRule::RemoveNop,
+        PeepholeRule::PushPop,
+        PeepholeRule::JumpToNext,
+        PeepholeRule::PrintConst,
+        PeepholeRule::AddLocalConst,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PeepholeRule::RemoveNop => "remove-nop",
+            PeepholeRule::PushPop => "push-pop",
+            PeepholeRule::JumpToNext => "jump-to-next",
+            PeepholeRule::PrintConst => "print-const",
+            PeepholeRule::AddLocalConst => "add-local-const",
+        }
+    }
+}
+
+/// How often a rule applied and what it saved
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RuleStats {
+    pub applied: usize,
+    pub bytes: usize,
+    pub instructions: usize,
+}
+
+/// What the optimizer saved, per rule
+#[derive(Debug, Clone, Default)]
+pub struct PeepholeStats {
+    /// Indexed like `PeepholeRule::ALL`
+    pub rules: [RuleStats; 5],
+}
+
+impl PeepholeStats {
+    pub fn get(&self, rule: PeepholeRule) -> RuleStats {
+        self.rules[rule as usize]
+    }
+
+    fn record(&mut self, rule: PeepholeRule, bytes: usize, instructions: usize) {
+        let stats = &mut self.rules[rule as usize];
+        stats.applied += 1;
+        stats.bytes += bytes;
+        stats.instructions += instructions;
+    }
+}
+
+/// A table with a row per rule and the totals, as `--peephole-stats`
+/// prints it
+impl fmt::Display for PeepholeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16} {:>8} {:>8} {:>13}", "rule", "applied", "bytes", "instructions")?;
+        let mut total = RuleStats::default();
+        for rule in PeepholeRule::ALL {
+            let stats = self.get(rule);
+            writeln!(
+                f,
+                "{:<16} {:>8} {:>8} {:>13}",
+                rule.name(),
+                stats.applied,
+                stats.bytes,
+                stats.instructions
+            )?;
+            total.applied += stats.applied;
+            total.bytes += stats.bytes;
+            total.instructions += stats.instructions;
+        }
+        writeln!(
+            f,
+            "{:<16} {:>8} {:>8} {:>13}",
+            "total", total.applied, total.bytes, total.instructions
+        )
+    }
+}
+
+/// A decoded instruction
+#[derive(Debug, Clone)]
+struct Instruction {
+    opcode: Opcode,
+    /// The index of the instruction a jump or call goes to; the number of
+    /// instructions stands for the end of the code
+    target: Option<usize>,
+    /// Operand bytes other than the target
+    operands: Vec<u8>,
+    span: Option<Span>,
+}
+
+impl Instruction {
+    fn new(opcode: Opcode, operands: Vec<u8>, span: Option<Span>) -> Self {
+        Instruction {
+            opcode,
+            target: None,
+            operands,
+            span,
+        }
+    }
+
+    fn len(&self) -> usize {
+        1 + self.opcode.operand_len()
+    }
+}
+
+fn has_target(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Jump | Opcode::JumpIfFalse | Opcode::IterNext | Opcode::Call | Opcode::TailCall
+    )
+}
+
+/// Optimize the instructions of `module` in place. Bytecode that does not
+/// decode is left alone.
+pub fn optimize_peephole(module: &mut BytecodeModule) -> PeepholeStats {
+    let mut stats = PeepholeStats::default();
+    let Some(code) = decode(module) else {
+        return stats;
+    };
+    let mut optimizer = Optimizer::new(code);
+    while optimizer.run_once(&mut stats) {}
+    optimizer.encode(module);
+    stats
+}
+
+/// `optimize_peephole` as a pass, which can print what it saved
+pub struct Peephole {
+    print_stats: bool,
+}
+
+impl Peephole {
+    pub fn new() -> Self {
+        Peephole { print_stats: false }
+    }
+
+    /// Print the table of what each rule saved after every run
+    pub fn with_stats() -> Self {
+        Peephole { print_stats: true }
+    }
+}
+
+impl Default for Peephole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass<BytecodeModule> for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+
+    fn run(&mut self, module: &mut BytecodeModule) -> Vec<Diagnostic> {
+        let stats = optimize_peephole(module);
+        if self.print_stats {
+            print!("{}", stats);
+        }
+        Vec::new()
+    }
+}
+
+/// Every bytecode pass in the order they run, enabled as `level` asks
+pub fn bytecode_pass_manager(level: OptLevel, print_stats: bool) -> PassManager<BytecodeModule> {
+    let mut manager = PassManager::new();
+    let peephole = if print_stats { Peephole::with_stats() } else { Peephole::new() };
+    manager.add(peephole, level != OptLevel::O0);
+    manager
+}
+
+fn decode(module: &BytecodeModule) -> Option<Vec<Instruction>> {
+    let bytes = &module.instructions;
+    let mut code = Vec::new();
+    // Instruction index by byte offset, for resolving targets
+    let mut index_at = vec![None; bytes.len() + 1];
+    let mut targets = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opcode = Opcode::from_u8(bytes[offset])?;
+        let end = offset + 1 + opcode.operand_len();
+        let operands = bytes.get(offset + 1..end)?;
+        index_at[offset] = Some(code.len());
+        let span = module.source_map.span_at(offset);
+        let mut instruction = Instruction::new(opcode, operands.to_vec(), span);
+        if has_target(opcode) {
+            let target = u32::from_be_bytes(operands[..4].try_into().ok()?) as usize;
+            targets.push((code.len(), target));
+            instruction.operands.drain(..4);
+        }
+        code.push(instruction);
+        offset = end;
+    }
+    index_at[bytes.len()] = Some(code.len());
+    for (index, target) in targets {
+        code[index].target = Some((*index_at.get(target)?)?);
+    }
+    Some(code)
+}
+
+struct Optimizer {
+    /// `None` for removed instructions
+    code: Vec<Option<Instruction>>,
+    /// Whether a jump or call can land on each index. Kept on the live
+    /// instruction a removed target falls through to.
+    is_target: Vec<bool>,
+}
+
+impl Optimizer {
+    fn new(code: Vec<Instruction>) -> Self {
+        let mut is_target = vec![false; code.len() + 1];
+        for instruction in &code {
+            if let Some(target) = instruction.target {
+                is_target[target] = true;
+            }
+        }
+        Optimizer {
+            code: code.into_iter().map(Some).collect(),
+            is_target,
+        }
+    }
+
+    /// The first live instruction at or after `index`
+    fn live_from(&self, mut index: usize) -> usize {
+        while index < self.code.len() && self.code[index].is_none() {
+            index += 1;
+        }
+        index
+    }
+
+    fn get(&self, index: usize) -> Option<&Instruction> {
+        self.code.get(index).and_then(Option::as_ref)
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.code[index] = None;
+        if std::mem::take(&mut self.is_target[index]) {
+            let next = self.live_from(index);
+            self.is_target[next] = true;
+        }
+    }
+
+    /// The live instructions starting at `index`, if there are `count` of
+    /// them and control can only enter the sequence at its first
+    fn sequence(&self, index: usize, count: usize) -> Option<Vec<usize>> {
+        let mut indices = vec![index];
+        while indices.len() < count {
+            let next = self.live_from(indices.last()? + 1);
+            if next >= self.code.len() || self.is_target[next] {
+                return None;
+            }
+            indices.push(next);
+        }
+        Some(indices)
+    }
+
+    fn opcodes(&self, indices: &[usize]) -> Vec<Opcode> {
+        indices.iter().filter_map(|&i| self.get(i)).map(|i| i.opcode).collect()
+    }
+
+    /// Try every rule at every instruction once; returns whether anything
+    /// changed
+    fn run_once(&mut self, stats: &mut PeepholeStats) -> bool {
+        let mut changed = false;
+        for index in 0..self.code.len() {
+            let Some(instruction) = self.get(index) else {
+                continue;
+            };
+            let opcode = instruction.opcode;
+
+            if opcode == Opcode::Nop {
+                self.remove(index);
+                stats.record(PeepholeRule::RemoveNop, 1, 1);
+                changed = true;
+                continue;
+            }
+
+            if opcode == Opcode::Jump {
+                let target = instruction.target.map(|target| self.live_from(target));
+                if target == Some(self.live_from(index + 1)) {
+                    self.remove(index);
+                    stats.record(PeepholeRule::JumpToNext, 5, 1);
+                    changed = true;
+                    continue;
+                }
+            }
+
+            if let Some(pair) = self.sequence(index, 2) {
+                match self.opcodes(&pair)[..] {
+                    [Opcode::PushConst | Opcode::PushConstWide | Opcode::LoadLocal | Opcode::Dup, Opcode::Pop] => {
+                        let bytes = self.get(index).unwrap().len() + 1;
+                        self.remove(pair[0]);
+                        self.remove(pair[1]);
+                        stats.record(PeepholeRule::PushPop, bytes, 2);
+                        changed = true;
+                        continue;
+                    }
+                    [Opcode::PushConst, Opcode::PrintLn] => {
+                        self.fuse(&pair, Opcode::PrintConst);
+                        stats.record(PeepholeRule::PrintConst, 1, 1);
+                        changed = true;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(triple) = self.sequence(index, 3) {
+                if self.opcodes(&triple)[..] == [Opcode::LoadLocal, Opcode::PushConst, Opcode::Add] {
+                    self.fuse(&triple, Opcode::AddLocalConst);
+                    stats.record(PeepholeRule::AddLocalConst, 2, 2);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Replace a sequence by one instruction taking all of its operands.
+    /// The last instruction is the one that can fail, so its span is kept.
+    fn fuse(&mut self, indices: &[usize], opcode: Opcode) {
+        let mut operands = Vec::new();
+        let mut span = None;
+        for &index in indices {
+            let instruction = self.code[index].take().expect("fusing a removed instruction");
+            operands.extend(instruction.operands);
+            span = instruction.span.or(span);
+        }
+        self.code[indices[0]] = Some(Instruction::new(opcode, operands, span));
+        for &index in &indices[1..] {
+            self.remove(index);
+        }
+    }
+
+    /// Write the live instructions back, with jump targets and the source
+    /// map moved to the new offsets
+    fn encode(self, module: &mut BytecodeModule) {
+        // A removed instruction's offset is that of the next live one
+        let mut offsets = Vec::with_capacity(self.code.len() + 1);
+        let mut offset = 0;
+        for instruction in &self.code {
+            offsets.push(offset);
+            offset += instruction.as_ref().map_or(0, Instruction::len);
+        }
+        offsets.push(offset);
+
+        let mut bytes = Vec::with_capacity(offset);
+        let mut source_map = SourceMap::new();
+        for (index, instruction) in self.code.iter().enumerate() {
+            let Some(instruction) = instruction else {
+                continue;
+            };
+            if let Some(span) = instruction.span {
+                source_map.mark(offsets[index], span);
+            }
+            bytes.push(instruction.opcode as u8);
+            if let Some(target) = instruction.target {
+                bytes.extend_from_slice(&(offsets[target] as u32).to_be_bytes());
+            }
+            bytes.extend_from_slice(&instruction.operands);
+        }
+        module.instructions = bytes;
+        module.source_map = source_map;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Value;
+    use crate::vm::VirtualMachine;
+    use artificial_core::FileId;
+
+    /// A span on its own line, so tests can tell spans apart
+    fn line(line: usize) -> Span {
+        Span::new(FileId(0), line * 10, line * 10 + 1, line, 1)
+    }
+
+    /// Mark the next instruction as coming from `span`
+    fn at(module: &mut BytecodeModule, span: Span) {
+        let offset = module.instructions.len();
+        module.source_map.mark(offset, span);
+    }
+
+    fn print(module: &mut BytecodeModule, text: &str, span: Span) {
+        let index = module.add_constant(Value::String(text.to_string())).unwrap();
+        at(module, span);
+        module.emit_constant(index);
+        module.emit(Opcode::PrintLn);
+    }
+
+    fn run(module: &BytecodeModule) -> String {
+        let mut vm = VirtualMachine::with_captured_output();
+        vm.execute(module).unwrap();
+        vm.get_output().to_string()
+    }
+
+    /// `if condition { accrete "then" } else { accrete "else" }` where
+    /// both jumps land on a `Nop`
+    fn branches(condition: bool) -> BytecodeModule {
+        let mut module = BytecodeModule::new();
+        let index = module.add_constant(Value::Boolean(condition)).unwrap();
+        at(&mut module, line(1));
+        module.emit_constant(index);
+        let to_else = module.emit_jump(Opcode::JumpIfFalse);
+        module.emit(Opcode::Nop);
+        print(&mut module, "then", line(2));
+        let to_end = module.emit_jump(Opcode::Jump);
+        module.patch_jump(to_else);
+        module.emit(Opcode::Nop);
+        print(&mut module, "else", line(3));
+        module.patch_jump(to_end);
+        at(&mut module, line(4));
+        module.emit(Opcode::Halt);
+        module
+    }
+
+    #[test]
+    fn retargets_jumps_to_removed_instructions() {
+        let mut module = branches(false);
+        let stats = optimize_peephole(&mut module);
+
+        assert_eq!(stats.get(PeepholeRule::RemoveNop).applied, 2);
+        assert_eq!(stats.get(PeepholeRule::PrintConst).applied, 2);
+        let (if_false, jump) = (Opcode::JumpIfFalse as u8, Opcode::Jump as u8);
+        let print = Opcode::PrintConst as u8;
+        #[rustfmt::skip]
+        let expected = vec![
+            Opcode::PushConst as u8, 0, 0,   // 0
+            if_false, 0, 0, 0, 16,           // 3: to the else branch
+            print, 0, 1,                     // 8
+            jump, 0, 0, 0, 19,               // 11: to the end
+            print, 0, 2,                     // 16
+            Opcode::Halt as u8,              // 19
+        ];
+        assert_eq!(module.instructions, expected);
+        assert_eq!(run(&module), "else\n");
+        assert_eq!(run(&branches(true)), "then\n");
+    }
+
+    #[test]
+    fn moves_the_source_map() {
+        let mut module = branches(false);
+        optimize_peephole(&mut module);
+        let spans: Vec<(usize, usize)> = module
+            .source_map
+            .entries()
+            .iter()
+            .map(|(offset, span)| (*offset, span.line))
+            .collect();
+        assert_eq!(spans, [(0, 1), (8, 2), (16, 3), (19, 4)]);
+    }
+
+    #[test]
+    fn removes_jumps_to_the_next_instruction() {
+        let mut module = BytecodeModule::new();
+        let jump = module.emit_jump(Opcode::Jump);
+        module.emit(Opcode::Nop);
+        module.patch_jump(jump);
+        module.emit(Opcode::Halt);
+
+        let stats = optimize_peephole(&mut module);
+        assert_eq!(stats.get(PeepholeRule::JumpToNext).applied, 1);
+        assert_eq!(module.instructions, [Opcode::Halt as u8]);
+    }
+
+    #[test]
+    fn does_not_fuse_across_a_jump_target() {
+        // A loop that jumps back between the load and the constant of
+        // `x + 1`, which must stay separate instructions
+        let mut module = BytecodeModule::new();
+        let one = module.add_constant(Value::Integer(1)).unwrap();
+        module.emit_with_operand(Opcode::LoadLocal, 0);
+        let target = module.instructions.len();
+        module.emit_constant(one);
+        module.emit(Opcode::Add);
+        module.emit(Opcode::Dup);
+        let jump = module.emit_jump(Opcode::JumpIfFalse);
+        module.patch_jump_to(jump, target);
+        module.emit(Opcode::Halt);
+        let before = module.instructions.clone();
+
+        let stats = optimize_peephole(&mut module);
+        assert_eq!(stats.get(PeepholeRule::AddLocalConst).applied, 0);
+        assert_eq!(module.instructions, before);
+    }
+}
@@ -0,0 +1,216 @@
+// artificial-language/artificial-vm/src/register.rs
+//! The instruction set of the register engine, an alternative to the
+//! stack machine. Every function runs in a frame of registers, and each
+//! instruction names its operands and its result directly: a register of
+//! the frame, or an entry of the constant pool that is read in place.
+use crate::bytecode::{SourceMap, Value};
+use artificial_core::BinaryOp;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Where an instruction reads a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A register of the current frame
+    Register(u32),
+    /// An entry of the constant pool
+    Constant(u32),
+}
+
+/// Instructions of the register engine. Jump targets are instruction
+/// indices; `dest`, `item` and the like are registers of the frame.
+#[derive(Debug, Clone)]
+pub enum RegisterInstruction {
+    /// Copy a value into a register
+    Move { dest: u32, source: Operand },
+    /// Move a value into a register, leaving null in `source`, which is
+    /// not read again before it is written
+    Take { dest: u32, source: u32 },
+    /// Run `functions[function]` in a new frame whose first registers hold
+    /// the arguments; its result is stored in `dest`
+    Call {
+        dest: u32,
+        function: u32,
+        args: Vec<Operand>,
+    },
+    /// Like `Call`, but the callee replaces the current frame and returns
+    /// to the caller's caller
+    TailCall { function: u32, args: Vec<Operand> },
+    /// Drop the frame and store the value in the caller's `dest`
+    Return(Operand),
+    Jump(usize),
+    /// Jump when the condition is false
+    JumpIfFalse { condition: Operand, target: usize },
+    Binary {
+        op: BinaryOp,
+        dest: u32,
+        left: Operand,
+        right: Operand,
+    },
+    Negate { dest: u32, operand: Operand },
+    IsNull { dest: u32, value: Operand },
+    List { dest: u32, items: Vec<Operand> },
+    /// Join the printed forms of the values into one string
+    Concat { dest: u32, parts: Vec<Operand> },
+    Range {
+        dest: u32,
+        start: Operand,
+        end: Operand,
+        step: Operand,
+        inclusive: bool,
+    },
+    Slice {
+        dest: u32,
+        target: Operand,
+        range: Operand,
+    },
+    /// Start iterating over a value; the loop becomes the innermost one
+    IterStart(Operand),
+    /// Store the next item of the innermost loop in `item`, or end the
+    /// loop and jump to `exit`
+    IterNext { item: u32, exit: usize },
+    /// Print a value followed by a newline
+    Print(Operand),
+    /// `location` is the constant holding `file:line:column`
+    Assert {
+        condition: Operand,
+        message: Option<Operand>,
+        location: u32,
+    },
+    AssertEq {
+        left: Operand,
+        right: Operand,
+        location: u32,
+    },
+    Halt,
+}
+
+/// Where a function starts and how many registers its frame has
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterFunction {
+    pub entry: usize,
+    pub registers: u32,
+}
+
+/// A compiled program for the register engine
+#[derive(Debug, Clone)]
+pub struct RegisterModule {
+    pub constants: Vec<Value>,
+    pub instructions: Vec<RegisterInstruction>,
+    /// The top-level code, which starts at instruction 0
+    pub main: RegisterFunction,
+    /// Callable functions, in the order of the IR module's
+    pub functions: Vec<RegisterFunction>,
+    /// Source spans by instruction index
+    pub source_map: SourceMap,
+    /// The index of each constant in the pool, for deduplication
+    constant_indices: HashMap<Value, u32>,
+}
+
+impl RegisterModule {
+    pub fn new() -> Self {
+        RegisterModule {
+            constants: Vec::new(),
+            instructions: Vec::new(),
+            main: RegisterFunction {
+                entry: 0,
+                registers: 0,
+            },
+            functions: Vec::new(),
+            source_map: SourceMap::new(),
+            constant_indices: HashMap::new(),
+        }
+    }
+
+    /// Add a constant to the pool unless it is already there, and return
+    /// its index
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        if let Some(&index) = self.constant_indices.get(&value) {
+            return index;
+        }
+        let index = self.constants.len() as u32;
+        self.constant_indices.insert(value.clone(), index);
+        self.constants.push(value);
+        index
+    }
+}
+
+impl Default for RegisterModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One instruction per line, in the style of the IR: `r2 = add r0, 1`
+impl fmt::Display for RegisterModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = |operand: &Operand| match *operand {
+            Operand::Register(register) => format!("r{}", register),
+            Operand::Constant(index) => match self.constants.get(index as usize) {
+                Some(Value::String(s)) => format!("{:?}", s),
+                Some(value) => value.to_string(),
+                None => "<missing>".to_string(),
+            },
+        };
+        let list = |operands: &[Operand]| operands.iter().map(operand).collect::<Vec<_>>().join(", ");
+        let entry = |function: u32| match self.functions.get(function as usize) {
+            Some(function) => format!("{:04}", function.entry),
+            None => "<missing>".to_string(),
+        };
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            write!(f, "{:04}  ", index)?;
+            match instruction {
+                RegisterInstruction::Move { dest, source } => writeln!(f, "r{} = {}", dest, operand(source))?,
+                RegisterInstruction::Take { dest, source } => writeln!(f, "r{} = take r{}", dest, source)?,
+                RegisterInstruction::Call { dest, function, args } => {
+                    writeln!(f, "r{} = call {}({})", dest, entry(*function), list(args))?
+                }
+                RegisterInstruction::TailCall { function, args } => {
+                    writeln!(f, "tail call {}({})", entry(*function), list(args))?
+                }
+                RegisterInstruction::Return(value) => writeln!(f, "return {}", operand(value))?,
+                RegisterInstruction::Jump(target) => writeln!(f, "jump {:04}", target)?,
+                RegisterInstruction::JumpIfFalse { condition, target } => {
+                    writeln!(f, "jump {:04} unless {}", target, operand(condition))?
+                }
+                RegisterInstruction::Binary { op, dest, left, right } => {
+                    writeln!(f, "r{} = {} {}, {}", dest, op, operand(left), operand(right))?
+                }
+                RegisterInstruction::Negate { dest, operand: value } => {
+                    writeln!(f, "r{} = neg {}", dest, operand(value))?
+                }
+                RegisterInstruction::IsNull { dest, value } => writeln!(f, "r{} = is_null {}", dest, operand(value))?,
+                RegisterInstruction::List { dest, items } => writeln!(f, "r{} = list [{}]", dest, list(items))?,
+                RegisterInstruction::Concat { dest, parts } => writeln!(f, "r{} = concat {}", dest, list(parts))?,
+                RegisterInstruction::Range {
+                    dest,
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                } => {
+                    let op = if *inclusive { "..=" } else { ".." };
+                    writeln!(f, "r{} = range {}{}{} step {}", dest, operand(start), op, operand(end), operand(step))?
+                }
+                RegisterInstruction::Slice { dest, target, range } => {
+                    writeln!(f, "r{} = slice {}[{}]", dest, operand(target), operand(range))?
+                }
+                RegisterInstruction::IterStart(value) => writeln!(f, "iter {}", operand(value))?,
+                RegisterInstruction::IterNext { item, exit } => writeln!(f, "r{} = next or jump {:04}", item, exit)?,
+                RegisterInstruction::Print(value) => writeln!(f, "print {}", operand(value))?,
+                RegisterInstruction::Assert {
+                    condition, message, ..
+                } => match message {
+                    Some(message) => writeln!(f, "assert {}, {}", operand(condition), operand(message))?,
+                    None => writeln!(f, "assert {}", operand(condition))?,
+                },
+                RegisterInstruction::AssertEq { left, right, .. } => {
+                    writeln!(f, "assert_eq {}, {}", operand(left), operand(right))?
+                }
+                RegisterInstruction::Halt => writeln!(f, "halt")?,
+            }
+        }
+        Ok(())
+    }
+}
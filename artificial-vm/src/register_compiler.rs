@@ -0,0 +1,498 @@
+// artificial-language/artificial-vm/src/register_compiler.rs
+use crate::bytecode::Value;
+use crate::compiler::{is_tail_call, CompilerOptions};
+use crate::register::{Operand, RegisterFunction, RegisterInstruction, RegisterModule};
+use artificial_core::{BlockId, InstructionKind, IrFunction, IrModule, Span, Temp, TerminatorKind, UnaryOp};
+
+/// Compiles IR to instructions for the register engine
+pub struct RegisterCompiler {
+    module: RegisterModule,
+    options: CompilerOptions,
+    /// Span of the instruction or terminator being compiled
+    current_span: Span,
+}
+
+impl RegisterCompiler {
+    pub fn new() -> Self {
+        Self::with_options(CompilerOptions::default())
+    }
+
+    pub fn with_options(options: CompilerOptions) -> Self {
+        RegisterCompiler {
+            module: RegisterModule::new(),
+            options,
+            current_span: Span::default(),
+        }
+    }
+
+    /// Compile an IR module; main comes first, then the functions in order
+    pub fn compile(mut self, ir: &IrModule) -> RegisterModule {
+        self.module.main = FunctionCompiler::new(&mut self, &ir.main).compile();
+        for function in &ir.functions {
+            let compiled = FunctionCompiler::new(&mut self, function).compile();
+            self.module.functions.push(compiled);
+        }
+        self.module
+    }
+
+    /// Append an instruction, recording the current span; returns its index
+    fn emit(&mut self, instruction: RegisterInstruction) -> usize {
+        let index = self.module.instructions.len();
+        self.module.source_map.mark(index, self.current_span);
+        self.module.instructions.push(instruction);
+        index
+    }
+
+    /// The constant holding `file:line:column`, for assertions
+    fn location_constant(&mut self) -> u32 {
+        let location = format!("{}:{}", self.options.source_name, self.current_span);
+        self.module.add_constant(Value::String(location))
+    }
+}
+
+impl Default for RegisterCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compiles one function. Every temporary the function computes has a
+/// register of its own; parameters arrive in registers `0..param_count`
+/// and constants are read from the pool. Phis become moves on the edges
+/// into their block.
+struct FunctionCompiler<'a> {
+    compiler: &'a mut RegisterCompiler,
+    function: &'a IrFunction,
+    homes: Vec<Operand>,
+    /// For temporaries read by a single phi and nothing else, the block
+    /// that defines them. On an edge from that block the value is fresh
+    /// and can be moved out of its register rather than copied.
+    moved_from: Vec<Option<BlockId>>,
+    /// Registers used for temporaries; scratch registers follow them
+    temp_registers: u32,
+    /// Registers in the frame
+    registers: u32,
+    block_starts: Vec<usize>,
+    /// Jumps and the block each should go to
+    jumps: Vec<(usize, BlockId)>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(compiler: &'a mut RegisterCompiler, function: &'a IrFunction) -> Self {
+        let mut homes = vec![Operand::Register(0); function.temp_count as usize];
+        let mut next = function.param_count as u32;
+        let mut assign = |temp: Temp, homes: &mut Vec<Operand>| {
+            homes[temp.index()] = Operand::Register(next);
+            next += 1;
+        };
+        for block in &function.blocks {
+            for phi in &block.phis {
+                assign(phi.dest, &mut homes);
+            }
+            for instruction in &block.instructions {
+                let Some(dest) = instruction.dest else { continue };
+                match &instruction.kind {
+                    InstructionKind::Const(constant) => {
+                        let index = compiler.module.add_constant(Value::from(constant.clone()));
+                        homes[dest.index()] = Operand::Constant(index);
+                    }
+                    InstructionKind::Param(index) => homes[dest.index()] = Operand::Register(*index as u32),
+                    _ => assign(dest, &mut homes),
+                }
+            }
+            if let TerminatorKind::Next { item, .. } = block.terminator.kind {
+                assign(item, &mut homes);
+            }
+        }
+
+        let mut uses = vec![0; function.temp_count as usize];
+        let mut phi_uses = vec![0; function.temp_count as usize];
+        for block in &function.blocks {
+            for phi in &block.phis {
+                for &(_, value) in &phi.incoming {
+                    phi_uses[value.index()] += 1;
+                }
+            }
+            let instruction_operands = block.instructions.iter().flat_map(|i| i.kind.operands());
+            for operand in instruction_operands.chain(block.terminator.kind.operands()) {
+                uses[operand.index()] += 1;
+            }
+        }
+        let mut moved_from = vec![None; function.temp_count as usize];
+        for id in function.block_ids() {
+            let block = function.block(id);
+            let phi_dests = block.phis.iter().map(|phi| phi.dest);
+            for dest in phi_dests.chain(block.instructions.iter().filter_map(|i| i.dest)) {
+                if uses[dest.index()] == 0 && phi_uses[dest.index()] == 1 {
+                    moved_from[dest.index()] = Some(id);
+                }
+            }
+        }
+
+        FunctionCompiler {
+            compiler,
+            function,
+            homes,
+            moved_from,
+            temp_registers: next,
+            registers: next,
+            block_starts: Vec::with_capacity(function.blocks.len()),
+            jumps: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> RegisterFunction {
+        let entry = self.compiler.module.instructions.len();
+        for id in self.function.block_ids() {
+            self.block_starts.push(self.compiler.module.instructions.len());
+            self.compile_block(id);
+        }
+        for (at, block) in std::mem::take(&mut self.jumps) {
+            let target = self.block_starts[block.index()];
+            self.patch(at, target);
+        }
+        RegisterFunction {
+            entry,
+            registers: self.registers,
+        }
+    }
+
+    fn compile_block(&mut self, id: BlockId) {
+        let block = self.function.block(id);
+        for instruction in &block.instructions {
+            self.compiler.current_span = instruction.span;
+            let kind = &instruction.kind;
+            if let InstructionKind::Const(_) | InstructionKind::Param(_) = kind {
+                continue;
+            }
+            // Instructions without a result do not read `dest`
+            let dest = instruction.dest.map_or(0, |dest| self.register(dest));
+            let compiled = match kind {
+                InstructionKind::Const(_) | InstructionKind::Param(_) => unreachable!("skipped above"),
+                InstructionKind::Call { function, args } => {
+                    let function = *function as u32;
+                    let args = self.operands(args);
+                    if self.compiler.options.tail_calls && is_tail_call(self.function, id, instruction) {
+                        // Nothing follows in this frame, so the block ends here
+                        self.compiler.emit(RegisterInstruction::TailCall { function, args });
+                        return;
+                    }
+                    RegisterInstruction::Call { dest, function, args }
+                }
+                InstructionKind::List(items) => RegisterInstruction::List {
+                    dest,
+                    items: self.operands(items),
+                },
+                InstructionKind::IsNull(value) => RegisterInstruction::IsNull {
+                    dest,
+                    value: self.home(*value),
+                },
+                InstructionKind::Binary { op, left, right } => RegisterInstruction::Binary {
+                    op: *op,
+                    dest,
+                    left: self.home(*left),
+                    right: self.home(*right),
+                },
+                InstructionKind::Unary { op, operand } => match op {
+                    UnaryOp::Negate => RegisterInstruction::Negate {
+                        dest,
+                        operand: self.home(*operand),
+                    },
+                },
+                InstructionKind::Concat(parts) => RegisterInstruction::Concat {
+                    dest,
+                    parts: self.operands(parts),
+                },
+                InstructionKind::Range {
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                } => RegisterInstruction::Range {
+                    dest,
+                    start: self.home(*start),
+                    end: self.home(*end),
+                    step: self.home(*step),
+                    inclusive: *inclusive,
+                },
+                InstructionKind::Slice { target, range } => RegisterInstruction::Slice {
+                    dest,
+                    target: self.home(*target),
+                    range: self.home(*range),
+                },
+                InstructionKind::IterStart(value) => RegisterInstruction::IterStart(self.home(*value)),
+                InstructionKind::Print(value) => RegisterInstruction::Print(self.home(*value)),
+                InstructionKind::Assert { condition, message } => RegisterInstruction::Assert {
+                    condition: self.home(*condition),
+                    message: message.map(|message| self.home(message)),
+                    location: self.compiler.location_constant(),
+                },
+                InstructionKind::AssertEq { left, right } => RegisterInstruction::AssertEq {
+                    left: self.home(*left),
+                    right: self.home(*right),
+                    location: self.compiler.location_constant(),
+                },
+            };
+            self.compiler.emit(compiled);
+        }
+
+        let terminator = &block.terminator;
+        self.compiler.current_span = terminator.span;
+        match terminator.kind {
+            TerminatorKind::Jump(target) => self.compile_edge(id, target, true),
+            TerminatorKind::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                let condition = self.home(condition);
+                let branch = self.compiler.emit(RegisterInstruction::JumpIfFalse { condition, target: 0 });
+                self.compile_edges(id, branch, then_block, else_block);
+            }
+            TerminatorKind::Next { item, body, exit, .. } => {
+                // The engine iterates over its innermost loop, which is
+                // this one, so the iterator itself needs no register
+                let item = self.register(item);
+                let next = self.compiler.emit(RegisterInstruction::IterNext { item, exit: 0 });
+                self.compile_edges(id, next, body, exit);
+            }
+            TerminatorKind::Return(value) => {
+                let value = self.home(value);
+                self.compiler.emit(RegisterInstruction::Return(value));
+            }
+            TerminatorKind::Halt => {
+                self.compiler.emit(RegisterInstruction::Halt);
+            }
+        }
+    }
+
+    /// The edges out of a block ending in `branch`, which goes to `taken`
+    /// by jumping and to `fallen` by falling through
+    fn compile_edges(&mut self, from: BlockId, branch: usize, fallen: BlockId, taken: BlockId) {
+        if self.has_copies(from, taken) {
+            self.compile_edge(from, fallen, false);
+            let here = self.compiler.module.instructions.len();
+            self.patch(branch, here);
+            self.compile_edge(from, taken, true);
+        } else {
+            self.jumps.push((branch, taken));
+            self.compile_edge(from, fallen, true);
+        }
+    }
+
+    /// Move values into the phis of `to` and continue there. The moves
+    /// happen in parallel, through scratch registers when a phi's register
+    /// is read by another move.
+    fn compile_edge(&mut self, from: BlockId, to: BlockId, may_fall_through: bool) {
+        let moves: Vec<(u32, Temp)> = self
+            .function
+            .block(to)
+            .phis
+            .iter()
+            .filter_map(|phi| {
+                let (_, value) = phi.incoming.iter().find(|(pred, _)| *pred == from)?;
+                Some((self.register(phi.dest), *value))
+            })
+            .filter(|&(dest, value)| self.home(value) != Operand::Register(dest))
+            .collect();
+        let overlaps = moves.iter().any(|&(dest, _)| {
+            moves.iter().any(|&(_, value)| self.home(value) == Operand::Register(dest))
+        });
+        if overlaps {
+            for (index, &(_, value)) in moves.iter().enumerate() {
+                let dest = self.scratch(index as u32);
+                self.compiler.emit(self.move_to(dest, value, from));
+            }
+            for (index, &(dest, _)) in moves.iter().enumerate() {
+                let source = self.scratch(index as u32);
+                self.compiler.emit(RegisterInstruction::Take { dest, source });
+            }
+        } else {
+            for (dest, value) in moves {
+                self.compiler.emit(self.move_to(dest, value, from));
+            }
+        }
+        if !(may_fall_through && to.index() == from.index() + 1) {
+            let jump = self.compiler.emit(RegisterInstruction::Jump(0));
+            self.jumps.push((jump, to));
+        }
+    }
+
+    /// Move `value` into `dest` on the edge out of `from`, without a copy
+    /// when nothing reads the value again
+    fn move_to(&self, dest: u32, value: Temp, from: BlockId) -> RegisterInstruction {
+        match self.home(value) {
+            Operand::Register(source) if self.moved_from[value.index()] == Some(from) => {
+                RegisterInstruction::Take { dest, source }
+            }
+            source => RegisterInstruction::Move { dest, source },
+        }
+    }
+
+    /// Whether taking the edge from `from` to `to` moves values into phis
+    fn has_copies(&self, from: BlockId, to: BlockId) -> bool {
+        self.function
+            .block(to)
+            .phis
+            .iter()
+            .any(|phi| phi.incoming.iter().any(|(pred, _)| *pred == from))
+    }
+
+    /// Point the jump at `at` to instruction `target`
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.compiler.module.instructions[at] {
+            RegisterInstruction::Jump(jump) => *jump = target,
+            RegisterInstruction::JumpIfFalse { target: jump, .. } => *jump = target,
+            RegisterInstruction::IterNext { exit, .. } => *exit = target,
+            other => unreachable!("patching {:?}, which does not jump", other),
+        }
+    }
+
+    fn home(&self, temp: Temp) -> Operand {
+        self.homes[temp.index()]
+    }
+
+    fn operands(&self, temps: &[Temp]) -> Vec<Operand> {
+        temps.iter().map(|&temp| self.home(temp)).collect()
+    }
+
+    /// The register a temporary is computed into
+    fn register(&self, temp: Temp) -> u32 {
+        match self.home(temp) {
+            Operand::Register(register) => register,
+            Operand::Constant(_) => unreachable!("{} is a constant", temp),
+        }
+    }
+
+    /// Scratch register `index`, growing the frame to hold it
+    fn scratch(&mut self, index: u32) -> u32 {
+        let register = self.temp_registers + index;
+        self.registers = self.registers.max(register + 1);
+        register
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode_pass_manager;
+    use crate::compiler::BytecodeCompiler;
+    use crate::register_vm::RegisterMachine;
+    use crate::vm::VirtualMachine;
+    use artificial_core::codes::ErrorCode;
+    use artificial_core::{ir_pass_manager, lower_module, Lexer, LoweringOptions, MacroExpander, OptLevel, Parser, Resolver};
+    use std::fs;
+    use std::path::Path;
+
+    /// What a run did: the output, and the code, message and span of the
+    /// error that stopped it, if one did
+    type Outcome = (String, Option<(ErrorCode, String, Option<Span>)>);
+
+    /// Lower and optimize a program as `ALC` does at `level`
+    fn compile(source: &str, level: OptLevel) -> IrModule {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "lex errors: {:?}", lex_errors);
+        let tokens = MacroExpander::new().expand(tokens).expect("expansion failed");
+        let (ast, parse_errors) = Parser::new(tokens).parse_module();
+        assert!(parse_errors.is_empty(), "parse errors: {:?}", parse_errors);
+        Resolver::new().resolve(&ast).expect("resolution failed");
+        let mut ir = lower_module(ast, &LoweringOptions::default());
+        ir_pass_manager(level, None).run(&mut ir).expect("IR passes failed");
+        ir
+    }
+
+    fn run_stack(ir: &IrModule, level: OptLevel) -> Outcome {
+        let mut bytecode = BytecodeCompiler::new()
+            .compile(ir)
+            .unwrap_or_else(|errors| panic!("{}", errors[0].message));
+        bytecode_pass_manager(level, false)
+            .run(&mut bytecode)
+            .expect("bytecode passes failed");
+        let mut vm = VirtualMachine::with_captured_output();
+        let error = vm.execute(&bytecode).err().map(|error| {
+            let diagnostic = error.to_diagnostic(&bytecode.source_map);
+            (diagnostic.code, diagnostic.message, diagnostic.span)
+        });
+        (vm.get_output().to_string(), error)
+    }
+
+    fn run_register(ir: &IrModule) -> Outcome {
+        let code = RegisterCompiler::new().compile(ir);
+        let mut vm = RegisterMachine::with_captured_output();
+        let error = vm.execute(&code).err().map(|error| {
+            let diagnostic = error.to_diagnostic(&code.source_map);
+            (diagnostic.code, diagnostic.message, diagnostic.span)
+        });
+        (vm.get_output().to_string(), error)
+    }
+
+    /// Run `source` on both engines at -O0 and -O2 and check they agree;
+    /// returns what the -O0 run did
+    fn assert_engines_agree(name: &str, source: &str) -> Outcome {
+        let mut outcomes = Vec::new();
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let ir = compile(source, level);
+            let stack = run_stack(&ir, level);
+            let register = run_register(&ir);
+            assert_eq!(stack, register, "{} at {:?}: the engines disagree", name, level);
+            outcomes.push(stack);
+        }
+        outcomes.swap_remove(0)
+    }
+
+    #[test]
+    fn engines_agree_on_the_bench_programs() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs");
+        let mut programs: Vec<_> = fs::read_dir(&directory)
+            .expect("benches/programs is missing")
+            .map(|entry| entry.expect("unreadable directory entry").path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "art"))
+            .collect();
+        programs.sort();
+        assert!(!programs.is_empty(), "no programs in {}", directory.display());
+
+        for path in programs {
+            let source = fs::read_to_string(&path).expect("unreadable program");
+            let (output, error) = assert_engines_agree(&path.display().to_string(), &source);
+            assert!(error.is_none(), "{}: {:?}", path.display(), error);
+            assert!(!output.is_empty(), "{} printed nothing", path.display());
+        }
+    }
+
+    #[test]
+    fn engines_agree_when_phis_swap_values() {
+        let source = "let mut a = 1\nlet mut b = 2\nfor i in 0..5 {\n    let t = a\n    a = b\n    b = t\n}\naccrete \"${a} ${b}\"\n";
+        assert_eq!(assert_engines_agree("swap", source), ("2 1\n".to_string(), None));
+    }
+
+    #[test]
+    fn engines_agree_when_a_value_is_moved_on_a_backedge() {
+        let source = "let base = \"s${1}\"\nlet mut cur = \"init\"\nfor i in 0..4 {\n    accrete cur\n    if i > 0 {\n        cur = base\n    } else {\n        cur = \"first\"\n    }\n}\naccrete cur\n";
+        let expected = "init\nfirst\ns1\ns1\ns1\n".to_string();
+        assert_eq!(assert_engines_agree("backedge", source), (expected, None));
+    }
+
+    #[test]
+    fn engines_agree_on_runtime_errors() {
+        let source = "fn divide(a, b) {\n    a / b\n}\naccrete 1\naccrete divide(1, 0)\naccrete 2\n";
+        let (output, error) = assert_engines_agree("division", source);
+        assert_eq!(output, "1\n");
+        let (_, message, span) = error.expect("dividing by zero should fail");
+        assert!(message.starts_with("Division by zero"), "{}", message);
+        assert_eq!(span.map(|span| span.line), Some(2));
+    }
+
+    #[test]
+    fn engines_agree_on_programs_with_more_than_65536_constants() {
+        let count = u16::MAX as usize + 10;
+        let source: String = (0..count).map(|n| format!("accrete \"c{}\"\n", n)).collect();
+        let expected: String = (0..count).map(|n| format!("c{}\n", n)).collect();
+        assert_eq!(assert_engines_agree("constants", &source), (expected, None));
+
+        let bytecode = BytecodeCompiler::new().compile(&compile(&source, OptLevel::O2)).unwrap();
+        assert_eq!(bytecode.constants.len(), count);
+        let listing = bytecode.to_string();
+        assert!(listing.contains("PushConstWide 65536  ; \"c65536\""), "no wide constant");
+        assert!(listing.contains("PushConst 65535  ; \"c65535\""), "{}", &listing[..200]);
+    }
+}
@@ -0,0 +1,381 @@
+// artificial-language/artificial-vm/src/register_vm.rs
+use crate::bytecode::Value;
+use crate::register::{Operand, RegisterInstruction, RegisterModule};
+use crate::vm::{binary, slice, Iteration, VmError, MAX_CALL_DEPTH};
+use artificial_core::codes;
+use artificial_core::ops::{self, OpError};
+use artificial_core::{BinaryOp, Constant, UnaryOp};
+use std::io::{self, Write};
+
+/// A function call in progress
+#[derive(Debug)]
+struct Frame {
+    /// Where the caller continues
+    return_pc: usize,
+    /// The caller's register 0
+    base: usize,
+    /// The caller's register that receives the result
+    dest: usize,
+    /// Loop state of the caller, restored on return
+    iterations_base: usize,
+}
+
+/// Register-based Virtual Machine for Artificial Language. Programs behave
+/// exactly as on `VirtualMachine`, errors included; only the instruction
+/// indices in errors differ.
+pub struct RegisterMachine {
+    /// The registers of every active frame, innermost last
+    registers: Vec<Value>,
+    /// Register 0 of the innermost frame
+    base: usize,
+    /// Active loops, innermost last
+    iterations: Vec<Iteration>,
+    /// Active function calls, innermost last
+    frames: Vec<Frame>,
+    /// Index of the next instruction
+    pc: usize,
+    /// Captured output (used for benchmarks)
+    output: String,
+    /// Whether to capture output or print directly
+    capture_output: bool,
+}
+
+impl RegisterMachine {
+    pub fn new() -> Self {
+        RegisterMachine {
+            registers: Vec::with_capacity(256),
+            base: 0,
+            iterations: Vec::new(),
+            frames: Vec::new(),
+            pc: 0,
+            output: String::new(),
+            capture_output: false,
+        }
+    }
+
+    /// Create a machine that captures output instead of printing
+    pub fn with_captured_output() -> Self {
+        RegisterMachine {
+            capture_output: true,
+            ..Self::new()
+        }
+    }
+
+    /// Execute a register module
+    pub fn execute(&mut self, module: &RegisterModule) -> Result<(), VmError> {
+        self.registers.clear();
+        self.registers.resize(module.main.registers as usize, Value::Null);
+        self.base = 0;
+        self.iterations.clear();
+        self.frames.clear();
+        self.output.clear();
+        self.pc = module.main.entry;
+
+        while let Some(instruction) = module.instructions.get(self.pc) {
+            let pc = self.pc;
+            self.pc += 1;
+            let error = |e: OpError| VmError {
+                code: e.code,
+                message: e.message,
+                ip: pc,
+            };
+
+            match instruction {
+                RegisterInstruction::Move { dest, source } => {
+                    let value = self.read(module, *source).clone();
+                    self.write(*dest, value);
+                }
+
+                RegisterInstruction::Take { dest, source } => {
+                    let value = std::mem::replace(&mut self.registers[self.base + *source as usize], Value::Null);
+                    self.write(*dest, value);
+                }
+
+                RegisterInstruction::Call { dest, function, args } => {
+                    if self.frames.len() >= MAX_CALL_DEPTH {
+                        return Err(VmError {
+                            code: codes::STACK_OVERFLOW,
+                            message: format!("Stack overflow: more than {} nested calls", MAX_CALL_DEPTH),
+                            ip: pc,
+                        });
+                    }
+                    let callee = module.functions[*function as usize];
+                    // The callee's registers start after every register in use
+                    let base = self.registers.len();
+                    for &arg in args {
+                        let value = self.read(module, arg).clone();
+                        self.registers.push(value);
+                    }
+                    self.registers.resize(base + callee.registers as usize, Value::Null);
+                    self.frames.push(Frame {
+                        return_pc: self.pc,
+                        base: self.base,
+                        dest: self.base + *dest as usize,
+                        iterations_base: self.iterations.len(),
+                    });
+                    self.base = base;
+                    self.pc = callee.entry;
+                }
+
+                RegisterInstruction::TailCall { function, args } => {
+                    let frame = self.frames.last().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "Tail call outside of a function".to_string(),
+                        ip: pc,
+                    })?;
+                    self.iterations.truncate(frame.iterations_base);
+                    let callee = module.functions[*function as usize];
+                    // The arguments go above the frame, which is then
+                    // dropped from under them
+                    let top = self.registers.len();
+                    for &arg in args {
+                        let value = self.read(module, arg).clone();
+                        self.registers.push(value);
+                    }
+                    self.registers.drain(self.base..top);
+                    self.registers.resize(self.base + callee.registers as usize, Value::Null);
+                    self.pc = callee.entry;
+                }
+
+                RegisterInstruction::Return(value) => {
+                    let value = match *value {
+                        Operand::Register(register) => {
+                            std::mem::replace(&mut self.registers[self.base + register as usize], Value::Null)
+                        }
+                        Operand::Constant(index) => module.constants[index as usize].clone(),
+                    };
+                    let frame = self.frames.pop().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "Return outside of a function".to_string(),
+                        ip: pc,
+                    })?;
+                    self.registers.truncate(self.base);
+                    self.iterations.truncate(frame.iterations_base);
+                    self.registers[frame.dest] = value;
+                    self.base = frame.base;
+                    self.pc = frame.return_pc;
+                }
+
+                RegisterInstruction::Jump(target) => self.pc = *target,
+
+                RegisterInstruction::JumpIfFalse { condition, target } => match self.read(module, *condition) {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => self.pc = *target,
+                    other => {
+                        return Err(VmError {
+                            code: codes::TYPE_MISMATCH,
+                            message: format!("Condition must be a boolean, found {}", other),
+                            ip: pc,
+                        });
+                    }
+                },
+
+                RegisterInstruction::Binary { op, dest, left, right } => {
+                    let left = self.read(module, *left);
+                    let right = self.read(module, *right);
+                    let result = match (op, left, right) {
+                        (BinaryOp::Equal, _, _) => Value::Boolean(left == right),
+                        (BinaryOp::NotEqual, _, _) => Value::Boolean(left != right),
+                        // Integers need no copies of their operands
+                        (_, Value::Integer(left), Value::Integer(right)) => {
+                            let (left, right) = (Constant::Integer(*left), Constant::Integer(*right));
+                            ops::evaluate_binary(*op, &left, &right).map(Value::from).map_err(error)?
+                        }
+                        _ => binary(*op, left.clone(), right.clone()).map_err(error)?,
+                    };
+                    self.write(*dest, result);
+                }
+
+                RegisterInstruction::Negate { dest, operand } => {
+                    let result = match self.read(module, *operand).clone().into_constant() {
+                        Ok(operand) => ops::evaluate_unary(UnaryOp::Negate, &operand).map(Value::from),
+                        Err(other) => Err(ops::unary_type_mismatch(UnaryOp::Negate, other.type_name())),
+                    };
+                    let result = result.map_err(error)?;
+                    self.write(*dest, result);
+                }
+
+                RegisterInstruction::IsNull { dest, value } => {
+                    let result = Value::Boolean(*self.read(module, *value) == Value::Null);
+                    self.write(*dest, result);
+                }
+
+                RegisterInstruction::List { dest, items } => {
+                    let items = items.iter().map(|&item| self.read(module, item).clone()).collect();
+                    self.write(*dest, Value::List(items));
+                }
+
+                RegisterInstruction::Concat { dest, parts } => {
+                    let mut text = String::new();
+                    for &part in parts {
+                        text.push_str(&self.read(module, part).to_string());
+                    }
+                    self.write(*dest, Value::String(text));
+                }
+
+                RegisterInstruction::Range {
+                    dest,
+                    start,
+                    end,
+                    step,
+                    inclusive,
+                } => {
+                    // Checked in the order the stack machine pops them
+                    let step = self.integer(module, *step, "Range step", pc)?;
+                    let end = self.integer(module, *end, "Range end", pc)?;
+                    let start = self.integer(module, *start, "Range start", pc)?;
+                    if step <= 0 {
+                        return Err(VmError {
+                            code: codes::INVALID_RANGE,
+                            message: format!("Range step must be positive, found {}", step),
+                            ip: pc,
+                        });
+                    }
+                    let range = Value::Range {
+                        start,
+                        end,
+                        step,
+                        inclusive: *inclusive,
+                    };
+                    self.write(*dest, range);
+                }
+
+                RegisterInstruction::Slice { dest, target, range } => {
+                    let target = self.read(module, *target);
+                    let range = self.read(module, *range);
+                    let sliced = slice(target, range).map_err(|e| VmError {
+                        code: e.code,
+                        message: e.message,
+                        ip: pc,
+                    })?;
+                    self.write(*dest, sliced);
+                }
+
+                RegisterInstruction::IterStart(value) => {
+                    let value = self.read(module, *value).clone();
+                    let iteration = Iteration::over(value).map_err(|other| VmError {
+                        code: codes::TYPE_MISMATCH,
+                        message: format!("Cannot iterate over {}", other),
+                        ip: pc,
+                    })?;
+                    self.iterations.push(iteration);
+                }
+
+                RegisterInstruction::IterNext { item, exit } => {
+                    let iteration = self.iterations.last_mut().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "IterNext outside of a loop".to_string(),
+                        ip: pc,
+                    })?;
+                    match iteration.next_item() {
+                        Some(value) => self.write(*item, value),
+                        None => {
+                            self.iterations.pop();
+                            self.pc = *exit;
+                        }
+                    }
+                }
+
+                RegisterInstruction::Print(value) => {
+                    let text = self.read(module, *value).to_string();
+                    self.print_line(&text);
+                }
+
+                RegisterInstruction::Assert {
+                    condition,
+                    message,
+                    location,
+                } => {
+                    let location = &module.constants[*location as usize];
+                    match self.read(module, *condition) {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) => {
+                            let message = match message.map(|message| self.read(module, message)) {
+                                None | Some(Value::Null) => format!("Assertion failed at {}", location),
+                                Some(message) => format!("Assertion failed at {}: {}", location, message),
+                            };
+                            return Err(VmError {
+                                code: codes::ASSERTION_FAILED,
+                                message,
+                                ip: pc,
+                            });
+                        }
+                        other => {
+                            return Err(VmError {
+                                code: codes::TYPE_MISMATCH,
+                                message: format!(
+                                    "Assertion condition at {} must be a boolean, found {}",
+                                    location, other
+                                ),
+                                ip: pc,
+                            });
+                        }
+                    }
+                }
+
+                RegisterInstruction::AssertEq { left, right, location } => {
+                    let left = self.read(module, *left);
+                    let right = self.read(module, *right);
+                    if left != right {
+                        return Err(VmError {
+                            code: codes::ASSERTION_FAILED,
+                            message: format!(
+                                "Assertion failed at {}: left: {}, right: {}",
+                                module.constants[*location as usize], left, right
+                            ),
+                            ip: pc,
+                        });
+                    }
+                }
+
+                RegisterInstruction::Halt => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get captured output
+    pub fn get_output(&self) -> &str {
+        &self.output
+    }
+
+    /// The value of an operand, in place
+    fn read<'a>(&'a self, module: &'a RegisterModule, operand: Operand) -> &'a Value {
+        match operand {
+            Operand::Register(register) => &self.registers[self.base + register as usize],
+            Operand::Constant(index) => &module.constants[index as usize],
+        }
+    }
+
+    fn write(&mut self, register: u32, value: Value) {
+        self.registers[self.base + register as usize] = value;
+    }
+
+    fn integer(&self, module: &RegisterModule, operand: Operand, what: &str, pc: usize) -> Result<i64, VmError> {
+        match self.read(module, operand) {
+            Value::Integer(n) => Ok(*n),
+            other => Err(VmError {
+                code: codes::TYPE_MISMATCH,
+                message: format!("{} must be an integer, found {}", what, other),
+                ip: pc,
+            }),
+        }
+    }
+
+    fn print_line(&mut self, text: &str) {
+        if self.capture_output {
+            self.output.push_str(text);
+            self.output.push('\n');
+        } else {
+            println!("{}", text);
+            io::stdout().flush().ok();
+        }
+    }
+}
+
+impl Default for RegisterMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,5 @@
 // artificial-language/artificial-vm/src/serializer.rs
-use crate::bytecode::{BytecodeModule, Value};
+use crate::bytecode::{BytecodeModule, Value, MAX_CONSTANTS};
 use std::io::{self, Read, Write};
 
 /// Magic bytes for Artificial Language Bytecode files
@@ -13,6 +13,8 @@ mod type_tags {
     pub const STRING: u8 = 1;
     pub const INTEGER: u8 = 2;
     pub const BOOLEAN: u8 = 3;
+    pub const RANGE: u8 = 4;
+    pub const LIST: u8 = 5;
 }
 
 /// Serialize a bytecode module to bytes
@@ -60,6 +62,26 @@ fn serialize_value(bytes: &mut Vec<u8>, value: &Value) {
             bytes.push(type_tags::BOOLEAN);
             bytes.push(if *b { 1 } else { 0 });
         }
+        Value::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => {
+            bytes.push(type_tags::RANGE);
+            bytes.extend_from_slice(&start.to_be_bytes());
+            bytes.extend_from_slice(&end.to_be_bytes());
+            bytes.extend_from_slice(&step.to_be_bytes());
+            bytes.push(if *inclusive { 1 } else { 0 });
+        }
+        Value::List(items) => {
+            bytes.push(type_tags::LIST);
+            let len = items.len() as u32;
+            bytes.extend_from_slice(&len.to_be_bytes());
+            for item in items {
+                serialize_value(bytes, item);
+            }
+        }
     }
 }
 
@@ -94,6 +116,12 @@ pub fn deserialize_from_reader<R: Read>(reader: &mut R) -> io::Result<BytecodeMo
     let mut const_count_bytes = [0u8; 4];
     reader.read_exact(&mut const_count_bytes)?;
     let const_count = u32::from_be_bytes(const_count_bytes) as usize;
+    if const_count > MAX_CONSTANTS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Too many constants: {} (at most {})", const_count, MAX_CONSTANTS),
+        ));
+    }
 
     let mut constants = Vec::with_capacity(const_count);
     for _ in 0..const_count {
@@ -108,10 +136,7 @@ pub fn deserialize_from_reader<R: Read>(reader: &mut R) -> io::Result<BytecodeMo
     let mut instructions = vec![0u8; instr_count];
     reader.read_exact(&mut instructions)?;
 
-    Ok(BytecodeModule {
-        constants,
-        instructions,
-    })
+    Ok(BytecodeModule::from_parts(constants, instructions))
 }
 
 fn deserialize_value<R: Read>(reader: &mut R) -> io::Result<Value> {
@@ -141,6 +166,31 @@ fn deserialize_value<R: Read>(reader: &mut R) -> io::Result<Value> {
             reader.read_exact(&mut bool_byte)?;
             Ok(Value::Boolean(bool_byte[0] != 0))
         }
+        type_tags::RANGE => {
+            let mut range_bytes = [0u8; 25];
+            reader.read_exact(&mut range_bytes)?;
+            let int_at = |offset: usize| {
+                let mut int_bytes = [0u8; 8];
+                int_bytes.copy_from_slice(&range_bytes[offset..offset + 8]);
+                i64::from_be_bytes(int_bytes)
+            };
+            Ok(Value::Range {
+                start: int_at(0),
+                end: int_at(8),
+                step: int_at(16),
+                inclusive: range_bytes[24] != 0,
+            })
+        }
+        type_tags::LIST => {
+            let mut len_bytes = [0u8; 4];
+            reader.read_exact(&mut len_bytes)?;
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            let mut items = Vec::with_capacity(len);
+            for _ in 0..len {
+                items.push(deserialize_value(reader)?);
+            }
+            Ok(Value::List(items))
+        }
         _ => Err(io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Unknown value type tag: {}", type_tag[0]),
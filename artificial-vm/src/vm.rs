@@ -1,11 +1,16 @@
 // artificial-language/artificial-vm/src/vm.rs
-use crate::bytecode::{BytecodeModule, Opcode, Value};
+use crate::bytecode::{BytecodeModule, Opcode, SourceMap, Value};
+use artificial_core::codes::{self, ErrorCode};
+use artificial_core::ops::{self, OpError};
+use artificial_core::{BinaryOp, Constant, Diagnostic, UnaryOp};
 use std::fmt;
 use std::io::{self, Write};
 
 /// Virtual Machine execution errors
 #[derive(Debug)]
 pub struct VmError {
+    /// A stable code from `artificial_core::codes`
+    pub code: ErrorCode,
     pub message: String,
     pub ip: usize,
 }
@@ -16,12 +21,126 @@ impl fmt::Display for VmError {
     }
 }
 
+impl VmError {
+    /// Locate the error in the source through the module's source map
+    pub fn to_diagnostic(&self, source_map: &SourceMap) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(self.code, self.message.clone())
+            .with_note(format!("raised by instruction {}", self.ip));
+        if self.code == codes::NULL_RECEIVER {
+            diagnostic = diagnostic.with_help("check for null with `if let`, or give a fallback with `??`".to_string());
+        }
+        match source_map.span_at(self.ip) {
+            Some(span) => diagnostic.with_span(span),
+            None => diagnostic,
+        }
+    }
+}
+
 impl std::error::Error for VmError {}
 
+/// Deepest nesting of function calls before execution is aborted
+pub(crate) const MAX_CALL_DEPTH: usize = 10_000;
+
+/// A function call in progress
+#[derive(Debug)]
+struct Frame {
+    /// Where the caller continues
+    return_ip: usize,
+    /// Index of the frame's local slot 0
+    locals_base: usize,
+    /// Operand stack and loop state of the caller, restored on return
+    stack_base: usize,
+    iterations_base: usize,
+}
+
+/// State of an in-progress `for` loop
+#[derive(Debug)]
+pub(crate) enum Iteration {
+    Range {
+        next: Option<i64>,
+        end: i64,
+        step: i64,
+        inclusive: bool,
+    },
+    Chars {
+        chars: Vec<char>,
+        index: usize,
+    },
+    Items {
+        items: Vec<Value>,
+        index: usize,
+    },
+}
+
+impl Iteration {
+    /// Start iterating over a value, or give it back if it has no items
+    pub(crate) fn over(value: Value) -> Result<Iteration, Value> {
+        match value {
+            Value::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => Ok(Iteration::Range {
+                next: Some(start),
+                end,
+                step,
+                inclusive,
+            }),
+            Value::String(s) => Ok(Iteration::Chars {
+                chars: s.chars().collect(),
+                index: 0,
+            }),
+            Value::List(items) => Ok(Iteration::Items { items, index: 0 }),
+            other => Err(other),
+        }
+    }
+
+    pub(crate) fn next_item(&mut self) -> Option<Value> {
+        match self {
+            Iteration::Range {
+                next,
+                end,
+                step,
+                inclusive,
+            } => {
+                let current = (*next)?;
+                let in_range = if *inclusive {
+                    current <= *end
+                } else {
+                    current < *end
+                };
+                if !in_range {
+                    *next = None;
+                    return None;
+                }
+                *next = current.checked_add(*step);
+                Some(Value::Integer(current))
+            }
+            Iteration::Chars { chars, index } => {
+                let ch = chars.get(*index)?;
+                *index += 1;
+                Some(Value::String(ch.to_string()))
+            }
+            Iteration::Items { items, index } => {
+                let item = items.get(*index)?.clone();
+                *index += 1;
+                Some(item)
+            }
+        }
+    }
+}
+
 /// Stack-based Virtual Machine for Artificial Language
 pub struct VirtualMachine {
     /// Operand stack
     stack: Vec<Value>,
+    /// Local variable slots
+    locals: Vec<Value>,
+    /// Active loops, innermost last
+    iterations: Vec<Iteration>,
+    /// Active function calls, innermost last
+    frames: Vec<Frame>,
     /// Instruction pointer
     ip: usize,
     /// Captured output (used for bundled execution)
@@ -34,6 +153,9 @@ impl VirtualMachine {
     pub fn new() -> Self {
         VirtualMachine {
             stack: Vec::with_capacity(256),
+            locals: Vec::new(),
+            iterations: Vec::new(),
+            frames: Vec::new(),
             ip: 0,
             output: String::new(),
             capture_output: false,
@@ -44,6 +166,9 @@ impl VirtualMachine {
     pub fn with_captured_output() -> Self {
         VirtualMachine {
             stack: Vec::with_capacity(256),
+            locals: Vec::new(),
+            iterations: Vec::new(),
+            frames: Vec::new(),
             ip: 0,
             output: String::new(),
             capture_output: true,
@@ -54,11 +179,15 @@ impl VirtualMachine {
     pub fn execute(&mut self, module: &BytecodeModule) -> Result<(), VmError> {
         self.ip = 0;
         self.stack.clear();
+        self.locals.clear();
+        self.iterations.clear();
+        self.frames.clear();
         self.output.clear();
 
         while self.ip < module.instructions.len() {
             let opcode_byte = module.instructions[self.ip];
             let opcode = Opcode::from_u8(opcode_byte).ok_or_else(|| VmError {
+                code: codes::INVALID_BYTECODE,
                 message: format!("Unknown opcode: 0x{:02X}", opcode_byte),
                 ip: self.ip,
             })?;
@@ -73,11 +202,16 @@ impl VirtualMachine {
                 }
 
                 Opcode::PushConst => {
+                    let start = self.ip;
                     let index = self.read_u16(module)?;
-                    let value = module.constants.get(index as usize).ok_or_else(|| VmError {
-                        message: format!("Constant index {} out of bounds", index),
-                        ip: self.ip - 3,
-                    })?;
+                    let value = self.constant(module, index as usize, start)?;
+                    self.stack.push(value.clone());
+                }
+
+                Opcode::PushConstWide => {
+                    let start = self.ip;
+                    let index = self.read_u32(module)?;
+                    let value = self.constant(module, index as usize, start)?;
                     self.stack.push(value.clone());
                 }
 
@@ -93,10 +227,357 @@ impl VirtualMachine {
                     self.ip += 1;
                 }
 
+                Opcode::PrintConst => {
+                    let start = self.ip;
+                    let index = self.read_u16(module)?;
+                    let value = self.constant(module, index as usize, start)?;
+                    self.print_value(value, true)?;
+                }
+
                 Opcode::Pop => {
                     self.pop()?;
                     self.ip += 1;
                 }
+
+                Opcode::Assert => {
+                    let start = self.ip;
+                    let location = self.read_location(module)?;
+                    let message = self.pop()?;
+                    let condition = self.pop()?;
+                    match condition {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) => {
+                            let message = match message {
+                                Value::Null => format!("Assertion failed at {}", location),
+                                message => format!("Assertion failed at {}: {}", location, message),
+                            };
+                            return Err(VmError {
+                                code: codes::ASSERTION_FAILED,
+                                message,
+                                ip: start,
+                            });
+                        }
+                        other => {
+                            return Err(VmError {
+                                code: codes::TYPE_MISMATCH,
+                                message: format!(
+                                    "Assertion condition at {} must be a boolean, found {}",
+                                    location, other
+                                ),
+                                ip: start,
+                            });
+                        }
+                    }
+                }
+
+                Opcode::Jump => {
+                    self.ip = self.read_jump_target(module)?;
+                }
+
+                Opcode::JumpIfFalse => {
+                    let start = self.ip;
+                    let target = self.read_jump_target(module)?;
+                    match self.pop()? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) => self.ip = target,
+                        other => {
+                            return Err(VmError {
+                                code: codes::TYPE_MISMATCH,
+                                message: format!("Condition must be a boolean, found {}", other),
+                                ip: start,
+                            });
+                        }
+                    }
+                }
+
+                Opcode::Dup => {
+                    let value = self.stack.last().cloned().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "Stack underflow".to_string(),
+                        ip: self.ip,
+                    })?;
+                    self.stack.push(value);
+                    self.ip += 1;
+                }
+
+                Opcode::Equal => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    self.stack.push(Value::Boolean(left == right));
+                    self.ip += 1;
+                }
+
+                Opcode::NotEqual => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    self.stack.push(Value::Boolean(left != right));
+                    self.ip += 1;
+                }
+
+                Opcode::Add
+                | Opcode::Subtract
+                | Opcode::Multiply
+                | Opcode::Divide
+                | Opcode::Remainder
+                | Opcode::Less
+                | Opcode::LessEqual
+                | Opcode::Greater
+                | Opcode::GreaterEqual => {
+                    let op = opcode.binary_op().unwrap();
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    let result = binary(op, left, right).map_err(|e| VmError {
+                        code: e.code,
+                        message: e.message,
+                        ip: self.ip,
+                    })?;
+                    self.stack.push(result);
+                    self.ip += 1;
+                }
+
+                Opcode::Negate => {
+                    let operand = self.pop()?;
+                    let result = match operand.into_constant() {
+                        Ok(operand) => ops::evaluate_unary(UnaryOp::Negate, &operand).map(Value::from),
+                        Err(other) => Err(ops::unary_type_mismatch(UnaryOp::Negate, other.type_name())),
+                    };
+                    let result = result.map_err(|e| VmError {
+                        code: e.code,
+                        message: e.message,
+                        ip: self.ip,
+                    })?;
+                    self.stack.push(result);
+                    self.ip += 1;
+                }
+
+                Opcode::Concat => {
+                    let start = self.ip;
+                    let count = self.read_u16(module)? as usize;
+                    if count > self.stack.len() {
+                        return Err(VmError {
+                            code: codes::INVALID_BYTECODE,
+                            message: "Stack underflow".to_string(),
+                            ip: start,
+                        });
+                    }
+                    let mut text = String::new();
+                    for value in self.stack.drain(self.stack.len() - count..) {
+                        text.push_str(&value.to_string());
+                    }
+                    self.stack.push(Value::String(text));
+                }
+
+                Opcode::IsNull => {
+                    let value = self.pop()?;
+                    self.stack.push(Value::Boolean(value == Value::Null));
+                    self.ip += 1;
+                }
+
+                Opcode::MakeRange => {
+                    let start_ip = self.ip;
+                    let inclusive = self.read_u8(module)? != 0;
+                    let step = self.pop_integer("Range step", start_ip)?;
+                    let end = self.pop_integer("Range end", start_ip)?;
+                    let start = self.pop_integer("Range start", start_ip)?;
+                    if step <= 0 {
+                        return Err(VmError {
+                            code: codes::INVALID_RANGE,
+                            message: format!("Range step must be positive, found {}", step),
+                            ip: start_ip,
+                        });
+                    }
+                    self.stack.push(Value::Range {
+                        start,
+                        end,
+                        step,
+                        inclusive,
+                    });
+                }
+
+                Opcode::Slice => {
+                    let range = self.pop()?;
+                    let target = self.pop()?;
+                    let sliced = slice(&target, &range).map_err(|e| VmError {
+                        code: e.code,
+                        message: e.message,
+                        ip: self.ip,
+                    })?;
+                    self.stack.push(sliced);
+                    self.ip += 1;
+                }
+
+                Opcode::LoadLocal => {
+                    let slot = self.locals_base() + self.read_u16(module)? as usize;
+                    let value = self.locals.get(slot).cloned().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: format!("Read of uninitialized local {}", slot),
+                        ip: self.ip - 3,
+                    })?;
+                    self.stack.push(value);
+                }
+
+                Opcode::AddLocalConst => {
+                    let start = self.ip;
+                    let slot = self.locals_base() + self.read_u16(module)? as usize;
+                    // The constant index directly follows the slot
+                    self.ip -= 1;
+                    let index = self.read_u16(module)?;
+                    let left = self.locals.get(slot).cloned().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: format!("Read of uninitialized local {}", slot),
+                        ip: start,
+                    })?;
+                    let right = self.constant(module, index as usize, start)?.clone();
+                    let result = binary(BinaryOp::Add, left, right).map_err(|e| VmError {
+                        code: e.code,
+                        message: e.message,
+                        ip: start,
+                    })?;
+                    self.stack.push(result);
+                }
+
+                Opcode::StoreLocal => {
+                    let slot = self.locals_base() + self.read_u16(module)? as usize;
+                    let value = self.pop()?;
+                    if slot >= self.locals.len() {
+                        self.locals.resize(slot + 1, Value::Null);
+                    }
+                    self.locals[slot] = value;
+                }
+
+                Opcode::IterStart => {
+                    let iteration = Iteration::over(self.pop()?).map_err(|other| VmError {
+                        code: codes::TYPE_MISMATCH,
+                        message: format!("Cannot iterate over {}", other),
+                        ip: self.ip,
+                    })?;
+                    self.iterations.push(iteration);
+                    self.ip += 1;
+                }
+
+                Opcode::IterNext => {
+                    let start = self.ip;
+                    let target = self.read_jump_target(module)?;
+                    let iteration = self.iterations.last_mut().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "IterNext outside of a loop".to_string(),
+                        ip: start,
+                    })?;
+                    match iteration.next_item() {
+                        Some(item) => self.stack.push(item),
+                        None => {
+                            self.iterations.pop();
+                            self.ip = target;
+                        }
+                    }
+                }
+
+                Opcode::Call => {
+                    let start = self.ip;
+                    let target = self.read_jump_target(module)?;
+                    // The argument count directly follows the target
+                    self.ip -= 1;
+                    let argc = self.read_u16(module)? as usize;
+                    if self.frames.len() >= MAX_CALL_DEPTH {
+                        return Err(VmError {
+                            code: codes::STACK_OVERFLOW,
+                            message: format!(
+                                "Stack overflow: more than {} nested calls",
+                                MAX_CALL_DEPTH
+                            ),
+                            ip: start,
+                        });
+                    }
+                    if argc > self.stack.len() {
+                        return Err(VmError {
+                            code: codes::INVALID_BYTECODE,
+                            message: "Stack underflow".to_string(),
+                            ip: start,
+                        });
+                    }
+                    // The callee's slots start after every slot in use
+                    let arguments = self.stack.split_off(self.stack.len() - argc);
+                    self.frames.push(Frame {
+                        return_ip: self.ip,
+                        locals_base: self.locals.len(),
+                        stack_base: self.stack.len(),
+                        iterations_base: self.iterations.len(),
+                    });
+                    self.locals.extend(arguments);
+                    self.ip = target;
+                }
+
+                Opcode::TailCall => {
+                    let start = self.ip;
+                    let target = self.read_jump_target(module)?;
+                    self.ip -= 1;
+                    let argc = self.read_u16(module)? as usize;
+                    if argc > self.stack.len() {
+                        return Err(VmError {
+                            code: codes::INVALID_BYTECODE,
+                            message: "Stack underflow".to_string(),
+                            ip: start,
+                        });
+                    }
+                    let frame = self.frames.last().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "Tail call outside of a function".to_string(),
+                        ip: start,
+                    })?;
+                    // The callee takes over the frame, and so its return
+                    // address; everything of the caller's is dropped
+                    let arguments = self.stack.split_off(self.stack.len() - argc);
+                    self.stack.truncate(frame.stack_base);
+                    self.locals.truncate(frame.locals_base);
+                    self.iterations.truncate(frame.iterations_base);
+                    self.locals.extend(arguments);
+                    self.ip = target;
+                }
+
+                Opcode::Return => {
+                    let value = self.pop()?;
+                    let frame = self.frames.pop().ok_or_else(|| VmError {
+                        code: codes::INVALID_BYTECODE,
+                        message: "Return outside of a function".to_string(),
+                        ip: self.ip,
+                    })?;
+                    self.stack.truncate(frame.stack_base);
+                    self.locals.truncate(frame.locals_base);
+                    self.iterations.truncate(frame.iterations_base);
+                    self.stack.push(value);
+                    self.ip = frame.return_ip;
+                }
+
+                Opcode::MakeList => {
+                    let start = self.ip;
+                    let count = self.read_u16(module)? as usize;
+                    if count > self.stack.len() {
+                        return Err(VmError {
+                            code: codes::INVALID_BYTECODE,
+                            message: "Stack underflow".to_string(),
+                            ip: start,
+                        });
+                    }
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::List(items));
+                }
+
+                Opcode::AssertEq => {
+                    let start = self.ip;
+                    let location = self.read_location(module)?;
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    if left != right {
+                        return Err(VmError {
+                            code: codes::ASSERTION_FAILED,
+                            message: format!(
+                                "Assertion failed at {}: left: {}, right: {}",
+                                location, left, right
+                            ),
+                            ip: start,
+                        });
+                    }
+                }
             }
         }
 
@@ -108,9 +589,15 @@ impl VirtualMachine {
         &self.output
     }
 
+    /// Local slot 0 of the innermost frame
+    fn locals_base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.locals_base)
+    }
+
     fn read_u16(&mut self, module: &BytecodeModule) -> Result<u16, VmError> {
         if self.ip + 2 >= module.instructions.len() {
             return Err(VmError {
+                code: codes::INVALID_BYTECODE,
                 message: "Unexpected end of bytecode while reading operand".to_string(),
                 ip: self.ip,
             });
@@ -123,13 +610,96 @@ impl VirtualMachine {
         Ok((high << 8) | low)
     }
 
+    fn read_u32(&mut self, module: &BytecodeModule) -> Result<u32, VmError> {
+        if self.ip + 4 >= module.instructions.len() {
+            return Err(VmError {
+                code: codes::INVALID_BYTECODE,
+                message: "Unexpected end of bytecode while reading operand".to_string(),
+                ip: self.ip,
+            });
+        }
+        let operand = &module.instructions[self.ip + 1..self.ip + 5];
+        self.ip += 5;
+        Ok(u32::from_be_bytes([operand[0], operand[1], operand[2], operand[3]]))
+    }
+
+    fn read_u8(&mut self, module: &BytecodeModule) -> Result<u8, VmError> {
+        if self.ip + 1 >= module.instructions.len() {
+            return Err(VmError {
+                code: codes::INVALID_BYTECODE,
+                message: "Unexpected end of bytecode while reading operand".to_string(),
+                ip: self.ip,
+            });
+        }
+        let operand = module.instructions[self.ip + 1];
+        self.ip += 2;
+        Ok(operand)
+    }
+
+    fn read_jump_target(&mut self, module: &BytecodeModule) -> Result<usize, VmError> {
+        if self.ip + 4 >= module.instructions.len() {
+            return Err(VmError {
+                code: codes::INVALID_BYTECODE,
+                message: "Unexpected end of bytecode while reading jump target".to_string(),
+                ip: self.ip,
+            });
+        }
+        let start = self.ip;
+        let operand = &module.instructions[self.ip + 1..self.ip + 5];
+        let target = u32::from_be_bytes([operand[0], operand[1], operand[2], operand[3]]) as usize;
+        if target > module.instructions.len() {
+            return Err(VmError {
+                code: codes::INVALID_BYTECODE,
+                message: format!("Jump target {} out of bounds", target),
+                ip: start,
+            });
+        }
+        self.ip += 5;
+        Ok(target)
+    }
+
+    /// The constant at `index`, for the instruction at `ip`
+    fn constant<'m>(&self, module: &'m BytecodeModule, index: usize, ip: usize) -> Result<&'m Value, VmError> {
+        module.constants.get(index).ok_or_else(|| VmError {
+            code: codes::INVALID_BYTECODE,
+            message: format!("Constant index {} out of bounds", index),
+            ip,
+        })
+    }
+
+    /// Read the u16 operand of an assertion and resolve it to the
+    /// source location stored in the constant pool
+    fn read_location(&mut self, module: &BytecodeModule) -> Result<String, VmError> {
+        let index = self.read_u16(module)?;
+        match module.constants.get(index as usize) {
+            Some(Value::String(location)) => Ok(location.clone()),
+            _ => Err(VmError {
+                code: codes::INVALID_BYTECODE,
+                message: format!("Invalid source location constant {}", index),
+                ip: self.ip - 3,
+            }),
+        }
+    }
+
     fn pop(&mut self) -> Result<Value, VmError> {
         self.stack.pop().ok_or_else(|| VmError {
+            code: codes::INVALID_BYTECODE,
             message: "Stack underflow".to_string(),
             ip: self.ip,
         })
     }
 
+    fn pop_integer(&mut self, what: &str, ip: usize) -> Result<i64, VmError> {
+        match self.pop()? {
+            Value::Integer(n) => Ok(n),
+            other => Err(VmError {
+                code: codes::TYPE_MISMATCH,
+                message: format!("{} must be an integer, found {}", what, other),
+                ip,
+            }),
+        }
+    }
+
     fn print_value(&mut self, value: &Value, newline: bool) -> Result<(), VmError> {
         let text = value.to_string();
 
@@ -151,8 +721,280 @@ impl VirtualMachine {
     }
 }
 
+/// Apply an arithmetic or ordering operator with the semantics of
+/// `artificial_core::ops`, which constant folding shares
+pub(crate) fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, OpError> {
+    match (left.into_constant(), right.into_constant()) {
+        (Ok(left), Ok(right)) => ops::evaluate_binary(op, &left, &right).map(Value::from),
+        (left, right) => {
+            let name = |operand: &Result<Constant, Value>| match operand {
+                Ok(constant) => ops::type_name(constant),
+                Err(value) => value.type_name(),
+            };
+            Err(ops::binary_type_mismatch(op, name(&left), name(&right)))
+        }
+    }
+}
+
+/// Select the characters of a string, or the items of a list, covered
+/// by `range`. Slicing `null` fails with `NULL_RECEIVER`, anything else
+/// with `INVALID_SLICE`.
+pub(crate) fn slice(target: &Value, range: &Value) -> Result<Value, OpError> {
+    let invalid = |message| OpError {
+        code: codes::INVALID_SLICE,
+        message,
+    };
+    let (start, end, step, inclusive) = match range {
+        Value::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => (*start, *end, *step, *inclusive),
+        other => return Err(invalid(format!("Cannot slice with {}", other))),
+    };
+    let (kind, len) = match target {
+        Value::String(s) => ("string", s.chars().count()),
+        Value::List(items) => ("list", items.len()),
+        Value::Null => {
+            return Err(OpError {
+                code: codes::NULL_RECEIVER,
+                message: "Cannot slice null".to_string(),
+            })
+        }
+        other => return Err(invalid(format!("Cannot slice {}", other))),
+    };
+
+    let end = if inclusive { end.saturating_add(1) } else { end };
+    if start < 0 || end < start || end as usize > len {
+        return Err(invalid(format!(
+            "Slice {} out of bounds for {} of length {}",
+            range, kind, len
+        )));
+    }
+    let (start, end, step) = (start as usize, end as usize, step as usize);
+    Ok(match target {
+        Value::List(items) => Value::List(items[start..end].iter().step_by(step).cloned().collect()),
+        _ => Value::String(target.to_string().chars().skip(start).take(end - start).step_by(step).collect()),
+    })
+}
+
 impl Default for VirtualMachine {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::BytecodeCompiler;
+    use artificial_core::{
+        fold_constants, lower_module, Instruction, InstructionKind, IrFunction, IrModule, Lexer, LoweringOptions,
+        Parser, Span, TerminatorKind,
+    };
+
+    /// What `accrete left op right` does: the printed value, or the code
+    /// and message of the error
+    type Outcome = Result<String, (ErrorCode, String)>;
+
+    /// `accrete left op right` as IR, with nothing folded yet
+    fn program(op: BinaryOp, left: &Constant, right: &Constant) -> IrModule {
+        let mut main = IrFunction::new("main".to_string(), 0, Span::default());
+        main.blocks.push(Default::default());
+        let emit = |main: &mut IrFunction, kind| {
+            let dest = main.new_temp();
+            main.blocks[0]
+                .instructions
+                .push(Instruction::new(Some(dest), kind, Span::default()));
+            dest
+        };
+        let left = emit(&mut main, InstructionKind::Const(left.clone()));
+        let right = emit(&mut main, InstructionKind::Const(right.clone()));
+        let result = emit(&mut main, InstructionKind::Binary { op, left, right });
+        main.blocks[0]
+            .instructions
+            .push(Instruction::new(None, InstructionKind::Print(result), Span::default()));
+        main.blocks[0].terminator.kind = TerminatorKind::Halt;
+        IrModule {
+            functions: Vec::new(),
+            main,
+            span: Span::default(),
+        }
+    }
+
+    /// The outcome constant folding predicts
+    fn folded(op: BinaryOp, left: &Constant, right: &Constant) -> Outcome {
+        let mut module = program(op, left, right);
+        if let Some(error) = fold_constants(&mut module).into_iter().next() {
+            return Err((error.code, error.message));
+        }
+        let printed = module.main.blocks[0].instructions.iter().find_map(|instruction| {
+            match instruction.kind {
+                InstructionKind::Print(value) => Some(value),
+                _ => None,
+            }
+        });
+        let constant = module.main.blocks[0].instructions.iter().find_map(|instruction| {
+            match (&instruction.kind, instruction.dest) {
+                (InstructionKind::Const(constant), Some(dest)) if Some(dest) == printed => Some(constant.clone()),
+                _ => None,
+            }
+        });
+        Ok(Value::from(constant.expect("the result was not folded")).to_string())
+    }
+
+    /// The outcome of `binary`
+    fn evaluated(op: BinaryOp, left: &Constant, right: &Constant) -> Outcome {
+        binary(op, Value::from(left.clone()), Value::from(right.clone()))
+            .map(|value| value.to_string())
+            .map_err(|error| (error.code, error.message))
+    }
+
+    /// The outcome of compiling the unfolded program and running it
+    fn executed(op: BinaryOp, left: &Constant, right: &Constant) -> Outcome {
+        run(&program(op, left, right))
+    }
+
+    /// Run `module` and return what it printed, or its error
+    fn run(module: &IrModule) -> Outcome {
+        let bytecode = BytecodeCompiler::new().compile(module).unwrap();
+        let mut vm = VirtualMachine::with_captured_output();
+        match vm.execute(&bytecode) {
+            Ok(()) => Ok(vm.get_output().trim_end().to_string()),
+            Err(error) => Err((error.code, error.message)),
+        }
+    }
+
+    /// The outcome of `source` with constants folded, and without
+    fn run_source(source: &str) -> (Outcome, Outcome) {
+        let (tokens, lex_errors) = Lexer::new(source).tokenize();
+        assert!(lex_errors.is_empty(), "{:?}", lex_errors);
+        let (ast, parse_errors) = Parser::new(tokens).parse_module();
+        assert!(parse_errors.is_empty(), "{:?}", parse_errors);
+        let module = lower_module(ast, &LoweringOptions::default());
+        let mut folded = module.clone();
+        let folded = match fold_constants(&mut folded).into_iter().next() {
+            Some(error) => Err((error.code, error.message)),
+            None => run(&folded),
+        };
+        (folded, run(&module))
+    }
+
+    fn check(op: BinaryOp, left: Constant, right: Constant, expected: Outcome) {
+        let folded = folded(op, &left, &right);
+        assert_eq!(folded, expected, "folding `{} {} {}`", left, op, right);
+        assert_eq!(evaluated(op, &left, &right), folded, "`binary` on `{} {} {}`", left, op, right);
+        assert_eq!(executed(op, &left, &right), folded, "running `{} {} {}`", left, op, right);
+    }
+
+    fn int(n: i64) -> Constant {
+        Constant::Integer(n)
+    }
+
+    fn string(s: &str) -> Constant {
+        Constant::String(s.to_string())
+    }
+
+    fn printed(text: &str) -> Outcome {
+        Ok(text.to_string())
+    }
+
+    fn failed(code: ErrorCode, message: &str) -> Outcome {
+        Err((code, message.to_string()))
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_overflow() {
+        let overflow = |text: &str| failed(codes::INTEGER_OVERFLOW, &format!("Integer overflow in `{}`", text));
+        check(BinaryOp::Add, int(i64::MAX), int(1), overflow("9223372036854775807 + 1"));
+        check(BinaryOp::Subtract, int(i64::MIN), int(1), overflow("-9223372036854775808 - 1"));
+        check(BinaryOp::Multiply, int(i64::MAX), int(2), overflow("9223372036854775807 * 2"));
+        check(BinaryOp::Divide, int(i64::MIN), int(-1), overflow("-9223372036854775808 / -1"));
+        check(BinaryOp::Remainder, int(i64::MIN), int(-1), overflow("-9223372036854775808 % -1"));
+        check(BinaryOp::Add, int(i64::MAX - 1), int(1), printed("9223372036854775807"));
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_the_smallest_integer_literal() {
+        let overflow = |text: &str| failed(codes::INTEGER_OVERFLOW, &format!("Integer overflow in `{}`", text));
+        for (source, expected) in [
+            ("accrete -9223372036854775808", printed("-9223372036854775808")),
+            ("accrete -9223372036854775808 + 1", printed("-9223372036854775807")),
+            ("accrete -9223372036854775808 - 1", overflow("-9223372036854775808 - 1")),
+            ("accrete -9223372036854775808 / -1", overflow("-9223372036854775808 / -1")),
+            ("accrete -9223372036854775808 % -1", overflow("-9223372036854775808 % -1")),
+            ("let min = -9223372036854775808\naccrete -min", overflow("-(-9223372036854775808)")),
+        ] {
+            let (folded, executed) = run_source(source);
+            assert_eq!(folded, expected, "folding {}", source);
+            assert_eq!(executed, folded, "running {}", source);
+        }
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_division_by_zero() {
+        check(BinaryOp::Divide, int(1), int(0), failed(codes::DIVISION_BY_ZERO, "Division by zero in `1 / 0`"));
+        check(BinaryOp::Remainder, int(1), int(0), failed(codes::DIVISION_BY_ZERO, "Remainder by zero in `1 % 0`"));
+        check(BinaryOp::Divide, int(0), int(0), failed(codes::DIVISION_BY_ZERO, "Division by zero in `0 / 0`"));
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_negative_remainders() {
+        // The sign follows the dividend, as in Rust
+        check(BinaryOp::Remainder, int(-7), int(3), printed("-1"));
+        check(BinaryOp::Remainder, int(7), int(-3), printed("1"));
+        check(BinaryOp::Remainder, int(-7), int(-3), printed("-1"));
+        check(BinaryOp::Divide, int(-7), int(2), printed("-3"));
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_strings() {
+        check(BinaryOp::Less, string("a"), string("b"), printed("true"));
+        check(BinaryOp::Less, string("Z"), string("a"), printed("true"));
+        check(BinaryOp::LessEqual, string("abc"), string("abd"), printed("true"));
+        check(BinaryOp::Greater, string("ab"), string("abc"), printed("false"));
+        check(BinaryOp::GreaterEqual, string(""), string(""), printed("true"));
+        check(BinaryOp::Equal, string("a"), string("a"), printed("true"));
+        check(BinaryOp::NotEqual, string("a"), int(1), printed("true"));
+        check(BinaryOp::Add, string("con"), string("cat"), printed("concat"));
+    }
+
+    #[test]
+    fn folding_matches_the_vm_on_type_mismatches() {
+        for (op, left, right) in [
+            (BinaryOp::Less, string("a"), int(1)),
+            (BinaryOp::Add, Constant::Null, int(1)),
+            (BinaryOp::Subtract, string("a"), string("b")),
+            (BinaryOp::Multiply, Constant::Boolean(true), int(2)),
+        ] {
+            let outcome = evaluated(op, &left, &right);
+            assert!(matches!(&outcome, Err((code, _)) if *code == codes::TYPE_MISMATCH), "{:?}", outcome);
+            check(op, left, right, outcome);
+        }
+    }
+
+    #[test]
+    fn slicing_null_fails_with_null_receiver() {
+        let range = |start, end| Value::Range {
+            start,
+            end,
+            step: 1,
+            inclusive: false,
+        };
+        let error = slice(&Value::Null, &range(0, 1)).unwrap_err();
+        assert_eq!((error.code, error.message.as_str()), (codes::NULL_RECEIVER, "Cannot slice null"));
+        let error = slice(&Value::Integer(1), &range(0, 1)).unwrap_err();
+        assert_eq!(error.code, codes::INVALID_SLICE);
+        let error = slice(&Value::String("ab".to_string()), &range(0, 3)).unwrap_err();
+        assert_eq!(error.code, codes::INVALID_SLICE);
+
+        let diagnostic = VmError {
+            code: codes::NULL_RECEIVER,
+            message: "Cannot slice null".to_string(),
+            ip: 0,
+        }
+        .to_diagnostic(&SourceMap::new());
+        assert!(diagnostic.help.is_some());
+    }
+}